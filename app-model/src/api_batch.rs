@@ -0,0 +1,278 @@
+use crate::{
+	ApiClient,
+	ApiError,
+};
+use futures::{
+	channel::oneshot,
+	future::{
+		FutureExt,
+		Shared,
+	},
+	task::Poll,
+};
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+use std::{
+	convert::Infallible,
+	sync::{
+		Arc,
+		Mutex,
+	},
+};
+
+/// Maximum number of calls of a batch request accepted by the server
+pub const MAX_CALLS: usize = 64;
+
+/// Client coalescing the api calls issued in the same tick into one request
+///
+/// Each call waits for the other calls polled or spawned alongside it before
+/// the whole batch is sent, and receives its own result from the response.
+/// The calls of a batch share the future sending it, so it is sent as long
+/// as any of them is polled.
+/// ```ignore
+/// let batch = api::ApiBatch::new();
+/// // one request to /api/v1/batch
+/// let (project, tasks) = futures::join!(
+///     batch.get_project(id),
+///     batch.get_project_tasks(id),
+/// );
+/// ```
+pub struct BatchClient<C, R> {
+	route: &'static str,
+	schema: &'static str,
+	client: Option<ApiClient>,
+	queue: Arc<Mutex<Option<Batch<C, R>>>>,
+}
+/// Calls waiting for their results
+type Calls<C, R> = Vec<(C, oneshot::Sender<Result<R, ApiError<Infallible>>>)>;
+/// Future sending a batch
+#[cfg(not(target_arch = "wasm32"))]
+type Sending = Shared<futures::future::BoxFuture<'static, ()>>;
+/// Future sending a batch
+#[cfg(target_arch = "wasm32")]
+type Sending = Shared<futures::future::LocalBoxFuture<'static, ()>>;
+/// Calls of the next batch and the future sending them
+struct Batch<C, R> {
+	calls: Calls<C, R>,
+	sending: Sending,
+}
+impl<C, R> Clone for BatchClient<C, R> {
+	fn clone(&self) -> Self {
+		Self {
+			route: self.route,
+			schema: self.schema,
+			client: self.client.clone(),
+			queue: self.queue.clone(),
+		}
+	}
+}
+impl<C, R> BatchClient<C, R>
+	where C: Serialize + Send + Sync + 'static,
+		  R: DeserializeOwned + Send + 'static,
+{
+	/// New client posting batches to route, of the api with schema hash
+	pub fn new(route: &'static str, schema: &'static str) -> Self {
+		Self {
+			route,
+			schema,
+			client: None,
+			queue: Arc::new(Mutex::new(None)),
+		}
+	}
+	/// Send the batches with client instead of the current `ApiClient`
+	pub fn client(mut self, client: ApiClient) -> Self {
+		self.client = Some(client);
+		self
+	}
+	/// Send call with the calls of the same tick and wait for its result
+	pub async fn call<E>(&self, call: C) -> Result<R, ApiError<E>> {
+		let (sender, receiver) = oneshot::channel();
+		let sending = {
+			let mut queue = self.queue.lock().unwrap();
+			let batch = queue.get_or_insert_with(|| Batch {
+				calls: Vec::new(),
+				sending: self.sending(),
+			});
+			batch.calls.push((call, sender));
+			batch.sending.clone()
+		};
+		sending.await;
+		match receiver.await {
+			Ok(result) => result.map_err(ApiError::cast),
+			Err(_) => Err(ApiError::Disconnected),
+		}
+	}
+	/// Future sending the next batch, after the calls of the same tick joined it
+	fn sending(&self) -> Sending {
+		let client = self.clone();
+		let sending = async move {
+			yield_now().await;
+			let batch = client.queue.lock().unwrap().take();
+			if let Some(batch) = batch {
+				client.send(batch.calls).await;
+			}
+		};
+		#[cfg(not(target_arch = "wasm32"))]
+		let sending = sending.boxed();
+		#[cfg(target_arch = "wasm32")]
+		let sending = sending.boxed_local();
+		sending.shared()
+	}
+	/// Send a batch and pass the results to its calls
+	async fn send(&self, batch: Calls<C, R>) {
+		for chunk in chunks(batch) {
+			let (calls, senders): (Vec<C>, Vec<_>) = chunk.into_iter().unzip();
+			let client = self.client.clone().unwrap_or_else(ApiClient::current);
+			match post(&client, self.route, self.schema, &calls).await {
+				Ok(results) if results.len() == senders.len() => {
+					for (sender, result) in senders.into_iter().zip(results) {
+						let _ = sender.send(result.map_err(ApiError::from_status));
+					}
+				}
+				Ok(_) => {
+					for sender in senders {
+						let _ = sender.send(Err(ApiError::Decode("Wrong number of batch results.".to_string())));
+					}
+				}
+				Err(e) => {
+					let e = e.shared();
+					for sender in senders {
+						let _ = sender.send(Err(e.clone_shared()));
+					}
+				}
+			}
+		}
+	}
+}
+/// Batches of at most `MAX_CALLS` calls
+fn chunks<T>(mut batch: Vec<T>) -> Vec<Vec<T>> {
+	let mut chunks = Vec::new();
+	while batch.len() > MAX_CALLS {
+		let rest = batch.split_off(MAX_CALLS);
+		chunks.push(batch);
+		batch = rest;
+	}
+	chunks.push(batch);
+	chunks
+}
+/// Future pending once, letting the futures of the same tick run first
+fn yield_now() -> impl std::future::Future<Output = ()> {
+	let mut yielded = false;
+	futures::future::poll_fn(move |cx| {
+		if yielded {
+			Poll::Ready(())
+		} else {
+			yielded = true;
+			cx.waker().wake_by_ref();
+			Poll::Pending
+		}
+	})
+}
+impl ApiError<Infallible> {
+	/// The error of the calls of a failed batch
+	///
+	/// Transport errors can't be cloned, the calls share them as `BatchTransport`.
+	fn shared(self) -> Self {
+		match self {
+			Self::Transport(e) => Self::BatchTransport(Arc::new(e)),
+			e => e,
+		}
+	}
+	/// Copy of an error returned by `shared`, for each call of the batch
+	fn clone_shared(&self) -> Self {
+		match self {
+			Self::Transport(_) => unreachable!("transport errors of batches are shared"),
+			Self::BatchTransport(e) => Self::BatchTransport(e.clone()),
+			Self::Status(status) => Self::Status(*status),
+			Self::Domain(never) => match *never {},
+			Self::Disconnected => Self::Disconnected,
+			Self::Decode(e) => Self::Decode(e.clone()),
+			Self::Outdated => Self::Outdated,
+		}
+	}
+}
+#[cfg(not(target_arch = "wasm32"))]
+async fn post<C, R>(client: &ApiClient, route: &str, schema: &str, calls: &Vec<C>) -> Result<Vec<Result<R, u16>>, ApiError<Infallible>>
+	where C: Serialize,
+		  R: DeserializeOwned,
+{
+	let response = client
+		.call(route, calls)
+		.header(crate::api_schema::HEADER, schema)
+		.send()
+		.await
+		.map_err(ApiError::Transport)?;
+	if !response.status().is_success() {
+		return Err(ApiError::from_status(response.status().as_u16()));
+	}
+	crate::api_client::decode(response).await
+}
+#[cfg(target_arch = "wasm32")]
+async fn post<C, R>(client: &ApiClient, route: &str, schema: &str, calls: &Vec<C>) -> Result<Vec<Result<R, u16>>, ApiError<Infallible>>
+	where C: Serialize,
+		  R: DeserializeOwned,
+{
+	use seed::browser::fetch::Header;
+	let req = client
+		.call(route, calls)
+		.header(Header::custom(crate::api_schema::HEADER, schema.to_string()));
+	let response = seed::fetch::fetch(req)
+		.await
+		.map_err(ApiError::Transport)?;
+	if !response.status().is_ok() {
+		return Err(ApiError::from_status(response.status().code));
+	}
+	crate::api_client::decode(response).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{
+		Read,
+		Write,
+	};
+	#[test]
+	fn chunked() {
+		let sizes: Vec<usize> = chunks((0..MAX_CALLS * 2 + 1).collect()).iter().map(Vec::len).collect();
+		assert_eq!(sizes, vec![MAX_CALLS, MAX_CALLS, 1]);
+	}
+	/// Origin of a server answering one request with a JSON body, once respond is sent to
+	fn respond_once(body: &'static str) -> (String, std::sync::mpsc::Sender<()>) {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let origin = format!("http://{}", listener.local_addr().unwrap());
+		let (respond, responding) = std::sync::mpsc::channel();
+		std::thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut request = String::new();
+			let mut buf = [0; 1024];
+			// read the headers and the body of the request
+			while !request.contains("\r\n\r\n") || request.split("\r\n\r\n").nth(1) == Some("") {
+				let read = stream.read(&mut buf).unwrap();
+				request.push_str(&String::from_utf8_lossy(&buf[..read]));
+			}
+			responding.recv().unwrap();
+			write!(stream, "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", body.len(), body).unwrap();
+		});
+		(origin, respond)
+	}
+	#[test]
+	fn batches_outlive_dropped_calls() {
+		let (origin, respond) = respond_once(r#"[{"Ok":1},{"Ok":2}]"#);
+		let client = BatchClient::<String, u32>::new("/batch", "schema")
+			.client(ApiClient::new().base_url(origin));
+		crate::api_runtime::block_on(async {
+			let mut first = Box::pin(client.call::<()>("first".to_string()));
+			let mut second = Box::pin(client.call::<()>("second".to_string()));
+			assert!(futures::poll!(first.as_mut()).is_pending());
+			assert!(futures::poll!(second.as_mut()).is_pending());
+			// the batch is being sent when the first call is dropped
+			assert!(futures::poll!(first.as_mut()).is_pending());
+			drop(first);
+			respond.send(()).unwrap();
+			assert_eq!(second.await.unwrap(), 2);
+		});
+	}
+}
@@ -0,0 +1,302 @@
+use crate::{
+	auth::UserSession,
+	encoding::Encoding,
+};
+use lazy_static::lazy_static;
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+use std::sync::RwLock;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::user::User;
+
+lazy_static! {
+	static ref CLIENT: RwLock<ApiClient> = RwLock::new(ApiClient::new());
+}
+/// Address of the server api, used by the generated api client functions
+///
+/// The base URL defaults to the origin of the page on wasm. A path prefix is
+/// put in front of every api route, e.g. when the server runs behind a
+/// reverse proxy.
+/// ```ignore
+/// ApiClient::new()
+///     .base_url("https://example.com:8443")
+///     .prefix("/budget")
+///     .set();
+/// // posts to https://example.com:8443/budget/api/v1/call/get_user_projects
+/// api::get_user_projects(id).await
+/// ```
+/// Natively the api functions send the token of the client's session:
+/// ```ignore
+/// let client = ApiClient::new().base_url("http://localhost:8000");
+/// client.clone().set();
+/// let session = api::login(Credentials::new("username", "password")).await?;
+/// client.session(&session).set();
+/// api::get_user_projects().await?
+/// ```
+/// Parameters and results are sent as JSON, unless another encoding is set:
+/// ```ignore
+/// ApiClient::new().encoding(Encoding::MessagePack).set();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ApiClient {
+	base_url: Option<String>,
+	prefix: String,
+	token: Option<String>,
+	encoding: Encoding,
+}
+impl ApiClient {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	/// Scheme, host and port of the server
+	pub fn base_url<S: ToString>(mut self, base_url: S) -> Self {
+		self.base_url = Some(base_url.to_string().trim_end_matches('/').to_string());
+		self
+	}
+	/// Path in front of every api route
+	pub fn prefix<S: ToString>(mut self, prefix: S) -> Self {
+		let prefix = prefix.to_string();
+		let prefix = prefix.trim_matches('/');
+		self.prefix = if prefix.is_empty() {
+			String::new()
+		} else {
+			format!("/{}", prefix)
+		};
+		self
+	}
+	/// Encoding of the parameters and results of the calls
+	pub fn encoding(mut self, encoding: Encoding) -> Self {
+		self.encoding = encoding;
+		self
+	}
+	/// Authenticate calls with the token of session
+	pub fn session(mut self, session: &UserSession) -> Self {
+		self.token = Some(session.token.clone());
+		self
+	}
+	/// Make this the client of the api functions
+	pub fn set(self) {
+		*CLIENT.write().unwrap() = self;
+	}
+	/// The client of the api functions
+	pub fn current() -> Self {
+		CLIENT.read().unwrap().clone()
+	}
+	/// The configured base URL, or the origin of the page
+	pub fn origin(&self) -> String {
+		match &self.base_url {
+			Some(base_url) => base_url.clone(),
+			None => default_origin(),
+		}
+	}
+	/// URL of an absolute api route, e.g. `/api/v1/call/get_user_projects`
+	pub fn url(&self, route: &str) -> String {
+		format!("{}{}{}", self.origin(), self.prefix, route)
+	}
+	/// Value of the Authorization header of api calls
+	pub fn authorization(&self) -> Option<&String> {
+		self.token.as_ref()
+	}
+	/// The encoding of the parameters and results of the calls
+	pub fn payload_encoding(&self) -> Encoding {
+		self.encoding
+	}
+	/// Parameters of a call in the encoding of the client
+	fn encode<P: Serialize>(&self, parameters: &P) -> Vec<u8> {
+		self.encoding.encode(parameters).expect("Failed to serialize api parameters.")
+	}
+	/// Token of the calls, of the client or the session
+	fn session_token(&self) -> Option<String> {
+		let token = self.authorization().cloned();
+		#[cfg(target_arch = "wasm32")]
+		let token = token.or_else(|| crate::auth::session::get().map(|session| session.token));
+		token
+	}
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl ApiClient {
+	/// Native POST request to an api route, with the token of the session
+	pub fn post(&self, route: &str) -> reqwest::RequestBuilder {
+		let req = reqwest::Client::new().post(self.url(route));
+		match self.authorization() {
+			Some(token) => req.header(reqwest::header::AUTHORIZATION, token.as_str()),
+			None => req,
+		}
+	}
+	/// Native POST request of parameters to an api route, in the encoding of the client
+	pub fn post_parameters<P: Serialize>(&self, route: &str, parameters: &P) -> reqwest::RequestBuilder {
+		self.post(route)
+			.header(reqwest::header::CONTENT_TYPE, self.encoding.content_type())
+			.body(self.encode(parameters))
+	}
+	/// Native request of a call, accepting the result in the encoding of the client
+	pub fn call<P: Serialize>(&self, route: &str, parameters: &P) -> reqwest::RequestBuilder {
+		self.post_parameters(route, parameters)
+			.header(reqwest::header::ACCEPT, self.encoding.content_type())
+	}
+	/// Register user and log in
+	pub async fn register(&self, user: &User) -> Result<UserSession, reqwest::Error> {
+		self.post("/api/auth/register")
+			.json(user)
+			.send()
+			.await?
+			.error_for_status()?
+			.json()
+			.await
+	}
+}
+#[cfg(target_arch = "wasm32")]
+impl ApiClient {
+	/// Request of parameters to an api route, with the token of the session
+	/// and in the encoding of the client
+	pub fn post_parameters<P: Serialize>(&self, route: &str, parameters: &P) -> seed::browser::fetch::Request<'static> {
+		use seed::browser::fetch::{
+			Header,
+			Method,
+			Request,
+		};
+		let body = js_sys::Uint8Array::from(&self.encode(parameters)[..]);
+		let req = Request::new(self.url(route))
+			.method(Method::Post)
+			.header(Header::custom("Content-Type", self.encoding.content_type()))
+			.body(body.into());
+		match self.session_token() {
+			Some(token) => req.header(Header::authorization(token)),
+			None => req,
+		}
+	}
+	/// Request of a call, accepting the result in the encoding of the client
+	pub fn call<P: Serialize>(&self, route: &str, parameters: &P) -> seed::browser::fetch::Request<'static> {
+		self.post_parameters(route, parameters)
+			.header(seed::browser::fetch::Header::custom("Accept", self.encoding.content_type()))
+	}
+}
+/// Token of the api calls, of the `ApiClient` or the session
+///
+/// The generated api client functions authenticate with it.
+pub fn token() -> Option<String> {
+	ApiClient::current().session_token()
+}
+/// Body of a response, decoded in the encoding of its Content-Type
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn decode<T: DeserializeOwned, E>(response: reqwest::Response) -> Result<T, ApiError<E>> {
+	let encoding = response.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.and_then(Encoding::from_header)
+		.unwrap_or_default();
+	let body = response.bytes().await.map_err(ApiError::Transport)?;
+	encoding.decode(&body).map_err(ApiError::Decode)
+}
+/// Body of a response, decoded in the encoding of its Content-Type
+#[cfg(target_arch = "wasm32")]
+pub async fn decode<T: DeserializeOwned, E>(response: seed::browser::fetch::Response) -> Result<T, ApiError<E>> {
+	let encoding = response.raw_response()
+		.headers()
+		.get("Content-Type")
+		.ok()
+		.flatten()
+		.and_then(|value| Encoding::from_header(&value))
+		.unwrap_or_default();
+	let body = response.bytes().await.map_err(ApiError::Transport)?;
+	encoding.decode(&body).map_err(ApiError::Decode)
+}
+/// Error of the transport of api calls
+#[cfg(target_arch = "wasm32")]
+pub type TransportError = seed::browser::fetch::FetchError;
+/// Error of the transport of api calls
+#[cfg(not(target_arch = "wasm32"))]
+pub type TransportError = reqwest::Error;
+
+/// Error of a call of an api function returning a `Result<T, E>`
+#[derive(Debug)]
+pub enum ApiError<E> {
+	/// The request failed or the response could not be decoded
+	Transport(TransportError),
+	/// The batch request of the call failed, the error is shared by the calls of the batch
+	BatchTransport(std::sync::Arc<TransportError>),
+	/// The server responded with an error status and without a result,
+	/// e.g. `401` when the call needs a token
+	Status(u16),
+	/// The api function returned an error
+	Domain(E),
+	/// The websocket or the batch request of the call failed before its response
+	Disconnected,
+	/// The response or an item of a streaming response could not be decoded
+	Decode(String),
+	/// The client was built with a different api than the server runs,
+	/// the page needs to be reloaded
+	Outdated,
+}
+impl<E> ApiError<E> {
+	/// Result of a call from the response status and the decoded response body
+	pub fn result<T>(status: u16, body: Result<Result<T, E>, Self>) -> Result<T, Self> {
+		match body {
+			Ok(result) => result.map_err(Self::Domain),
+			Err(_) if !(200..300).contains(&status) => Err(Self::from_status(status)),
+			Err(e) => Err(e),
+		}
+	}
+	/// Error of a response with status and without a result
+	pub fn from_status(status: u16) -> Self {
+		if status == crate::api_schema::OUTDATED {
+			Self::Outdated
+		} else {
+			Self::Status(status)
+		}
+	}
+}
+impl ApiError<std::convert::Infallible> {
+	/// The error as the error of a function returning a `Result<T, E>`
+	pub fn cast<E>(self) -> ApiError<E> {
+		match self {
+			Self::Transport(e) => ApiError::Transport(e),
+			Self::BatchTransport(e) => ApiError::BatchTransport(e),
+			Self::Status(status) => ApiError::Status(status),
+			Self::Domain(never) => match never {},
+			Self::Disconnected => ApiError::Disconnected,
+			Self::Decode(e) => ApiError::Decode(e),
+			Self::Outdated => ApiError::Outdated,
+		}
+	}
+}
+#[cfg(target_arch = "wasm32")]
+fn default_origin() -> String {
+	use seed::prelude::*;
+	web_sys::window()
+		.and_then(|window| window.location().origin().ok())
+		.unwrap_or_default()
+}
+#[cfg(not(target_arch = "wasm32"))]
+fn default_origin() -> String {
+	"http://localhost:8000".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn url() {
+		let client = ApiClient::new()
+			.base_url("https://example.com:8443/")
+			.prefix("budget/");
+		assert_eq!(client.url("/api/v1/call/f"), "https://example.com:8443/budget/api/v1/call/f");
+		assert_eq!(ApiClient::new().prefix("/").url("/api"), "http://localhost:8000/api");
+	}
+	#[cfg(not(target_arch = "wasm32"))]
+	#[test]
+	fn call_encoding() {
+		let parameters = vec![1u32, 2];
+		let req = ApiClient::new()
+			.encoding(Encoding::MessagePack)
+			.call("/api/v1/call/f", &parameters)
+			.build()
+			.unwrap();
+		assert_eq!(req.headers()[reqwest::header::CONTENT_TYPE], "application/msgpack");
+		assert_eq!(req.headers()[reqwest::header::ACCEPT], "application/msgpack");
+		let body = req.body().and_then(|body| body.as_bytes()).unwrap();
+		assert_eq!(Encoding::MessagePack.decode::<Vec<u32>>(body), Ok(parameters));
+	}
+}
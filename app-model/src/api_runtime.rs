@@ -0,0 +1,29 @@
+use lazy_static::lazy_static;
+use std::future::Future;
+use tokio::runtime::Runtime;
+
+lazy_static! {
+	static ref RUNTIME: Runtime = tokio::runtime::Builder::new_multi_thread()
+		.thread_name("api-runtime")
+		.enable_all()
+		.build()
+		.expect("Failed to start the api runtime");
+}
+/// Run future to completion on the runtime of the api functions
+///
+/// Synchronous handlers, like the Rocket ones, call async api functions
+/// through this, so the functions can use tokio timers and IO. Must not be
+/// called from inside an async context.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+	RUNTIME.block_on(future)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn runs_tokio_futures() {
+		// spawning needs a tokio runtime, unlike futures::executor::block_on
+		assert_eq!(block_on(async { tokio::spawn(async { 1 }).await.unwrap() }), 1);
+	}
+}
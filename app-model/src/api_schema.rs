@@ -0,0 +1,325 @@
+use serde_json::{
+	Map,
+	Value,
+};
+use std::collections::HashSet;
+#[cfg(not(target_arch = "wasm32"))]
+use schemars::schema::RootSchema;
+
+/// Header carrying the `api_schema_hash()` of the api a client was built with
+pub const HEADER: &str = "X-Api-Schema";
+/// Status of the responses to calls of clients built with a different api
+pub const OUTDATED: u16 = 412;
+/// Message of the responses to calls of clients built with a different api
+pub const OUTDATED_MESSAGE: &str = "The api has changed, please reload the page.";
+
+/// Whether a call with the schema header value comes from a client built
+/// with a different api than the one with hash
+///
+/// Calls without the header, e.g. from scripts, are not checked.
+pub fn is_outdated(header: Option<&str>, hash: &str) -> bool {
+	header.map(|header| header != hash).unwrap_or(false)
+}
+/// Hash of the version of an api and the schemas of its functions, sent in
+/// the header by its clients
+///
+/// The functions are given by their name and the schemas of their parameters
+/// and result, so a changed field of a type also changes the hash. The hash
+/// is FNV-1a, stable across compilers and targets.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn hash(version: u32, mut functions: Vec<(&str, RootSchema, RootSchema)>) -> String {
+	functions.sort_by(|a, b| a.0.cmp(b.0));
+	let schema = serde_json::to_string(&(version, functions)).expect("Failed to serialize api schemas.");
+	let hash = schema.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+		(hash ^ byte as u64).wrapping_mul(0x100000001b3)
+	});
+	format!("{:016x}", hash)
+}
+/// Changes between two OpenAPI documents of an api which break old clients
+///
+/// Compares the documents returned by the generated `openapi()` function,
+/// e.g. a dump of the deployed api and the current one:
+/// ```ignore
+/// let deployed = serde_json::from_str(include_str!("../schema/v1.json")).unwrap();
+/// assert_eq!(api_schema::breaking_changes(&deployed, &api::openapi()), Vec::<String>::new());
+/// ```
+/// Old clients break when a function is removed, when it requires
+/// parameters they don't send or stops returning values they expect, and
+/// when values change their type.
+pub fn breaking_changes(old: &Value, new: &Value) -> Vec<String> {
+	let mut diff = Diff {
+		old,
+		new,
+		visited: HashSet::new(),
+		changes: Vec::new(),
+	};
+	let empty = Map::new();
+	let new_paths = new["paths"].as_object().unwrap_or(&empty);
+	for (route, old_path) in old["paths"].as_object().unwrap_or(&empty) {
+		let new_path = match new_paths.get(route) {
+			Some(path) => path,
+			None => {
+				diff.changes.push(format!("{} was removed", route));
+				continue;
+			}
+		};
+		diff.compare(
+			&format!("{} parameters", route),
+			request_schema(old_path),
+			request_schema(new_path),
+			Flow::Request,
+		);
+		diff.compare(
+			&format!("{} result", route),
+			response_schema(old_path),
+			response_schema(new_path),
+			Flow::Response,
+		);
+	}
+	diff.changes
+}
+fn request_schema(path: &Value) -> &Value {
+	&path["post"]["requestBody"]["content"]["application/json"]["schema"]
+}
+fn response_schema(path: &Value) -> &Value {
+	path["post"]["responses"]["200"]["content"]
+		.as_object()
+		.and_then(|content| content.values().next())
+		.map(|content| &content["schema"])
+		.unwrap_or(&Value::Null)
+}
+/// Direction values of a schema are sent in
+#[derive(Clone, Copy)]
+enum Flow {
+	/// From old clients to the new server, the new schema must accept the old values
+	Request,
+	/// From the new server to old clients, the old schema must accept the new values
+	Response,
+}
+struct Diff<'a> {
+	old: &'a Value,
+	new: &'a Value,
+	/// Pairs of compared definitions, for recursive schemas
+	visited: HashSet<(String, String)>,
+	changes: Vec<String>,
+}
+impl<'a> Diff<'a> {
+	fn compare(&mut self, at: &str, old: &'a Value, new: &'a Value, flow: Flow) {
+		if let (Some(old_ref), Some(new_ref)) = (reference(old), reference(new)) {
+			if !self.visited.insert((old_ref.to_string(), new_ref.to_string())) {
+				return;
+			}
+		}
+		let old = resolve(self.old, old);
+		let new = resolve(self.new, new);
+		if old.get("type") != new.get("type") || old.get("format") != new.get("format") {
+			self.changes.push(format!("{} changed type from {} to {}", at, type_name(old), type_name(new)));
+			return;
+		}
+		let nullable = |schema: &Value| schema["nullable"].as_bool().unwrap_or(false);
+		// the schema accepting the values must accept all values of the sent schema
+		let ((accepting, accepting_document), (sent, sent_document)) = match flow {
+			Flow::Request => ((new, self.new), (old, self.old)),
+			Flow::Response => ((old, self.old), (new, self.new)),
+		};
+		if nullable(sent) && !nullable(accepting) {
+			self.changes.push(format!("{} is no longer optional", at));
+		}
+		let required = |schema: &'a Value| -> HashSet<&'a str> {
+			schema["required"]
+				.as_array()
+				.map(|names| names.iter().filter_map(Value::as_str).collect())
+				.unwrap_or_default()
+		};
+		let mut missing: Vec<&str> = required(accepting).difference(&required(sent)).cloned().collect();
+		missing.sort_unstable();
+		for name in missing {
+			self.changes.push(match flow {
+				Flow::Request => format!("{}.{} is required", at, name),
+				Flow::Response => format!("{}.{} is no longer returned", at, name),
+			});
+		}
+		if let (Some(old_properties), Some(new_properties)) = (old["properties"].as_object(), new["properties"].as_object()) {
+			for (name, old_property) in old_properties {
+				if let Some(new_property) = new_properties.get(name) {
+					self.compare(&format!("{}.{}", at, name), old_property, new_property, flow);
+				}
+			}
+		}
+		if let (Some(old_items), Some(new_items)) = (old.get("items"), new.get("items")) {
+			self.compare(&format!("{}[]", at), old_items, new_items, flow);
+		}
+		let mut unknown: Vec<String> = variants(sent_document, sent)
+			.difference(&variants(accepting_document, accepting))
+			.cloned()
+			.collect();
+		unknown.sort_unstable();
+		for variant in unknown {
+			self.changes.push(match flow {
+				Flow::Request => format!("{} no longer accepts {}", at, variant),
+				Flow::Response => format!("{} may return the new {}", at, variant),
+			});
+		}
+	}
+}
+/// Definition referenced by schema, if it is a reference
+fn reference(schema: &Value) -> Option<&str> {
+	schema["$ref"].as_str().or_else(|| match schema["allOf"].as_array() {
+		Some(all) if all.len() == 1 => reference(&all[0]),
+		_ => None,
+	})
+}
+/// Schema with its references replaced by their definitions in document
+fn resolve<'a>(document: &'a Value, schema: &'a Value) -> &'a Value {
+	match reference(schema) {
+		Some(path) => {
+			let pointer = path.trim_start_matches('#');
+			document.pointer(pointer).map(|schema| resolve(document, schema)).unwrap_or(&Value::Null)
+		}
+		None => schema,
+	}
+}
+fn type_name(schema: &Value) -> String {
+	match (schema["type"].as_str(), schema["format"].as_str()) {
+		(Some(ty), Some(format)) => format!("{} ({})", ty, format),
+		(Some(ty), None) => ty.to_string(),
+		_ => "untyped".to_string(),
+	}
+}
+/// Names of the values of an enum schema, its unit variants and the
+/// properties of its data variants
+fn variants(document: &Value, schema: &Value) -> HashSet<String> {
+	let values = |schema: &Value| -> Vec<String> {
+		schema["enum"]
+			.as_array()
+			.map(|values| values.iter().map(|value| format!("variant {}", value)).collect())
+			.unwrap_or_default()
+	};
+	let mut variants: HashSet<String> = values(schema).into_iter().collect();
+	for variant in schema["oneOf"].as_array().into_iter().chain(schema["anyOf"].as_array()).flatten() {
+		let variant = resolve(document, variant);
+		variants.extend(values(variant));
+		if let Some(properties) = variant["properties"].as_object() {
+			let names: Vec<&str> = properties.keys().map(String::as_str).collect();
+			variants.insert(format!("variant {}", names.join(", ")));
+		}
+	}
+	variants
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+	fn document(parameters: Value, result: Value, definitions: Value) -> Value {
+		json!({
+			"paths": {
+				"/api/v1/call/f": {
+					"post": {
+						"requestBody": { "content": { "application/json": { "schema": parameters } } },
+						"responses": { "200": { "content": { "application/json": { "schema": result } } } },
+					},
+				},
+			},
+			"components": { "schemas": definitions },
+		})
+	}
+	#[test]
+	fn outdated() {
+		assert!(!is_outdated(None, "a"));
+		assert!(!is_outdated(Some("a"), "a"));
+		assert!(is_outdated(Some("b"), "a"));
+	}
+	mod old {
+		#[derive(schemars::JsonSchema)]
+		pub struct Row {
+			pub name: String,
+		}
+	}
+	mod new {
+		#[derive(schemars::JsonSchema)]
+		pub struct Row {
+			pub name: String,
+			pub size: u32,
+		}
+	}
+	#[test]
+	fn hash_covers_types() {
+		let functions = || vec![
+			("f", schemars::schema_for!(old::Row), schemars::schema_for!(u32)),
+			("g", schemars::schema_for!(u32), schemars::schema_for!(old::Row)),
+		];
+		let old = hash(1, functions());
+		assert_eq!(old, hash(1, functions().into_iter().rev().collect()));
+		assert_ne!(old, hash(2, functions()));
+		// a field added to a type of an existing function
+		assert_ne!(old, hash(1, vec![
+			("f", schemars::schema_for!(new::Row), schemars::schema_for!(u32)),
+			("g", schemars::schema_for!(u32), schemars::schema_for!(old::Row)),
+		]));
+	}
+	#[test]
+	fn compatible_changes() {
+		let old = document(
+			json!({ "type": "object", "required": ["id"], "properties": { "id": { "type": "string" } } }),
+			json!({ "$ref": "#/components/schemas/Task" }),
+			json!({ "Task": { "type": "object", "required": ["name"], "properties": { "name": { "type": "string" } } } }),
+		);
+		// optional parameters and new results don't break old clients
+		let new = document(
+			json!({ "type": "object", "required": ["id"], "properties": {
+				"id": { "type": "string" },
+				"limit": { "type": "integer", "nullable": true },
+			} }),
+			json!({ "$ref": "#/components/schemas/Task" }),
+			json!({ "Task": { "type": "object", "required": ["name", "done"], "properties": {
+				"name": { "type": "string" },
+				"done": { "type": "boolean" },
+			} } }),
+		);
+		assert_eq!(breaking_changes(&old, &new), Vec::<String>::new());
+		assert_eq!(breaking_changes(&old, &old), Vec::<String>::new());
+	}
+	#[test]
+	fn breaking() {
+		let old = document(
+			json!({ "type": "object", "required": ["id"], "properties": { "id": { "type": "string" } } }),
+			json!({ "type": "array", "items": { "$ref": "#/components/schemas/Task" } }),
+			json!({
+				"Task": { "type": "object", "required": ["name", "state"], "properties": {
+					"name": { "type": "string" },
+					"state": { "$ref": "#/components/schemas/State" },
+				} },
+				"State": { "type": "string", "enum": ["Open", "Done"] },
+			}),
+		);
+		// renamed parameter, removed and retyped result fields, new enum variant
+		let new = document(
+			json!({ "type": "object", "required": ["task_id"], "properties": { "task_id": { "type": "string" } } }),
+			json!({ "type": "array", "items": { "$ref": "#/components/schemas/Task" } }),
+			json!({
+				"Task": { "type": "object", "required": ["state"], "properties": {
+					"state": { "$ref": "#/components/schemas/State" },
+				} },
+				"State": { "type": "string", "enum": ["Open", "Done", "Blocked"] },
+			}),
+		);
+		assert_eq!(
+			breaking_changes(&old, &new),
+			vec![
+				"/api/v1/call/f parameters.task_id is required",
+				"/api/v1/call/f result[].name is no longer returned",
+				"/api/v1/call/f result[].state may return the new variant \"Blocked\"",
+			]
+		);
+		let mut removed = new.clone();
+		removed["paths"] = json!({});
+		assert_eq!(breaking_changes(&old, &removed), vec!["/api/v1/call/f was removed"]);
+		let mut retyped = old.clone();
+		retyped["components"]["schemas"]["State"] = json!({ "type": "integer", "format": "uint32" });
+		assert_eq!(
+			breaking_changes(&old, &retyped),
+			vec!["/api/v1/call/f result[].state changed type from string to integer (uint32)"]
+		);
+	}
+}
@@ -0,0 +1,189 @@
+use crate::ApiError;
+use futures::channel::{
+	mpsc,
+	oneshot,
+};
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use std::{
+	collections::HashMap,
+	sync::{
+		atomic::{
+			AtomicU64,
+			Ordering,
+		},
+		Arc,
+		Mutex,
+	},
+};
+
+/// Call of an api function sent over a websocket
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SocketRequest<C> {
+	/// Id of the request, repeated by its response
+	pub id: u64,
+	/// Token authenticating the call
+	pub token: Option<String>,
+	/// Schema hash of the api of the client, see `api_schema::HEADER`
+	#[serde(default)]
+	pub schema: Option<String>,
+	pub call: C,
+}
+/// Message sent by the server over an api websocket
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SocketMessage<R, P> {
+	/// Result of the request with id, or the error status of the call
+	Response {
+		id: u64,
+		result: Result<R, u16>,
+	},
+	/// Message pushed by the server
+	Push(P),
+}
+/// Senders completing the calls waiting for a response, by request id
+type Pending<R> = Mutex<HashMap<u64, oneshot::Sender<Result<R, u16>>>>;
+/// Client multiplexing api calls over a websocket
+///
+/// Responses are matched to their calls by the id of the request. The app
+/// owns the websocket, it sends the requests of the receiver returned by `new`
+/// and passes the messages it receives to `receive`.
+/// ```ignore
+/// let (socket, requests) = api::ApiSocket::new();
+/// spawn(requests.for_each(|request| send(serde_json::to_string(&request))));
+/// // for each message received
+/// if let Some(push) = socket.receive(serde_json::from_str(&text)?) { .. }
+/// // elsewhere
+/// let tasks = socket.get_project_tasks(id).await?;
+/// ```
+pub struct SocketClient<C, R> {
+	next_id: Arc<AtomicU64>,
+	pending: Arc<Pending<R>>,
+	requests: mpsc::UnboundedSender<SocketRequest<C>>,
+	schema: &'static str,
+}
+impl<C, R> Clone for SocketClient<C, R> {
+	fn clone(&self) -> Self {
+		Self {
+			next_id: self.next_id.clone(),
+			pending: self.pending.clone(),
+			requests: self.requests.clone(),
+			schema: self.schema,
+		}
+	}
+}
+impl<C, R> SocketClient<C, R> {
+	/// New client of the api with schema hash and the requests to send over the websocket
+	pub fn new(schema: &'static str) -> (Self, mpsc::UnboundedReceiver<SocketRequest<C>>) {
+		let (requests, receiver) = mpsc::unbounded();
+		let client = Self {
+			next_id: Arc::new(AtomicU64::new(0)),
+			pending: Arc::new(Mutex::new(HashMap::new())),
+			requests,
+			schema,
+		};
+		(client, receiver)
+	}
+	/// Complete the call of a response, returns pushed messages
+	pub fn receive<P>(&self, message: SocketMessage<R, P>) -> Option<P> {
+		match message {
+			SocketMessage::Response { id, result } => {
+				if let Some(sender) = self.pending.lock().unwrap().remove(&id) {
+					let _ = sender.send(result);
+				}
+				None
+			}
+			SocketMessage::Push(push) => Some(push),
+		}
+	}
+	/// Fail the pending calls, after the websocket closed
+	pub fn disconnect(&self) {
+		self.pending.lock().unwrap().clear();
+	}
+	/// Send call and wait for its response
+	pub async fn call<E>(&self, call: C) -> Result<R, ApiError<E>> {
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let (sender, receiver) = oneshot::channel();
+		self.pending.lock().unwrap().insert(id, sender);
+		// forget the call when it completes or is dropped before its response
+		let _pending = PendingCall {
+			pending: &self.pending,
+			id,
+		};
+		let request = SocketRequest {
+			id,
+			token: crate::api_client::token(),
+			schema: Some(self.schema.to_string()),
+			call,
+		};
+		if self.requests.unbounded_send(request).is_err() {
+			return Err(ApiError::Disconnected);
+		}
+		match receiver.await {
+			Ok(result) => result.map_err(ApiError::from_status),
+			Err(_) => Err(ApiError::Disconnected),
+		}
+	}
+}
+/// Removes a call from the pending calls when dropped
+struct PendingCall<'a, R> {
+	pending: &'a Pending<R>,
+	id: u64,
+}
+impl<R> Drop for PendingCall<'_, R> {
+	fn drop(&mut self) {
+		if let Ok(mut pending) = self.pending.lock() {
+			pending.remove(&self.id);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{
+		executor::block_on,
+		StreamExt,
+	};
+	#[test]
+	fn responses_complete_their_calls() {
+		let (client, mut requests) = SocketClient::<String, String>::new("schema");
+		let first = client.call::<()>("first".to_string());
+		let second = client.call::<()>("second".to_string());
+		block_on(async {
+			let (first, second, _) = futures::join!(first, second, async {
+				let a = requests.next().await.unwrap();
+				let b = requests.next().await.unwrap();
+				// respond out of order
+				let push = client.receive(SocketMessage::<_, ()>::Response { id: b.id, result: Err(403) });
+				assert_eq!(push, None);
+				client.receive(SocketMessage::<_, ()>::Response { id: a.id, result: Ok(a.call) });
+				assert_eq!(client.receive(SocketMessage::<String, _>::Push(1)), Some(1));
+			});
+			assert_eq!(first.unwrap(), "first");
+			match second {
+				Err(ApiError::Status(status)) => assert_eq!(status, 403),
+				_ => panic!("expected a status"),
+			}
+		});
+	}
+	#[test]
+	fn dropped_calls_are_forgotten() {
+		let (client, mut requests) = SocketClient::<String, String>::new("schema");
+		block_on(async {
+			let call = Box::pin(client.call::<()>("dropped".to_string()));
+			// send the request, then cancel the call before its response
+			let call = match futures::future::select(call, requests.next()).await {
+				futures::future::Either::Right((request, call)) => {
+					assert!(request.is_some());
+					call
+				}
+				_ => panic!("expected the request to be sent"),
+			};
+			assert_eq!(client.pending.lock().unwrap().len(), 1);
+			drop(call);
+		});
+		assert!(client.pending.lock().unwrap().is_empty());
+	}
+}
@@ -0,0 +1,186 @@
+use crate::{
+	api_client::TransportError,
+	ApiError,
+};
+use futures::{
+	Stream,
+	StreamExt,
+};
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+use std::convert::Infallible;
+
+/// Content type of streaming responses, one JSON value per line
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// JSON line of an item of a streaming response
+pub fn line<T: Serialize>(item: &T) -> Vec<u8> {
+	let mut line = serde_json::to_vec(item).expect("Failed to serialize streamed item.");
+	line.push(b'\n');
+	line
+}
+/// Items decoded from the JSON lines in chunks
+pub fn decode_lines<T, C>(chunks: C) -> impl Stream<Item = Result<T, ApiError<Infallible>>>
+	where T: DeserializeOwned,
+		  C: Stream<Item = Result<Vec<u8>, TransportError>>,
+{
+	let chunks = Box::pin(chunks);
+	futures::stream::unfold((chunks, Vec::new(), false), |(mut chunks, mut buffer, mut done)| async move {
+		loop {
+			if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+				let line: Vec<u8> = buffer.drain(..=end).collect();
+				let item = serde_json::from_slice(&line[..end])
+					.map_err(|e| ApiError::Decode(e.to_string()));
+				return Some((item, (chunks, buffer, done)));
+			}
+			if done {
+				return None;
+			}
+			match chunks.next().await {
+				Some(Ok(chunk)) => buffer.extend(chunk),
+				Some(Err(e)) => return Some((Err(ApiError::Transport(e)), (chunks, Vec::new(), true))),
+				None => {
+					// last line without line break
+					done = true;
+					if !buffer.is_empty() {
+						buffer.push(b'\n');
+					}
+				}
+			}
+		}
+	})
+}
+/// Call a streaming api function at route with parameters, from a client
+/// of the api with schema hash
+///
+/// The parameters are sent in the encoding of the `ApiClient`, the items
+/// always arrive as JSON lines. Fails with the status of the response if it
+/// has no items.
+pub async fn post<T, P>(route: &str, schema: &str, parameters: &P) -> Result<impl Stream<Item = Result<T, ApiError<Infallible>>>, ApiError<Infallible>>
+	where T: DeserializeOwned,
+		  P: Serialize,
+{
+	Ok(decode_lines(chunks(route, schema, parameters).await?))
+}
+#[cfg(not(target_arch = "wasm32"))]
+async fn chunks<P: Serialize>(route: &str, schema: &str, parameters: &P) -> Result<impl Stream<Item = Result<Vec<u8>, TransportError>>, ApiError<Infallible>> {
+	let response = crate::ApiClient::current()
+		.post_parameters(route, parameters)
+		.header(crate::api_schema::HEADER, schema)
+		.header(reqwest::header::ACCEPT, CONTENT_TYPE)
+		.send()
+		.await
+		.map_err(ApiError::Transport)?;
+	if !response.status().is_success() {
+		return Err(ApiError::from_status(response.status().as_u16()));
+	}
+	Ok(response.bytes_stream().map(|chunk| chunk.map(|bytes| bytes.to_vec())))
+}
+#[cfg(target_arch = "wasm32")]
+async fn chunks<P: Serialize>(route: &str, schema: &str, parameters: &P) -> Result<impl Stream<Item = Result<Vec<u8>, TransportError>>, ApiError<Infallible>> {
+	use seed::{
+		browser::fetch::{
+			FetchError,
+			Header,
+		},
+		prelude::*,
+	};
+	use wasm_bindgen_futures::JsFuture;
+	use web_sys::ReadableStreamDefaultReader;
+	let req = crate::ApiClient::current()
+		.post_parameters(route, parameters)
+		.header(Header::custom(crate::api_schema::HEADER, schema.to_string()))
+		.header(Header::custom("Accept", CONTENT_TYPE));
+	let response = seed::fetch::fetch(req)
+		.await
+		.map_err(ApiError::Transport)?;
+	if !response.status().is_ok() {
+		return Err(ApiError::from_status(response.status().code));
+	}
+	let reader: ReadableStreamDefaultReader = response
+		.raw_response()
+		.body()
+		.ok_or_else(|| ApiError::Decode("Response has no body.".to_string()))?
+		.get_reader()
+		.unchecked_into();
+	// read chunks until the reader is done
+	Ok(futures::stream::unfold(Some(reader), |reader| async move {
+		let reader = reader?;
+		match JsFuture::from(reader.read()).await {
+			Ok(chunk) => {
+				let field = |name: &str| js_sys::Reflect::get(&chunk, &JsValue::from_str(name)).unwrap_or(JsValue::UNDEFINED);
+				if field("done").as_bool().unwrap_or(true) {
+					None
+				} else {
+					let bytes = js_sys::Uint8Array::new(&field("value")).to_vec();
+					Some((Ok(bytes), Some(reader)))
+				}
+			}
+			Err(e) => Some((Err(FetchError::PromiseError(e)), None)),
+		}
+	}))
+}
+/// Blocking reader of the JSON lines of a stream
+///
+/// Body of streaming responses of synchronous servers like Rocket, the items
+/// are awaited in `api_runtime`.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct JsonLines {
+	lines: std::pin::Pin<Box<dyn Stream<Item = Vec<u8>>>>,
+	line: Vec<u8>,
+	read: usize,
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl JsonLines {
+	pub fn new<S, T>(items: S) -> Self
+		where S: Stream<Item = T> + 'static,
+			  T: Serialize,
+	{
+		Self {
+			lines: Box::pin(items.map(|item| line(&item))),
+			line: Vec::new(),
+			read: 0,
+		}
+	}
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl std::io::Read for JsonLines {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		if self.read == self.line.len() {
+			match crate::api_runtime::block_on(self.lines.next()) {
+				Some(line) => {
+					self.line = line;
+					self.read = 0;
+				}
+				None => return Ok(0),
+			}
+		}
+		let len = buf.len().min(self.line.len() - self.read);
+		buf[..len].copy_from_slice(&self.line[self.read..self.read + len]);
+		self.read += len;
+		Ok(len)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on_stream;
+	use std::io::Read;
+	#[test]
+	fn lines() {
+		let mut body = String::new();
+		JsonLines::new(futures::stream::iter(vec![1, 2, 3]))
+			.read_to_string(&mut body)
+			.unwrap();
+		assert_eq!(body, "1\n2\n3\n");
+		// lines split across chunks, the last without line break
+		let chunks = futures::stream::iter(vec![Ok(b"[1,".to_vec()), Ok(b"2]\n[3".to_vec()), Ok(b"]".to_vec())]);
+		let items: Vec<Vec<u32>> = block_on_stream(Box::pin(decode_lines(chunks)))
+			.map(|item| item.unwrap())
+			.collect();
+		assert_eq!(items, vec![vec![1, 2], vec![3]]);
+	}
+}
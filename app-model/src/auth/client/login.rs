@@ -1,21 +1,45 @@
-use crate::auth::{
-	credentials::Credentials,
-	session::Session,
-	Auth,
-	UserSession,
+use crate::{
+	auth::{
+		credentials::Credentials,
+		session::Session,
+		Auth,
+		LoginError,
+		UserSession,
+	},
+	ApiError,
 };
 use components::{
 	Component,
 	Viewable,
 };
+use futures::future::LocalBoxFuture;
+use lazy_static::lazy_static;
 use seed::{
-	browser::fetch::FetchError,
 	prelude::*,
 	*,
 };
-use std::result::Result;
+use std::{
+	result::Result,
+	sync::RwLock,
+};
 use tracing::debug;
 
+/// Future of a call of the `login` api function
+pub type LoginCall = LocalBoxFuture<'static, Result<UserSession, ApiError<LoginError>>>;
+/// The `login` function generated by `api!`
+pub type LoginFn = fn(Credentials) -> LoginCall;
+
+lazy_static! {
+	static ref LOGIN: RwLock<Option<LoginFn>> = RwLock::new(None);
+}
+/// Log in with the `login` function of the app's api, call at startup:
+/// ```ignore
+/// app_model::auth::set_login(|credentials| api::login(credentials).boxed_local());
+/// ```
+/// Apps without one log in at the session route `/api/auth/login`.
+pub fn set_login(login: LoginFn) {
+	*LOGIN.write().unwrap() = Some(login);
+}
 #[derive(Debug, Clone)]
 pub struct Login {
 	pub credentials: Credentials,
@@ -28,14 +52,18 @@ pub enum Msg {
 	Submit,
 }
 impl Login {
-	async fn login_request(self) -> Result<UserSession, FetchError> {
-		let req = seed::fetch::Request::new(format!("{}/api/auth/login", crate::get_base_url().unwrap())).method(Method::Post);
-		seed::fetch::fetch(req.json(&self.credentials)?)
-			.await?
-			.check_status()?
-			.json()
+	/// Call the `login` api function, or the session route
+	async fn login_request(self) -> Result<UserSession, ApiError<LoginError>> {
+		let login = *LOGIN.read().unwrap();
+		if let Some(login) = login {
+			return login(self.credentials).await;
+		}
+		let req = seed::fetch::Request::new(crate::ApiClient::current().url("/api/auth/login")).method(Method::Post);
+		let response = seed::fetch::fetch(req.json(&self.credentials).map_err(ApiError::Transport)?)
 			.await
-			.map(|session: UserSession| session)
+			.map_err(ApiError::Transport)?;
+		let status = response.status().code;
+		ApiError::result(status, response.json().await.map(Ok).map_err(ApiError::Transport))
 	}
 }
 impl Default for Login {
@@ -54,7 +82,7 @@ impl Component for Login {
 			Msg::Submit => {
 				debug!("Logging in...");
 				orders.perform_cmd(self.clone().login_request().map(
-					|result: Result<UserSession, FetchError>| {
+					|result: Result<UserSession, ApiError<LoginError>>| {
 						Msg::LoginResponse(result.map_err(|e| format!("{:?}", e)))
 					},
 				));
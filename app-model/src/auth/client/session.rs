@@ -42,7 +42,7 @@ pub fn end() {
 pub struct Session;
 impl Session {
 	async fn logout_request(self) -> Result<(), FetchError> {
-		let req = seed::fetch::Request::new(format!("{}/api/auth/logout", crate::get_base_url().unwrap())).method(Method::Post);
+		let req = seed::fetch::Request::new(crate::ApiClient::current().url("/api/auth/logout")).method(Method::Post);
 		seed::fetch::fetch(req)
 			.await?
 			.check_status()?;
@@ -5,6 +5,7 @@ use serde::{
 };
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct Credentials {
 	pub username: String,
 	pub password: String,
@@ -28,7 +28,29 @@ pub enum Route {
 impl database_table::Route for Route {}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct UserSession {
+	#[cfg_attr(not(target_arch = "wasm32"), schemars(with = "String"))]
 	pub user_id: Id<User>,
 	pub token: String,
 }
+/// Error of a login
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+pub enum LoginError {
+	/// No user has the username
+	UnknownUser,
+	/// The password does not match the user
+	WrongPassword,
+	/// The user could not be read or the token could not be created
+	Internal(String),
+}
+impl database_table::ErrorStatus for LoginError {
+	fn status(&self) -> u16 {
+		match self {
+			Self::UnknownUser => 404,
+			Self::WrongPassword => 401,
+			Self::Internal(_) => 500,
+		}
+	}
+}
@@ -1,4 +1,5 @@
 use crate::user::*;
+use database_table::Principal;
 use chrono::{
 	Duration,
 	Utc,
@@ -72,6 +73,19 @@ pub struct JWTClaims {
 	exp: i64,
 	iat: i64,
 	nbf: i64,
+	#[serde(default)]
+	roles: Vec<String>,
+}
+impl JWTClaims {
+	pub fn subject(&self) -> &String {
+		&self.sub
+	}
+	pub fn roles(&self) -> &Vec<String> {
+		&self.roles
+	}
+	pub fn has_role(&self, role: &str) -> bool {
+		self.roles.iter().any(|r| r == role)
+	}
 }
 impl From<&User> for JWTClaims {
 	fn from(user: &User) -> Self {
@@ -80,6 +94,7 @@ impl From<&User> for JWTClaims {
 			iat: Utc::now().timestamp(),
 			nbf: Utc::now().timestamp(),
 			exp: (Utc::now() + get_token_lifetime()).timestamp(),
+			roles: user.roles().clone(),
 		}
 	}
 }
@@ -117,6 +132,18 @@ impl From<String> for JWT {
 		Self(s)
 	}
 }
+impl From<&JWT> for Principal {
+	fn from(token: &JWT) -> Self {
+		token.decode()
+			.map(|claims| Principal::from(&claims))
+			.unwrap_or(Principal::Anonymous)
+	}
+}
+impl From<&JWTClaims> for Principal {
+	fn from(claims: &JWTClaims) -> Self {
+		Principal::Subject(claims.sub.clone())
+	}
+}
 use rocket::{
 	http::Status,
 	request::{
@@ -130,7 +157,7 @@ impl<'a, 'r> FromRequest<'a, 'r> for JWT {
 	fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
 		let keys: Vec<_> = request.headers().get("authorization").collect();
 		match keys.len() {
-			0 => Outcome::Failure((Status::BadRequest, JWTError::MissingToken)),
+			0 => Outcome::Failure((Status::Unauthorized, JWTError::MissingToken)),
 			1 => {
 				let token = keys[0];
 				match JWT_PROVIDER.decode(token) {
@@ -156,4 +183,19 @@ pub mod tests {
 		let token = JWT::encode(&claims).unwrap();
 		assert_eq!(token.decode().unwrap(), claims)
 	}
+	#[test]
+	fn principal() {
+		let user = User::new("Slim Shady", "my_name_is");
+		let token = JWT::try_from(&user).unwrap();
+		assert_eq!(Principal::from(&token), Principal::Subject("Slim Shady".into()));
+		assert_eq!(Principal::from(&JWT::from("invalid".to_string())), Principal::Anonymous);
+	}
+	#[test]
+	fn roles() {
+		let mut user = User::new("Slim Shady", "my_name_is");
+		user.roles_mut().push("admin".into());
+		let claims = JWT::try_from(&user).unwrap().decode().unwrap();
+		assert!(claims.has_role("admin"));
+		assert!(!claims.has_role("moderator"));
+	}
 }
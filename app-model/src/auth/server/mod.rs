@@ -9,17 +9,22 @@ pub use {
 		credentials::*,
 	},
 	database_table::{
+		AsyncDatabase,
+		AsyncDatabaseTable,
 		Database,
 		DatabaseTable,
+		Error as TableError,
 	},
 	jwt::*,
 	std::convert::TryFrom,
 	tide::Error,
 };
-pub async fn login<'db, D: Database<'db, User>>(
+pub async fn login<D: AsyncDatabase<User>>(
 	credentials: Credentials,
 ) -> Result<UserSession, Error> {
-	DatabaseTable::<'db, D>::find(|user| *user.name() == credentials.username)
+	AsyncDatabaseTable::<D>::find_by_index("name".into(), credentials.username.clone())
+		.await
+		.map_err(|e| Error::from_str(e.status(), e.to_string()))?
 		.ok_or(Error::from_str(404, "User not found."))
 		.and_then(|entry| {
 			let user = entry.data();
@@ -43,18 +48,20 @@ pub async fn login<'db, D: Database<'db, User>>(
 			}
 		})
 }
-pub async fn register<'db, D: Database<'db, User>>(user: User) -> Result<UserSession, Error> {
-	if DatabaseTable::<'db, D>::find(|u| u.name() == user.name()).is_none() {
-		let id = DatabaseTable::<'db, D>::insert(user.clone());
-		JWT::try_from(&user)
-			.map_err(|e| Error::from_str(500, e.to_string()))
-			.map(move |jwt| {
-				UserSession {
-					user_id: id.clone(),
-					token: jwt.to_string(),
-				}
-			})
-	} else {
-		Err(Error::from_str(409, "User already exists."))
-	}
+pub async fn register<D: AsyncDatabase<User>>(mut user: User) -> Result<UserSession, Error> {
+	user.roles_mut().clear();
+	let id = AsyncDatabaseTable::<D>::insert(user.clone())
+		.await
+		.map_err(|e| match e {
+			TableError::Conflict(_) => Error::from_str(409, "User already exists."),
+			e => Error::from_str(e.status(), e.to_string()),
+		})?;
+	JWT::try_from(&user)
+		.map_err(|e| Error::from_str(500, e.to_string()))
+		.map(move |jwt| {
+			UserSession {
+				user_id: id.clone(),
+				token: jwt.to_string(),
+			}
+		})
 }
@@ -0,0 +1,239 @@
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+
+/// Encoding of api payloads, negotiated by content type
+///
+/// JSON is the default, MessagePack is a compact binary encoding for large
+/// payloads like price histories.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+	/// `application/json`
+	Json,
+	/// `application/msgpack`
+	MessagePack,
+}
+impl Default for Encoding {
+	fn default() -> Self {
+		Self::Json
+	}
+}
+impl Encoding {
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Self::Json => "application/json",
+			Self::MessagePack => "application/msgpack",
+		}
+	}
+	/// Encoding named by a Content-Type or Accept header value
+	///
+	/// Of several named encodings the one with the highest q-weight is
+	/// returned, the first on ties. Encodings with weight 0 are excluded.
+	pub fn from_header(value: &str) -> Option<Self> {
+		media_ranges(value)
+			.filter_map(|(media, quality)| Self::named(media).map(|encoding| (encoding, quality)))
+			.filter(|(_, quality)| *quality > 0.0)
+			.fold(None, |best: Option<(Self, f32)>, (encoding, quality)| match best {
+				Some((_, max)) if max >= quality => best,
+				_ => Some((encoding, quality)),
+			})
+			.map(|(encoding, _)| encoding)
+	}
+	fn named(media: &str) -> Option<Self> {
+		match media {
+			"application/json" => Some(Self::Json),
+			"application/msgpack" | "application/x-msgpack" => Some(Self::MessagePack),
+			_ => None,
+		}
+	}
+	/// q-weight of this encoding in an Accept header value, 0 if not accepted
+	///
+	/// The most specific media range matching the encoding decides, e.g.
+	/// `application/*` before `*/*`.
+	pub fn quality(&self, accept: &str) -> f32 {
+		media_ranges(accept)
+			.filter_map(|(media, quality)| match media {
+				"*/*" => Some((0, quality)),
+				"application/*" => Some((1, quality)),
+				_ if Self::named(media) == Some(*self) => Some((2, quality)),
+				_ => None,
+			})
+			.fold(None, |best: Option<(u8, f32)>, (specificity, quality)| match best {
+				Some((max, _)) if max >= specificity => best,
+				_ => Some((specificity, quality)),
+			})
+			.map(|(_, quality)| quality)
+			.unwrap_or(0.0)
+	}
+	/// Encoding named by an `encoding` parameter of a query string, e.g. `encoding=msgpack`
+	pub fn from_query(query: &str) -> Option<Self> {
+		query
+			.split('&')
+			.find_map(|pair| match pair.split_once('=') {
+				Some(("encoding", "json")) => Some(Self::Json),
+				Some(("encoding", "msgpack")) => Some(Self::MessagePack),
+				_ => None,
+			})
+	}
+	/// Encodings of a request and of its response
+	///
+	/// The request is decoded as its Content-Type, the response is encoded in
+	/// the encoding with the highest weight in the Accept header, or like the
+	/// request on ties and without the header. None if the Accept header
+	/// accepts no encoding, which is responded to with 406 Not Acceptable.
+	pub fn negotiate(content_type: Option<&str>, accept: Option<&str>) -> Option<(Self, Self)> {
+		let request = content_type.and_then(Self::from_header).unwrap_or_default();
+		let response = match accept {
+			Some(accept) => {
+				let other = match request {
+					Self::Json => Self::MessagePack,
+					Self::MessagePack => Self::Json,
+				};
+				// the request encoding wins ties
+				let best = if other.quality(accept) > request.quality(accept) {
+					other
+				} else {
+					request
+				};
+				if best.quality(accept) <= 0.0 {
+					return None;
+				}
+				best
+			}
+			None => request,
+		};
+		Some((request, response))
+	}
+	pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+		match self {
+			Self::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+			Self::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+		}
+	}
+	pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, String> {
+		match self {
+			Self::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+			Self::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+		}
+	}
+}
+/// Media ranges of a Content-Type or Accept header value, with their q-weights
+fn media_ranges(value: &str) -> impl Iterator<Item = (&str, f32)> {
+	value
+		.split(',')
+		.filter_map(|range| {
+			let mut params = range.split(';');
+			let media = params.next()?.trim();
+			let quality = params
+				.find_map(|param| match param.split_once('=') {
+					Some((name, quality)) if name.trim() == "q" => quality.trim().parse().ok(),
+					_ => None,
+				})
+				.unwrap_or(1.0);
+			Some((media, quality))
+		})
+}
+/// Payload of a Rocket request or response, in the negotiated `Encoding`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub struct Encoded<T>(pub T);
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: DeserializeOwned> rocket::data::FromDataSimple for Encoded<T> {
+	type Error = String;
+	fn from_data(request: &rocket::Request, data: rocket::Data) -> rocket::data::Outcome<Self, String> {
+		use rocket::{
+			http::Status,
+			Outcome,
+		};
+		use std::io::Read;
+		let encoding = request.headers().get_one("Content-Type")
+			.and_then(Encoding::from_header)
+			.unwrap_or_default();
+		let limit = request.limits().get("json").unwrap_or(1 << 20);
+		let mut bytes = Vec::new();
+		// read one byte more than the limit to tell oversized bodies apart
+		if let Err(e) = data.open().take(limit + 1).read_to_end(&mut bytes) {
+			return Outcome::Failure((Status::BadRequest, e.to_string()));
+		}
+		if bytes.len() as u64 > limit {
+			return Outcome::Failure((Status::PayloadTooLarge, format!("Body is larger than {} bytes.", limit)));
+		}
+		match encoding.decode(&bytes) {
+			Ok(value) => Outcome::Success(Self(value)),
+			Err(e) => Outcome::Failure((Status::BadRequest, e)),
+		}
+	}
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl<'r, T: Serialize> rocket::response::Responder<'r> for Encoded<T> {
+	fn respond_to(self, request: &rocket::Request) -> rocket::response::Result<'r> {
+		use rocket::http::{
+			ContentType,
+			Status,
+		};
+		let headers = request.headers();
+		let (_, encoding) = Encoding::negotiate(headers.get_one("Content-Type"), headers.get_one("Accept"))
+			.ok_or(Status::NotAcceptable)?;
+		let body = encoding.encode(&self.0).map_err(|_| Status::InternalServerError)?;
+		rocket::Response::build()
+			.header(ContentType::parse_flexible(encoding.content_type()).unwrap())
+			.sized_body(std::io::Cursor::new(body))
+			.ok()
+	}
+}
+/// Request guard rejecting requests whose Accept header accepts no `Encoding`
+/// with 406 Not Acceptable, before the handler runs
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Acceptable;
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a, 'r> rocket::request::FromRequest<'a, 'r> for Acceptable {
+	type Error = ();
+	fn from_request(request: &'a rocket::Request<'r>) -> rocket::request::Outcome<Self, ()> {
+		let headers = request.headers();
+		match Encoding::negotiate(headers.get_one("Content-Type"), headers.get_one("Accept")) {
+			Some(_) => rocket::Outcome::Success(Acceptable),
+			None => rocket::Outcome::Failure((rocket::http::Status::NotAcceptable, ())),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn negotiate() {
+		assert_eq!(Encoding::negotiate(None, None), Some((Encoding::Json, Encoding::Json)));
+		assert_eq!(
+			Encoding::negotiate(Some("application/msgpack"), Some("*/*")),
+			Some((Encoding::MessagePack, Encoding::MessagePack)),
+		);
+		assert_eq!(
+			Encoding::negotiate(Some("application/json; charset=utf-8"), Some("text/html, application/x-msgpack;q=0.9")),
+			Some((Encoding::Json, Encoding::MessagePack)),
+		);
+		// q-weights decide, not the order
+		assert_eq!(
+			Encoding::negotiate(None, Some("application/json;q=0.5, application/msgpack")),
+			Some((Encoding::Json, Encoding::MessagePack)),
+		);
+		assert_eq!(Encoding::from_header("application/msgpack;q=0.1, application/json;q=0.8"), Some(Encoding::Json));
+		assert_eq!(
+			Encoding::negotiate(Some("application/msgpack"), Some("application/msgpack;q=0, */*;q=0.1")),
+			Some((Encoding::MessagePack, Encoding::Json)),
+		);
+		// nothing acceptable
+		assert_eq!(Encoding::negotiate(None, Some("text/html")), None);
+		assert_eq!(Encoding::negotiate(None, Some("application/json;q=0")), None);
+		assert_eq!(Encoding::from_query("a=1&encoding=msgpack"), Some(Encoding::MessagePack));
+	}
+	#[test]
+	fn encode() {
+		let value = (vec![1u32, 2, 3], "text".to_string(), Some(1.5f64));
+		for encoding in [Encoding::Json, Encoding::MessagePack].iter() {
+			let bytes = encoding.encode(&value).unwrap();
+			assert_eq!(encoding.decode::<(Vec<u32>, String, Option<f64>)>(&bytes).unwrap(), value);
+		}
+		assert!(Encoding::MessagePack.encode(&value).unwrap().len() < Encoding::Json.encode(&value).unwrap().len());
+	}
+}
@@ -1,3 +1,15 @@
+pub mod api_client;
+pub use api_client::{
+	ApiClient,
+	ApiError,
+};
+pub mod api_batch;
+pub mod api_schema;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod api_runtime;
+pub mod api_socket;
+pub mod api_stream;
+pub mod encoding;
 pub mod auth;
 pub use auth::*;
 pub mod market;
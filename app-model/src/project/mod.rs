@@ -4,6 +4,9 @@ use crate::{
 };
 use database_table::{
 	Entry,
+	IndexDef,
+	Indexed,
+	SearchField,
 	TableRoutable,
 };
 use derive_builder::Builder;
@@ -36,10 +39,13 @@ pub mod list;
 pub mod profile;
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Builder)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct Project {
 	name: String,
 	description: String,
+	#[cfg_attr(not(target_arch = "wasm32"), schemars(with = "Vec<String>"))]
 	members: Vec<Id<User>>,
+	#[cfg_attr(not(target_arch = "wasm32"), schemars(with = "Vec<String>"))]
 	tasks: Vec<Id<Task>>,
 }
 #[derive(Clone, Debug, AsPath)]
@@ -58,6 +64,19 @@ impl TableRoutable for Project {
 		Route::Project(id)
 	}
 }
+impl Indexed for Project {
+	fn indexes() -> Vec<IndexDef<Self>> {
+		vec![IndexDef::multi("members", |project: &Project| {
+			project.members().iter().map(ToString::to_string).collect()
+		})]
+	}
+	fn search_fields() -> Vec<SearchField<Self>> {
+		vec![
+			SearchField::new("name", |project: &Project| project.name().clone()).weight(2.0),
+			SearchField::new("description", |project: &Project| project.description().clone()),
+		]
+	}
+}
 
 impl Project {
 	pub fn new(name: String) -> Self {
@@ -86,12 +105,18 @@ impl Project {
 	pub fn add_member(&mut self, id: Id<User>) {
 		self.members.push(id);
 	}
+	pub fn remove_member(&mut self, id: Id<User>) {
+		self.members.retain(|m| *m != id);
+	}
 	pub fn tasks(&self) -> &Vec<Id<Task>> {
 		&self.tasks
 	}
 	pub fn add_task(&mut self, id: Id<Task>) {
 		self.tasks.push(id);
 	}
+	pub fn remove_task(&mut self, id: Id<Task>) {
+		self.tasks.retain(|t| *t != id);
+	}
 }
 impl From<Entry<Project>> for Project {
 	fn from(entry: Entry<Self>) -> Self {
@@ -1,6 +1,8 @@
 use crate::user::*;
 use database_table::{
 	Entry,
+	Indexed,
+	SearchField,
 	TableRoutable,
 };
 use enum_paths::AsPath;
@@ -34,11 +36,14 @@ pub mod list;
 pub mod profile;
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Builder)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct Task {
 	title: String,
 	description: String,
 
+	#[cfg_attr(not(target_arch = "wasm32"), schemars(with = "Vec<String>"))]
 	assignees: Vec<Id<User>>,
+	#[cfg_attr(not(target_arch = "wasm32"), schemars(with = "Vec<String>"))]
 	subtasks: Vec<Id<Task>>,
 }
 #[derive(Clone, Debug, AsPath)]
@@ -57,6 +62,14 @@ impl TableRoutable for Task {
 		Route::Task(id)
 	}
 }
+impl Indexed for Task {
+	fn search_fields() -> Vec<SearchField<Self>> {
+		vec![
+			SearchField::new("title", |task: &Task| task.title().clone()).weight(2.0),
+			SearchField::new("description", |task: &Task| task.description().clone()),
+		]
+	}
+}
 impl Task {
 	pub fn new<S: ToString>(title: S) -> Self {
 		Self {
@@ -92,12 +105,18 @@ impl Task {
 	pub fn add_assignee(&mut self, id: Id<User>) {
 		self.assignees.push(id);
 	}
+	pub fn remove_assignee(&mut self, id: Id<User>) {
+		self.assignees.retain(|a| *a != id);
+	}
 	pub fn subtasks(&self) -> &Vec<Id<Self>> {
 		&self.subtasks
 	}
 	pub fn children_mut(&mut self) -> &mut Vec<Id<Self>> {
 		&mut self.subtasks
 	}
+	pub fn remove_subtask(&mut self, id: Id<Self>) {
+		self.subtasks.retain(|t| *t != id);
+	}
 }
 impl From<Entry<Task>> for Task {
 	fn from(entry: Entry<Self>) -> Self {
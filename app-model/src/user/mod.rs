@@ -1,6 +1,9 @@
 use crate::auth::credentials::*;
 use database_table::{
 	Entry,
+	IndexDef,
+	Indexed,
+	SearchField,
 	TableRoutable,
 };
 use enum_paths::AsPath;
@@ -29,12 +32,19 @@ use {
 
 //#[cfg(target_arch = "wasm32")]
 //pub mod profile;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod policy;
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct User {
 	credentials: Credentials,
 	full_name: Option<String>,
+	#[cfg_attr(not(target_arch = "wasm32"), schemars(with = "Vec<String>"))]
 	followers: Vec<Id<User>>,
+	/// Roles of the user, e.g. "admin", for `#[role(..)]` api functions
+	#[serde(default)]
+	roles: Vec<String>,
 }
 impl From<Credentials> for User {
 	fn from(credentials: Credentials) -> Self {
@@ -42,6 +52,7 @@ impl From<Credentials> for User {
 			credentials,
 			full_name: None,
 			followers: vec![],
+			roles: vec![],
 		}
 	}
 }
@@ -59,6 +70,7 @@ impl User {
 			credentials: Credentials::new(name, password),
 			full_name: None,
 			followers: vec![],
+			roles: vec![],
 		}
 	}
 	pub fn name(&self) -> &String {
@@ -79,6 +91,12 @@ impl User {
 	pub fn full_name(&self) -> &Option<String> {
 		&self.full_name
 	}
+	pub fn roles(&self) -> &Vec<String> {
+		&self.roles
+	}
+	pub fn roles_mut(&mut self) -> &mut Vec<String> {
+		&mut self.roles
+	}
 }
 #[derive(Clone, Debug, AsPath)]
 pub enum Route {
@@ -96,6 +114,17 @@ impl TableRoutable for User {
 		Route::User(id)
 	}
 }
+impl Indexed for User {
+	fn indexes() -> Vec<IndexDef<Self>> {
+		vec![IndexDef::unique("name", |user: &User| vec![user.name().clone()])]
+	}
+	fn search_fields() -> Vec<SearchField<Self>> {
+		vec![
+			SearchField::new("name", |user: &User| user.name().clone()).weight(2.0),
+			SearchField::new("full_name", |user: &User| user.full_name().clone().unwrap_or_default()),
+		]
+	}
+}
 impl From<Entry<User>> for User {
 	fn from(entry: Entry<User>) -> Self {
 		entry.into_inner()
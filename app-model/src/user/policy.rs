@@ -0,0 +1,34 @@
+//! Access policy of `User` rows, shared by the servers
+use super::User;
+use database_table::{
+	Entry,
+	Principal,
+};
+
+/// Users may change their own row, except their name and roles
+///
+/// New users register through the auth routes, roles are granted by admins.
+pub fn can_write(principal: &Principal, old: Option<&Entry<User>>, data: &User) -> bool {
+	old.map(|old| principal.subject() == Some(old.data().name())
+			&& old.data().name() == data.name()
+			&& old.data().roles() == data.roles())
+		.unwrap_or(false)
+}
+/// Users may only delete themselves
+pub fn can_delete(principal: &Principal, entry: &Entry<User>) -> bool {
+	principal.subject() == Some(entry.data().name())
+}
+/// Passwords are never sent out
+pub fn redact(user: &mut User) {
+	user.credentials_mut().password.clear();
+}
+/// Whether principal is a user with role, e.g. "admin"
+pub fn has_role<'db, D>(principal: &Principal, role: &str) -> bool
+	where User: database_table::DatabaseTable<'db, D>,
+		  D: database_table::Database<'db, User>,
+{
+	principal.subject()
+		.and_then(|name| <User as database_table::DatabaseTable<'db, D>>::find_by_index("name", name).ok().flatten())
+		.map(|entry| entry.data().roles().iter().any(|r| r == role))
+		.unwrap_or(false)
+}
@@ -16,13 +16,17 @@ use seed::{
 use std::collections::HashMap;
 use shared::{
 	subscriptions::{
+		Request,
 		Response,
 		PriceSubscription,
 		Route,
 	},
+	ClientMessage,
 };
 use database_table::{
+	Change,
 	Entry,
+	ListQuery,
 	RemoteTable,
 };
 #[allow(unused)]
@@ -59,11 +63,13 @@ impl Init<Route> for Subscriptions {
 	fn init(_: Route, orders: &mut impl Orders<Msg>) -> Self {
 		// TODO add components for list and entry
 		orders.send_msg(Msg::GetList);
+		orders.notify(ClientMessage::Subscriptions(Request::WatchPriceSubscriptions));
 		Self {
 			server_msg_sub: orders.subscribe_with_handle(|msg: Response| {
 				debug!("Received Subscription Response");
 				match msg {
 					Response::SubscriptionList(list) => Some(Msg::SetList(list)),
+					Response::Changed(change) => Some(Msg::Changed(change)),
 					Response::PriceHistory(id, history) => Some(
 						Msg::Subscription(
 							id,
@@ -84,6 +90,7 @@ pub enum Msg {
 	OpenEditor,
 	Editor(<Editor<PriceSubscription> as Component>::Msg),
 	SetList(Vec<Entry<PriceSubscription>>),
+	Changed(Change<PriceSubscription>),
 	Subscription(Id<PriceSubscription>, subscription::Msg),
 }
 impl Component for Subscriptions {
@@ -100,9 +107,9 @@ impl Component for Subscriptions {
 				debug!("Msg::GetList...");
 				orders.perform_cmd(async move {
 					debug!("Calling command..");
-					PriceSubscription::get_all()
+					PriceSubscription::get_all(ListQuery::default())
                         .await
-						.map(Msg::SetList)
+						.map(|page| Msg::SetList(page.items))
                         .expect("Failed to get SubscriptionList")
 				});
 			},
@@ -118,6 +125,22 @@ impl Component for Subscriptions {
 				.collect();
 				self.update_list = true;
 			},
+			Msg::Changed(change) => {
+				debug!("Applying SubscriptionList change");
+				match change {
+					Change::Inserted(entry) | Change::Updated(entry) => {
+						let id = entry.id.clone();
+						self.subscriptions.insert(
+							id.clone(),
+							SubscriptionInfo::init(entry, &mut orders.proxy(move |msg| Msg::Subscription(id, msg)))
+						);
+					},
+					Change::Deleted(id) => {
+						self.subscriptions.remove(&id);
+					},
+				}
+				self.update_list = true;
+			},
 			Msg::Editor(msg) => {
 				debug!("Editor Msg {:#?}", msg);
 				if let Some(ed) = &mut self.editor {
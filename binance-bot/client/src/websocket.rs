@@ -3,6 +3,7 @@ use shared::{
     ServerMessage,
     WebsocketCommand,
 };
+use app_model::encoding::Encoding;
 use components::{
     Component,
     Init,
@@ -53,7 +54,9 @@ impl WebSocket {
     fn create_websocket(host: &str, orders: &mut impl Orders<Msg>) -> SeedWebSocket {
         debug!("Creating Websocket...");
         let msg_sender = orders.msg_sender();
-        let url = format!("wss://{}/wss", host);
+        // server messages are compact MessagePack in release builds, readable JSON otherwise
+        let encoding = if cfg!(debug_assertions) { "json" } else { "msgpack" };
+        let url = format!("wss://{}/wss?encoding={}", host, encoding);
         let ws = SeedWebSocket::builder(url, orders)
             .on_open(|| Msg::Opened)
             .on_message(move |msg| Self::receive_message(msg, msg_sender))
@@ -77,8 +80,11 @@ impl WebSocket {
                     .bytes()
                     .await
                     .expect("websocket::Error on binary data");
-                let msg: ServerMessage = serde_json::de::from_slice(&bytes).unwrap();
-                msg_sender(Some(Msg::MessageReceived(msg)));
+                // binary messages are MessagePack
+                match Encoding::MessagePack.decode::<ServerMessage>(&bytes) {
+                    Ok(msg) => msg_sender(Some(Msg::MessageReceived(msg))),
+                    Err(err) => error!("Failed to decode WebSocket binary message: {}", err),
+                }
             });
         }
     }
@@ -0,0 +1,132 @@
+use crate::database::Schema;
+use app_model::auth::jwt;
+use database_table::*;
+use define_api::api;
+use futures::{
+	SinkExt,
+	StreamExt,
+};
+use shared::PriceSubscription;
+use tide::Request;
+use tide_websockets::{
+	Message,
+	WebSocket,
+	WebSocketConnection,
+};
+use tracing::{
+	debug,
+	error,
+};
+
+api! {
+	#![backend(tide)]
+	#![websocket(push = "Change<PriceSubscription>")]
+	/// Price subscriptions readable by the caller
+	#[public]
+	fn get_price_subscriptions(principal: Principal) -> Result<Vec<Entry<PriceSubscription>>, database_table::Error> {
+		<PriceSubscription as DatabaseTable<'_, Schema>>::list_as(&principal, ListQuery::default())
+			.map(|page| page.items)
+	}
+}
+
+/// Serve the api calls and the api websocket at server
+pub fn routes(server: &mut tide::Server<()>) {
+	handlers::routes(server);
+	server.at("/api/socket").get(WebSocket::new(async move |request: Request<()>, ws| {
+		socket(ws, crate::server::principal(&request)).await;
+		Ok(())
+	}));
+}
+/// Serve an api websocket connection, pushing the subscription changes principal may read
+///
+/// Requests and messages are sent as JSON text messages.
+pub async fn socket(ws: WebSocketConnection, principal: Principal) {
+	let (sink, stream) = ws.split();
+	let incoming = stream.filter_map(async move |msg| match msg {
+		Ok(Message::Text(text)) => serde_json::from_str(&text)
+			.map_err(|e| error!("Failed to parse ApiRequest: {}", e))
+			.ok(),
+		_ => None,
+	});
+	let outgoing = sink.with(async move |msg: ApiMessage| {
+		Ok(Message::Text(serde_json::to_string(&msg).expect("Failed to serialize ApiMessage."))) as Result<_, tide_websockets::Error>
+	});
+	let pushes = <PriceSubscription as DatabaseTable<'_, Schema>>::watch_as(&principal);
+	if let Err(e) = websocket::serve(incoming, outgoing, pushes).await {
+		debug!("Api websocket closed: {}", e);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use app_model::ApiClient;
+	use async_std::net::{
+		SocketAddr,
+		TcpListener,
+	};
+	use async_tungstenite::tungstenite;
+
+	/// Serve the api on a free port with an in-memory database
+	async fn listen() -> SocketAddr {
+		std::env::set_var(crate::database::DATABASE_ENV, "sqlite::memory:");
+		crate::database::open().unwrap();
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut server = tide::new();
+		server.with(tide::sessions::SessionMiddleware::new(
+			tide::sessions::MemoryStore::new(),
+			&[0; 32],
+		));
+		routes(&mut server);
+		async_std::task::spawn(server.listen(listener));
+		addr
+	}
+	fn subscription() -> PriceSubscription {
+		PriceSubscription {
+			market_pair: "SOLBTC".into(),
+			owner: None,
+		}
+	}
+	#[tokio::test]
+	async fn serves_calls() {
+		let addr = listen().await;
+		ApiClient::new().base_url(format!("http://{}", addr)).set();
+		let id = <PriceSubscription as DatabaseTable<'_, Schema>>::insert(subscription()).unwrap();
+		let subscriptions = get_price_subscriptions().await.unwrap();
+		assert!(subscriptions.iter().any(|entry| *entry.id() == id));
+	}
+	#[tokio::test]
+	async fn serves_socket() {
+		let addr = listen().await;
+		let (ws, _) = async_tungstenite::async_std::connect_async(format!("ws://{}/api/socket", addr)).await.unwrap();
+		let (sink, stream) = ws.split();
+		let (socket, requests) = ApiSocket::new();
+		async_std::task::spawn(requests
+			.map(|request| tungstenite::Message::Text(serde_json::to_string(&request).unwrap()))
+			.map(Ok)
+			.forward(sink));
+		let (pushed, pushes) = futures::channel::mpsc::unbounded();
+		let receiver = socket.clone();
+		async_std::task::spawn(stream.for_each(move |msg| {
+			if let Ok(tungstenite::Message::Text(text)) = msg {
+				if let Some(push) = receiver.receive(serde_json::from_str(&text).unwrap()) {
+					pushed.unbounded_send(push).unwrap();
+				}
+			}
+			futures::future::ready(())
+		}));
+
+		// the server watches the table once it responded
+		socket.get_price_subscriptions().await.unwrap();
+		let id = <PriceSubscription as DatabaseTable<'_, Schema>>::insert(subscription()).unwrap();
+		// other tests write to the table as well
+		let mut changes = pushes.filter(|change| futures::future::ready(change.id() == id));
+		match changes.next().await {
+			Some(Change::Inserted(entry)) => assert_eq!(*entry.id(), id),
+			_ => panic!("expected the insert to be pushed"),
+		}
+		let subscriptions = socket.get_price_subscriptions().await.unwrap();
+		assert!(subscriptions.iter().any(|entry| *entry.id() == id));
+	}
+}
@@ -1,35 +1,60 @@
 use shared::subscriptions::PriceSubscription;
-use rql::*;
-use lazy_static::lazy_static;
 use database_table::{
-	Database,
+	Authorized,
+	Entry,
+	Migrations,
+	Principal,
+	Referenced,
+};
+use define_api::database_table;
+use app_model::user::{
+	self,
+	User,
 };
-use app_model::user::User;
 
-schema! {
+database_table! {
 	pub Schema {
 		user: User,
 		subscription: PriceSubscription,
 	}
+	name = "binance_bot_database",
+	env = "BINANCE_BOT_DATABASE",
+	snapshot_env = "BINANCE_BOT",
+	migrations = migrations,
 }
 
-lazy_static! {
-	pub static ref DB: Schema = Schema::new("binance_bot_database", rql::BinaryStable).unwrap();
+/// Migrations of the stored subscriptions and users
+fn migrations() -> Migrations {
+	Migrations::new()
+		.register("subscription", 0, |subscription| {
+			subscription["owner"] = serde_json::Value::Null;
+			Ok(())
+		})
 }
-
-impl<'db> Database<'db, User> for Schema {
-	fn table() -> TableGuard<'db, User> {
-		DB.user()
+impl<'db> Referenced<'db, Schema> for User {}
+impl<'db> Referenced<'db, Schema> for PriceSubscription {}
+impl Authorized<Schema> for User {
+	fn can_write(principal: &Principal, old: Option<&Entry<Self>>, data: &Self) -> bool {
+		user::policy::can_write(principal, old, data)
 	}
-	fn table_mut() -> TableGuardMut<'db, User> {
-		DB.user_mut()
+	fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+		user::policy::can_delete(principal, entry)
 	}
+	fn redact(&mut self, _principal: &Principal) {
+		user::policy::redact(self)
+	}
+}
+/// Whether principal owns subscription
+fn owns(principal: &Principal, subscription: &PriceSubscription) -> bool {
+	principal.subject().is_some() && principal.subject() == subscription.owner.as_deref()
 }
-impl<'db> Database<'db, PriceSubscription> for Schema {
-	fn table() -> TableGuard<'db, PriceSubscription> {
-		DB.subscription()
+impl Authorized<Schema> for PriceSubscription {
+	/// Admins may change all subscriptions, users only their own
+	fn can_write(principal: &Principal, old: Option<&Entry<Self>>, data: &Self) -> bool {
+		user::policy::has_role::<Schema>(principal, "admin")
+			|| (owns(principal, data) && old.map(|old| owns(principal, old.data())).unwrap_or(true))
 	}
-	fn table_mut() -> TableGuardMut<'db, PriceSubscription> {
-		DB.subscription_mut()
+	fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+		user::policy::has_role::<Schema>(principal, "admin") || owns(principal, entry.data())
 	}
 }
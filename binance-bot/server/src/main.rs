@@ -1,6 +1,7 @@
 #![feature(async_closure)]
 #![feature(bool_to_option)]
 #![feature(map_into_keys_values)]
+pub mod api;
 pub mod binance;
 pub mod command;
 pub mod error;
@@ -47,7 +48,20 @@ pub use server::*;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+	if let Some(code) = database::run_command(std::env::args()) {
+		std::process::exit(code);
+	}
 	let _tracing = init_tracing();
+	if let Err(e) = database::open() {
+		return Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
+	}
+	match database_table::SnapshotSchedule::from_env(database::SNAPSHOT_ENV) {
+		Ok(Some(schedule)) => {
+			database::snapshot().schedule(schedule);
+		}
+		Ok(None) => {}
+		Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+	}
 	server::run().await
 }
 
@@ -12,7 +12,10 @@ use app_model::{
         login,
         register,
         Credentials,
+        UserSession,
+        JWT,
     },
+    encoding::Encoding,
     user::User,
 };
 use async_std::net::SocketAddr;
@@ -42,11 +45,16 @@ use tracing::{
 };
 use database::Schema;
 use database_table::{
-    Database,
+    AsyncDatabase,
+    AsyncDatabaseTable,
     DatabaseTable,
+    ListQuery,
+    Principal,
     TableRoutable,
 };
 use enum_paths::AsPath;
+use futures::StreamExt;
+use serde::Deserialize;
 use std::fmt::Debug;
 
 macro_rules! client_file {
@@ -65,8 +73,8 @@ macro_rules! index {
     };
 }
 #[async_trait::async_trait]
-trait ServeSession<'db, DB>
-    where DB: Database<'db, User> + 'db,
+trait ServeSession<DB>
+    where DB: AsyncDatabase<User>,
 {
     type Api;
     type Response;
@@ -76,10 +84,15 @@ trait ServeSession<'db, DB>
     async fn logout_handler(mut req: Self::Request) -> Self::Response;
     async fn registration_handler(mut req: Self::Request) -> Self::Response;
 }
+/// Query parameters of a table search request
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+}
 #[async_trait::async_trait]
-trait ServeTable<'db, R, T, DB>
-    where T: TableRoutable + DatabaseTable<'db, DB> + 'db,
-          DB: Database<'db, T> + 'db,
+trait ServeTable<R, T, DB>
+    where T: TableRoutable + AsyncDatabaseTable<DB>,
+          DB: AsyncDatabase<T>,
           R: Router<T> + 'static
 {
     type Api;
@@ -90,6 +103,8 @@ trait ServeTable<'db, R, T, DB>
     async fn get_handler(req: Self::Request) -> Self::Response;
     async fn get_list_handler(req: Self::Request) -> Self::Response;
     async fn delete_handler(req: Self::Request) -> Self::Response;
+    async fn search_handler(req: Self::Request) -> Self::Response;
+    async fn changes_handler(req: Self::Request, sender: tide::sse::Sender) -> tide::Result<()>;
 }
 
 use shared::{
@@ -114,7 +129,7 @@ impl TideServer {
         new
     }
     fn auth(server: &mut tide::Server<()>) {
-        <Self as ServeSession<'_, Schema>>::serve(server)
+        <Self as ServeSession<Schema>>::serve(server)
     }
     fn wss(&mut self) {
         let server = &mut self.server;
@@ -136,14 +151,19 @@ impl TideServer {
         debug!("Routing {}", route);
         let mut api = tide::new();
         Self::auth(&mut api);
-        <Self as ServeTable<'_, Route, PriceSubscription, Schema>>
+        <Self as ServeTable<Route, PriceSubscription, Schema>>
             ::serve(&mut api);
         api.at("/price_history").nest(price_api());
         server.at(&route).nest(api);
+        crate::api::routes(server);
     }
     async fn wss_handler(request: Request<()>) -> tide::Result {
-        WebSocket::new(async move |_, ws| {
-            websocket::connection(ws).await;
+        // server messages are JSON, unless the client asks for ?encoding=msgpack
+        WebSocket::new(async move |request: Request<()>, ws| {
+            let encoding = request.url().query()
+                .and_then(Encoding::from_query)
+                .unwrap_or_default();
+            websocket::connection(ws, encoding, principal(&request)).await;
             Ok(())
         })
         .call(request).await
@@ -186,17 +206,17 @@ impl TideServer {
     }
 }
 #[async_trait::async_trait]
-impl<DB> ServeSession<'static, DB> for TideServer
-    where DB: Database<'static, User> + 'static,
+impl<DB> ServeSession<DB> for TideServer
+    where DB: AsyncDatabase<User>,
 {
     type Api = tide::Server<()>;
     type Response = tide::Result;
     type Request = Request<()>;
     fn serve(server: &mut Self::Api) {
         let mut auth = tide::new();
-        auth.at("/login").post(<Self as ServeSession<'static, DB>>::login_handler);
-        auth.at("/register").post(<Self as ServeSession<'static, DB>>::registration_handler);
-        auth.at("/logout").post(<Self as ServeSession<'static, DB>>::logout_handler);
+        auth.at("/login").post(<Self as ServeSession<DB>>::login_handler);
+        auth.at("/register").post(<Self as ServeSession<DB>>::registration_handler);
+        auth.at("/logout").post(<Self as ServeSession<DB>>::logout_handler);
         server.at("/auth").nest(auth);
     }
     async fn login_handler(mut req: Self::Request) -> Self::Response {
@@ -219,15 +239,26 @@ impl<DB> ServeSession<'static, DB> for TideServer
         let user: User = req.body_json().await?;
         match register::<database::Schema>(user).await {
             Ok(_session) => Ok(Response::new(200)),
-            Err(e) => Err(tide::Error::from_str(500, e.to_string())),
+            Err(e) => Err(e),
         }
     }
 }
 
+/// Principal of the logged in user of the request session
+pub(crate) fn principal(req: &Request<()>) -> Principal {
+    req.session()
+        .get::<UserSession>("session")
+        .map(|session| Principal::from(&JWT::from(session.token)))
+        .unwrap_or(Principal::Anonymous)
+}
+/// Response error with the status of a table error
+fn table_error(e: database_table::Error) -> tide::Error {
+    tide::Error::from_str(e.status(), e.to_string())
+}
 #[async_trait::async_trait]
-impl<R, T, DB> ServeTable<'static, R, T, DB> for TideServer
-    where T: TableRoutable + DatabaseTable<'static, DB> + Debug + 'static,
-          DB: Database<'static, T> + 'static,
+impl<R, T, DB> ServeTable<R, T, DB> for TideServer
+    where T: TableRoutable + AsyncDatabaseTable<DB> + Debug,
+          DB: AsyncDatabase<T>,
           R: Router<T> + AsPath + 'static
 {
     type Api = tide::Server<()>;
@@ -238,38 +269,61 @@ impl<R, T, DB> ServeTable<'static, R, T, DB> for TideServer
     fn serve(api: &mut Self::Api) {
         let mut t = tide::new();
         t.at("/")
-            .get(<Self as ServeTable<'_, R, T, DB>>::get_list_handler)
-            .post(<Self as ServeTable<'_, R, T, DB>>::post_handler);
+            .get(<Self as ServeTable<R, T, DB>>::get_list_handler)
+            .post(<Self as ServeTable<R, T, DB>>::post_handler);
         t.at("/:id")
-            .get(<Self as ServeTable<'_, R, T, DB>>::get_handler)
-            .delete(<Self as ServeTable<'_, R, T, DB>>::delete_handler);
+            .get(<Self as ServeTable<R, T, DB>>::get_handler)
+            .delete(<Self as ServeTable<R, T, DB>>::delete_handler);
+        t.at("/search")
+            .get(<Self as ServeTable<R, T, DB>>::search_handler);
+        t.at("/changes")
+            .get(tide::sse::endpoint(<Self as ServeTable<R, T, DB>>::changes_handler));
         let route = R::route_sub(T::table_route()).prefix();
         debug!("Routing {}", route);
         api.at(&route).nest(t);
     }
     async fn post_handler(mut req: Self::Request) -> Self::Response {
         let s: T = req.body_json().await?;
-        let id = T::insert(s);
+        let id = <T as AsyncDatabaseTable<DB>>::insert_as(principal(&req), s).await
+            .map_err(table_error)?;
         let body = Body::from_json(&id)?;
         debug!("{:#?}", body);
         Ok(body)
     }
     async fn get_handler(req: Self::Request) -> Self::Response {
         let id: rql::Id<T> = req.param("id")?.parse()?;
-        let r = T::get(id);
+        let r = <T as AsyncDatabaseTable<DB>>::get_as(principal(&req), id).await
+            .map_err(table_error)?;
         Ok(Body::from_json(&r)?)
     }
-    async fn get_list_handler(_req: Self::Request) -> Self::Response {
+    async fn get_list_handler(req: Self::Request) -> Self::Response {
         debug!("Get subscription list handler");
-        let list = T::get_all();
-        debug!("Result: {:?}", list);
-        Ok(Body::from_json(&list)?)
+        let query: ListQuery = req.query()?;
+        let page = <T as AsyncDatabaseTable<DB>>::list_as(principal(&req), query).await
+            .map_err(table_error)?;
+        debug!("Result: {:?}", page);
+        Ok(Body::from_json(&page)?)
     }
     async fn delete_handler(req: Self::Request) -> Self::Response {
         let id: rql::Id<T> = req.param("id")?.parse()?;
-        let r = T::delete(id);
+        let r = <T as AsyncDatabaseTable<DB>>::delete_as(principal(&req), id).await
+            .map_err(table_error)?;
         Ok(Body::from_json(&r)?)
     }
+    async fn search_handler(req: Self::Request) -> Self::Response {
+        let SearchQuery { q } = req.query()?;
+        let r = <T as AsyncDatabaseTable<DB>>::search_as(principal(&req), q).await
+            .map_err(table_error)?;
+        Ok(Body::from_json(&r)?)
+    }
+    /// Stream changes of the table as server-sent events
+    async fn changes_handler(req: Self::Request, sender: tide::sse::Sender) -> tide::Result<()> {
+        let mut changes = <T as DatabaseTable<'static, DB>>::watch_as(&principal(&req));
+        while let Some(change) = changes.next().await {
+            sender.send("message", serde_json::to_string(&change)?, None).await?;
+        }
+        Ok(())
+    }
 }
 
 async fn price_history_handler(_: Request<()>) -> tide::Result<Body> {
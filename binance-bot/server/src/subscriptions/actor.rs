@@ -136,6 +136,9 @@ impl Receive<Request> for SubscriptionsActor {
 					let id = add_subscription(request.clone()).await.unwrap();
 					myself.tell(Msg::AddActor(id.clone()), sender);
 				},
+				Request::WatchPriceSubscriptions => {
+					error!("WatchPriceSubscriptions must be handled by ConnectionActor");
+				},
 				Request::Subscription(id, req) => {
 					let id = id.clone();
 					trace!("Request for Subscription {:#?}", id);
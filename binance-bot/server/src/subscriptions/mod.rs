@@ -156,9 +156,9 @@ pub struct StaticSubscriptions {
 }
 impl StaticSubscriptions {
 	fn load_subscriptions_table() -> HashMap<Id<PriceSubscription>, Arc<RwLock<SubscriptionCache>>> {
-		<PriceSubscription as DatabaseTable<'_, Schema>>::table()
-			.rows()
-			.map(|row| (row.id.clone(), Arc::new(RwLock::new(SubscriptionCache::from(row.data.clone())))))
+		<PriceSubscription as DatabaseTable<'_, Schema>>::get_all()
+			.into_iter()
+			.map(|entry| (entry.id.clone(), Arc::new(RwLock::new(SubscriptionCache::from(entry.into_inner())))))
 			.collect()
 	}
 	pub fn new() -> Self {
@@ -181,8 +181,8 @@ impl StaticSubscriptions {
 			Ok(id)
 		} else {
 			let sub = PriceSubscription::from(request);
-			let id = DatabaseTable::<'_, Schema>::table_mut()
-				.insert(sub.clone());
+			let id = DatabaseTable::<'_, Schema>::insert(sub.clone())
+				.map_err(|e| Error::from(e.to_string()))?;
 			self.subscriptions.insert(id.clone(), Arc::new(RwLock::new(SubscriptionCache::from(sub))));
 			self.new_subscriptions = true;
 			Ok(id)
@@ -1,12 +1,22 @@
 use crate::{
+	database::Schema,
 	subscriptions::SubscriptionsActor,
 	websocket,
 };
 use shared::{
+	subscriptions::{
+		PriceSubscription,
+		Request,
+		Response,
+	},
 	ClientMessage,
 	ServerMessage,
 	WebsocketCommand,
 };
+use database_table::{
+	DatabaseTable,
+	Principal,
+};
 #[allow(unused)]
 use tracing::{
 	debug,
@@ -19,10 +29,13 @@ use riker::actors::{
 	Sender as RkSender,
 	CreateError
 };
+use async_std::task::JoinHandle;
 use futures::{
 	channel::mpsc::{
 		Sender,
 	},
+	SinkExt,
+	StreamExt,
 };
 
 #[derive(Clone, Debug)]
@@ -34,15 +47,38 @@ pub enum Msg {
 pub struct ConnectionActor {
 	id: usize,
 	sender: Sender<ServerMessage>,
+	/// Caller the changes forwarded to the connection are filtered for
+	principal: Principal,
 	subscriptions: Option<ActorRef<<SubscriptionsActor as Actor>::Msg>>,
+	/// Task forwarding changes of the subscriptions table, if watched
+	watching_subscriptions: Option<JoinHandle<()>>,
 }
 impl ConnectionActor {
 	pub fn actor_name(id: usize) -> String {
 		format!("Connection_{}", id)
 	}
-	pub async fn create(sender: Sender<ServerMessage>) -> Result<ActorRef<<ConnectionActor as Actor>::Msg>, CreateError> {
+	pub async fn create(sender: Sender<ServerMessage>, principal: Principal) -> Result<ActorRef<<ConnectionActor as Actor>::Msg>, CreateError> {
 		let id = websocket::new_connection_id();
-		crate::actor_sys().await.actor_of_args::<ConnectionActor, _>(&Self::actor_name(id), (id, sender))
+		crate::actor_sys().await.actor_of_args::<ConnectionActor, _>(&Self::actor_name(id), (id, sender, principal))
+	}
+	/// Forward the changes of the subscriptions table readable by the principal
+	/// until the connection is closed
+	///
+	/// Repeated requests keep the running subscription.
+	fn watch_subscriptions(&mut self) {
+		if self.watching_subscriptions.is_some() {
+			return;
+		}
+		let mut sender = self.sender.clone();
+		let mut changes = <PriceSubscription as DatabaseTable<'_, Schema>>::watch_as(&self.principal);
+		self.watching_subscriptions = Some(async_std::task::spawn(async move {
+			while let Some(change) = changes.next().await {
+				let msg = ServerMessage::Subscriptions(Response::Changed(change));
+				if sender.send(msg).await.is_err() {
+					break;
+				}
+			}
+		}));
 	}
 }
 impl Actor for ConnectionActor {
@@ -60,6 +96,9 @@ impl Actor for ConnectionActor {
 	}
 	fn post_stop(&mut self) {
 		debug!("Stopped connection actor");
+		if let Some(task) = self.watching_subscriptions.take() {
+			async_std::task::spawn(task.cancel());
+		}
         //if let Some(actor) = self.subscriptions.clone() {
 		//    ctx.run(async move {
 		//	    crate::actor_sys().await.stop(actor);
@@ -95,6 +134,7 @@ impl Receive<ClientMessage> for ConnectionActor {
 	fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: ClientMessage, sender: RkSender) {
 		trace!("ClientMessage in ConnectionActor");
 		match msg {
+			ClientMessage::Subscriptions(Request::WatchPriceSubscriptions) => self.watch_subscriptions(),
 			ClientMessage::Subscriptions(req) => if let Some(actor) = &self.subscriptions {
 				actor.tell(req, sender);
 			} else {
@@ -110,13 +150,15 @@ impl Receive<ServerMessage> for ConnectionActor {
 		self.sender.try_send(msg).unwrap()
 	}
 }
-impl ActorFactoryArgs<(usize, Sender<ServerMessage>)> for ConnectionActor {
-	fn create_args((id, sender): (usize, Sender<ServerMessage>)) -> Self {
+impl ActorFactoryArgs<(usize, Sender<ServerMessage>, Principal)> for ConnectionActor {
+	fn create_args((id, sender, principal): (usize, Sender<ServerMessage>, Principal)) -> Self {
 		debug!("Creating ConnectionActor");
 		Self {
 			id,
 			sender,
+			principal,
 			subscriptions: None,
+			watching_subscriptions: None,
 		}
 	}
 }
@@ -1,3 +1,5 @@
+use app_model::encoding::Encoding;
+use database_table::Principal;
 use shared::{
 	ClientMessage,
 	ServerMessage,
@@ -38,17 +40,46 @@ use tracing::{
 	trace,
 	warn,
 };
+/// Message sent over a websocket, in the encoding of the connection
+///
+/// JSON is sent as text messages, MessagePack as binary messages.
 #[derive(Debug)]
-pub struct WebsocketPacket(Vec<u8>);
-impl From<ServerMessage> for WebsocketPacket {
-	fn from(msg: ServerMessage) -> Self {
-		Self(serde_json::to_vec(&msg).expect("Failed to serialize ServerMessage to Vec<u8>."))
+pub struct WebsocketPacket {
+	data: Vec<u8>,
+	encoding: Encoding,
+}
+impl WebsocketPacket {
+	pub fn encode(msg: &ServerMessage, encoding: Encoding) -> Self {
+		Self {
+			data: encoding.encode(msg).expect("Failed to serialize ServerMessage to Vec<u8>."),
+			encoding,
+		}
+	}
+}
+impl From<Message> for WebsocketPacket {
+	fn from(msg: Message) -> Self {
+		let encoding = match msg {
+			Message::Binary(_) => Encoding::MessagePack,
+			_ => Encoding::Json,
+		};
+		Self {
+			data: msg.into_data(),
+			encoding,
+		}
+	}
+}
+impl From<WebsocketPacket> for Message {
+	fn from(packet: WebsocketPacket) -> Self {
+		match packet.encoding {
+			Encoding::Json => Message::Text(String::from_utf8(packet.data).expect("JSON is not UTF-8.")),
+			Encoding::MessagePack => Message::Binary(packet.data),
+		}
 	}
 }
 impl TryInto<ClientMessage> for WebsocketPacket {
-	type Error = serde_json::Error;
+	type Error = String;
 	fn try_into(self) -> Result<ClientMessage, Self::Error> {
-		serde_json::from_slice(&self.0)
+		self.encoding.decode(&self.data)
 	}
 }
 pub async fn poll_messages<E, Rx>(connection: ActorRef<<ConnectionActor as Actor>::Msg>, mut rx: Rx)
@@ -80,23 +111,24 @@ pub async fn poll_messages<E, Rx>(connection: ActorRef<<ConnectionActor as Actor
 		}
 	}
 }
-pub async fn send_messages<Tx>(receiver: Receiver<ServerMessage>, tx: Tx) -> Result<(), String>
+pub async fn send_messages<Tx>(receiver: Receiver<ServerMessage>, tx: Tx, encoding: Encoding) -> Result<(), String>
 	where Tx: Sink<WebsocketPacket> + Send + 'static,
 		  <Tx as Sink<WebsocketPacket>>::Error: ToString,
 {
 	receiver
-		.map(|msg: ServerMessage|
-			Ok(WebsocketPacket::from(msg))
+		.map(move |msg: ServerMessage|
+			Ok(WebsocketPacket::encode(&msg, encoding))
 		)
 		// send messages through websocket sink
 		.forward(tx.sink_map_err(|e| e.to_string()))
 		.await
 }
-pub async fn connection(ws: WebSocketConnection) {
+/// Serve a websocket connection of principal, sending ServerMessages in encoding
+pub async fn connection(ws: WebSocketConnection, encoding: Encoding, principal: Principal) {
 	let (sink, stream) = ws.split();
-	let rx = stream.map(|msg| msg.map(|msg| WebsocketPacket(msg.into_data())));
+	let rx = stream.map(|msg| msg.map(WebsocketPacket::from));
 	let tx = sink.with(async move |msg: WebsocketPacket| {
-		Ok(Message::from(msg.0)) as Result<_, tide_websockets::Error>
+		Ok(Message::from(msg)) as Result<_, tide_websockets::Error>
 	});
 	// connection lasts for the duration of this async fn
 	debug!("Starting websocket connection");
@@ -104,13 +136,13 @@ pub async fn connection(ws: WebSocketConnection) {
 	let (sender, receiver) = channel(CHANNEL_BUFFER_SIZE);
 
 	// create a connection actor with a ServerMessage sender
-	let connection = ConnectionActor::create(sender).await.unwrap();
+	let connection = ConnectionActor::create(sender, principal).await.unwrap();
 	let connection2 = connection.clone();
 	// spawn listener for websocket stream
 	let ws_listener = async_std::task::spawn(async move {
 		poll_messages(connection2, rx).await
 	});
-	send_messages(receiver, tx).await
+	send_messages(receiver, tx, encoding).await
 		.expect("Failed to forward connection messages to websocket!");
 	//// wait for ServerMessages from connection actor
 	ws_listener.await;
@@ -1,5 +1,6 @@
 use app_model::market::PriceHistory;
 use database_table::{
+    Change,
     Entry,
     Route as DbRoute,
 };
@@ -42,6 +43,8 @@ impl From<Interval> for UpdatePriceSubscriptionRequest {
 pub enum Request {
     GetPriceSubscriptionList,
     AddPriceSubscription(PriceSubscription),
+    /// Receive `Response::Changed` for every change of the subscriptions table
+    WatchPriceSubscriptions,
     Subscription(Id<PriceSubscription>, SubscriptionRequest),
 }
 
@@ -51,6 +54,7 @@ impl Routable for Request {
         match self {
             Request::GetPriceSubscriptionList => Route::List,
             Request::AddPriceSubscription(_) => Route::List,
+            Request::WatchPriceSubscriptions => Route::List,
             Request::Subscription(id, _) => Route::Entry(id.clone()),
         }
     }
@@ -68,4 +72,5 @@ pub enum Response {
     SubscriptionAdded(Id<PriceSubscription>),
     SubscriptionNotFound(Id<PriceSubscription>),
     SubscriptionUpdated,
+    Changed(Change<PriceSubscription>),
 }
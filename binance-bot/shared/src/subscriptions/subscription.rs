@@ -10,6 +10,7 @@ use {
         Route as CrateRoute,
     },
     database_table::{
+        Indexed,
         TableRoutable,
         Routed,
     },
@@ -28,8 +29,12 @@ use {
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct PriceSubscription {
     pub market_pair: String,
+    /// Subject of the user who created the subscription, None for those of the server
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 #[cfg(target_arch = "wasm32")]
 #[derive(Debug, Clone)]
@@ -69,6 +74,7 @@ impl TableRoutable for PriceSubscription {
         Route::Entry(id)
     }
 }
+impl Indexed for PriceSubscription {}
 impl Routed for PriceSubscription {
     type AbsoluteRoute = CrateRoute;
     fn to_absolute_route(route: <Self as TableRoutable>::Route) -> Self::AbsoluteRoute {
@@ -78,6 +84,9 @@ impl Routed for PriceSubscription {
 
 impl From<String> for PriceSubscription {
     fn from(market_pair: String) -> Self {
-        Self { market_pair }
+        Self {
+            market_pair,
+            owner: None,
+        }
     }
 }
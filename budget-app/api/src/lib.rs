@@ -12,39 +12,77 @@ mod server;
 pub use server::*;
 
 use app_model::{
+    auth::credentials::Credentials,
     project::Project,
     task::Task,
     user::User,
 };
 use database_table::*;
 use define_api::api;
-use futures::future::FutureExt;
+use futures::{
+    future::FutureExt,
+    Stream,
+    StreamExt,
+};
 use interpreter::*;
 use rql::*;
 use seqraph::*;
 use updatable::*;
 
 api! {
-    fn get_project_tasks(id: Id<Project>) -> Vec<Entry<Task>> {
-        let ids = <Project as DatabaseTable<'_, Schema>>::get(id)
+    /// Log in with credentials, the token of the session authenticates the other calls
+    #[public]
+    fn login(credentials: Credentials) -> Result<UserSession, LoginError> {
+        let entry = <User as DatabaseTable<'_, Schema>>::find_by_index("name", &credentials.username)
+            .map_err(|e| LoginError::Internal(e.to_string()))?
+            .ok_or(LoginError::UnknownUser)?;
+        if *entry.data().password() != credentials.password {
+            return Err(LoginError::WrongPassword);
+        }
+        let token = JWT::try_from(entry.data())
+            .map_err(|e| LoginError::Internal(e.to_string()))?;
+        Ok(UserSession {
+            user_id: *entry.id(),
+            token: token.to_string(),
+        })
+    }
+    /// Tasks of project, if the caller is a member
+    fn get_project_tasks(principal: Principal, id: Id<Project>) -> Result<Vec<Entry<Task>>, database_table::Error> {
+        let ids = <Project as DatabaseTable<'_, Schema>>::get_as(&principal, id)?
             .map(|entry| entry.data().tasks().clone())
             .unwrap_or(Vec::new());
-        <Task as DatabaseTable<'_, Schema>>::get_list(ids)
+        <Task as DatabaseTable<'_, Schema>>::get_list_as(&principal, ids)
     }
-    fn get_user_projects(id: Id<User>) -> Vec<Entry<Project>> {
-        <Project as DatabaseTable<'_, Schema>>::filter(|project| project.members().contains(&id))
+    /// Projects of the calling user
+    fn get_user_projects(claims: JWTClaims) -> Result<Vec<Entry<Project>>, database_table::Error> {
+        let user = <User as DatabaseTable<'_, Schema>>::find_by_index("name", claims.subject())?
+            .ok_or_else(|| database_table::Error::NotFound(claims.subject().clone()))?;
+        <Project as DatabaseTable<'_, Schema>>::filter_by_index("members", user.id)
+    }
+    /// Create task in project, if the caller may write both
+    fn project_create_subtask(principal: Principal, project: Id<Project>, task: Task) -> Result<Id<Task>, database_table::Error> {
+        <Schema as Database<'_, Task>>::transaction(|tx| {
+            let id = tx.insert_as(&principal, task)?;
+            tx.update_as(&principal, project, |project: &mut Project| project.add_task(id))?
+                .ok_or_else(|| database_table::Error::NotFound(project.to_string()))?;
+            Ok(id)
+        })
     }
-    //fn project_create_subtask(project: Id<Project>, task: Task) -> Id<Task> {
-    //	let id = <Task as DatabaseTable>::insert(task);
-    //	<Project as DatabaseTable>::update(project, Project::update().tasks(vec![id.clone()]));
-    //	id
-    //}
     fn interpret_text(text: String) -> String {
         let mut g = TG.lock().unwrap();
         g.read_sequence(text.chars());
         g.write_to_file("graphs/g1").unwrap();
         "Done".into()
     }
+    /// Read text into the graph line by line, streaming the number of read lines
+    fn interpret_lines(text: String) -> impl Stream<Item = usize> {
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        futures::stream::iter(lines.into_iter().enumerate())
+            .map(|(i, line)| {
+                TG.lock().unwrap().read_sequence(line.chars());
+                i + 1
+            })
+    }
     fn query_text(query: String) -> Option<NodeInfo<char>> {
         TG.lock().unwrap().query(query.chars())
     }
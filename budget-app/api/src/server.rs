@@ -5,10 +5,14 @@ use app_model::{
     },
     project::Project,
     task::Task,
-    user::User,
+    user::{
+        self,
+        User,
+    },
     UserSession,
 };
 use database_table::*;
+use define_api::database_table;
 use lazy_static::lazy_static;
 use rocket::{
     http::*,
@@ -22,83 +26,224 @@ use seqraph::*;
 use std::convert::TryFrom;
 use std::sync::Mutex;
 
-schema! {
+database_table! {
     pub Schema {
         user: User,
         task: Task,
         project: Project,
     }
+    name = "test_database",
+    env = "BUDGET_APP_DATABASE",
+    snapshot_env = "BUDGET_APP",
+    migrations = migrations,
 }
 lazy_static! {
     pub static ref TG: Mutex<SequenceGraph<char>> = Mutex::new(SequenceGraph::new());
-    pub static ref DB: Schema = Schema::new("test_database", rql::BinaryStable).unwrap();
 }
-impl<'db> Database<'db, User> for Schema {
-    fn table() -> TableGuard<'db, User> {
-        DB.user()
+/// Migrations of stored rows, register one for each change of a table type
+fn migrations() -> Migrations {
+    Migrations::new()
+}
+impl<'db> Referenced<'db, Schema> for User {
+    fn references() -> Vec<Reference<'db, Schema, Self>> {
+        vec![
+            Reference::nullify("project.members", |project: &Project| project.members().clone(), Project::remove_member),
+            Reference::nullify("task.assignees", |task: &Task| task.assignees().clone(), Task::remove_assignee),
+        ]
+    }
+}
+impl<'db> Referenced<'db, Schema> for Project {}
+impl<'db> Referenced<'db, Schema> for Task {
+    fn references() -> Vec<Reference<'db, Schema, Self>> {
+        vec![
+            Reference::nullify("project.tasks", |project: &Project| project.tasks().clone(), Project::remove_task),
+            Reference::nullify("task.subtasks", |task: &Task| task.subtasks().clone(), Task::remove_subtask),
+        ]
+    }
+}
+/// Id of the user principal is authenticated as
+fn principal_user(principal: &Principal) -> Option<Id<User>> {
+    principal.subject()
+        .and_then(|name| <User as DatabaseTable<'_, Schema>>::find_by_index("name", name).ok().flatten())
+        .map(|entry| *entry.id())
+}
+/// Whether principal is one of users, never if there are none
+fn is_one_of(principal: &Principal, users: &[Id<User>]) -> bool {
+    !users.is_empty()
+        && principal_user(principal)
+            .map(|id| users.contains(&id))
+            .unwrap_or(false)
+}
+impl Authorized<Schema> for User {
+    fn can_write(principal: &Principal, old: Option<&Entry<Self>>, data: &Self) -> bool {
+        user::policy::can_write(principal, old, data)
+    }
+    fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+        user::policy::can_delete(principal, entry)
     }
-    fn table_mut() -> TableGuardMut<'db, User> {
-        DB.user_mut()
+    fn redact(&mut self, _principal: &Principal) {
+        user::policy::redact(self)
     }
 }
-impl<'db> Database<'db, Project> for Schema {
-    fn table() -> TableGuard<'db, Project> {
-        DB.project()
+impl Authorized<Schema> for Project {
+    fn can_read(principal: &Principal, entry: &Entry<Self>) -> bool {
+        is_one_of(principal, entry.data().members())
     }
-    fn table_mut() -> TableGuardMut<'db, Project> {
-        DB.project_mut()
+    /// Members may change a project, new projects must include their creator
+    fn can_write(principal: &Principal, old: Option<&Entry<Self>>, data: &Self) -> bool {
+        is_one_of(principal, old.map(|old| old.data()).unwrap_or(data).members())
+    }
+    fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+        is_one_of(principal, entry.data().members())
     }
 }
-impl<'db> Database<'db, Task> for Schema {
-    fn table() -> TableGuard<'db, Task> {
-        DB.task()
+impl Authorized<Schema> for Task {
+    /// Assignees may change a task, new tasks must be assigned to their creator
+    fn can_write(principal: &Principal, old: Option<&Entry<Self>>, data: &Self) -> bool {
+        is_one_of(principal, old.map(|old| old.data()).unwrap_or(data).assignees())
     }
-    fn table_mut() -> TableGuardMut<'db, Task> {
-        DB.task_mut()
+    fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+        is_one_of(principal, entry.data().assignees())
     }
 }
 
-#[post("/api/auth/login", data = "<credentials>")]
-pub fn login(credentials: Json<Credentials>) -> std::result::Result<Json<UserSession>, Status> {
-    let credentials = credentials.into_inner();
-    <User as DatabaseTable<'_, Schema>>::find(|user| *user.name() == credentials.username)
-        .ok_or(Status::NotFound)
-        .and_then(|entry| {
-            let user = entry.data();
-            if *user.password() == credentials.password {
-                Ok(entry)
-            } else {
-                Err(Status::Unauthorized)
-            }
-        })
-        .and_then(|entry| {
-            let user = entry.data().clone();
-            let id = entry.id().clone();
-            JWT::try_from(&user)
-                .map_err(|_| Status::InternalServerError)
-                .map(move |jwt| (id, jwt))
-        })
-        .map(|(id, jwt)| {
+#[post("/api/auth/register", data = "<user>")]
+pub fn register(user: Json<User>) -> std::result::Result<Json<UserSession>, Status> {
+    let mut user = user.into_inner();
+    user.roles_mut().clear();
+    let id = <User as DatabaseTable<'_, Schema>>::insert(user.clone())
+        .map_err(|e| Status::from_code(e.status()).unwrap_or(Status::InternalServerError))?;
+    JWT::try_from(&user)
+        .map_err(|_| Status::InternalServerError)
+        .map(move |jwt| {
             Json(UserSession {
                 user_id: id.clone(),
                 token: jwt.to_string(),
             })
         })
 }
-#[post("/api/auth/register", data = "<user>")]
-pub fn register(user: Json<User>) -> std::result::Result<Json<UserSession>, Status> {
-    let user = user.into_inner();
-    if <User as DatabaseTable<'_, Schema>>::find(|u| u.name() == user.name()).is_none() {
-        let id = <User as DatabaseTable<'_, Schema>>::insert(user.clone());
-        JWT::try_from(&user)
-            .map_err(|_| Status::InternalServerError)
-            .map(move |jwt| {
-                Json(UserSession {
-                    user_id: id.clone(),
-                    token: jwt.to_string(),
-                })
-            })
-    } else {
-        Err(Status::Conflict)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use app_model::{
+        auth::LoginError,
+        ApiClient,
+        ApiError,
+    };
+    use std::{
+        net::{
+            TcpListener,
+            TcpStream,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+    lazy_static! {
+        /// Origin of the server shared by the tests
+        static ref ORIGIN: String = launch();
+        /// Held while a test uses the global `ApiClient` of the api functions
+        static ref GLOBAL_CLIENT: futures::lock::Mutex<()> = futures::lock::Mutex::new(());
+    }
+    /// Launch the server on a free port with an in-memory database, once it accepts connections
+    fn launch() -> String {
+        std::env::set_var(DATABASE_ENV, "sqlite::memory:");
+        open().unwrap();
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = rocket::Config::build(rocket::config::Environment::Development)
+            .address("127.0.0.1")
+            .port(port)
+            .finalize()
+            .unwrap();
+        std::thread::spawn(move || {
+            rocket::custom(config)
+                .mount("/", rocket::routes![
+                    register,
+                    crate::handlers::login,
+                    crate::handlers::get_project_tasks,
+                    crate::handlers::batch,
+                ])
+                .register(rocket::catchers![crate::handlers::outdated])
+                .launch();
+        });
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "server did not start");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        format!("http://127.0.0.1:{}", port)
+    }
+    /// Client of the server
+    fn client() -> ApiClient {
+        ApiClient::new().base_url(&*ORIGIN)
+    }
+    /// Client with the session of a newly registered user
+    async fn registered(name: &str) -> (ApiClient, Credentials) {
+        let credentials = Credentials::new(name, "password");
+        let session = client().register(&User::from(credentials.clone())).await.unwrap();
+        (client().session(&session), credentials)
+    }
+    #[tokio::test]
+    async fn login() {
+        let (_, credentials) = registered("login_user").await;
+        let _global = GLOBAL_CLIENT.lock().await;
+        client().set();
+        let wrong = Credentials::new(&credentials.username, "wrong");
+        match crate::login(wrong).await {
+            Err(ApiError::Domain(e)) => assert_eq!(e, LoginError::WrongPassword),
+            _ => panic!("expected a wrong password"),
+        }
+        let session = crate::login(credentials).await.unwrap();
+        client().session(&session).set();
+        let tasks = crate::get_project_tasks(Id::new()).await.unwrap();
+        assert_eq!(tasks.len(), 0);
+    }
+    #[tokio::test]
+    async fn project_members() {
+        let (client, _) = registered("member_user").await;
+        let project = <Project as DatabaseTable<'_, Schema>>::insert(Project::new("other".into())).unwrap();
+        let _global = GLOBAL_CLIENT.lock().await;
+        client.set();
+        match crate::get_project_tasks(project).await {
+            Err(ApiError::Domain(e)) => assert_eq!(e, database_table::Error::Unauthorized(Access::Read)),
+            _ => panic!("expected a denial"),
+        }
+    }
+    #[tokio::test]
+    async fn batch() {
+        let (client, _) = registered("batch_user").await;
+        let _global = GLOBAL_CLIENT.lock().await;
+        client.set();
+        // calls of the same tick are sent in one batch request
+        let batch = crate::ApiBatch::new();
+        let (tasks, more) = futures::join!(
+            batch.get_project_tasks(Id::new()),
+            batch.get_project_tasks(Id::new()),
+        );
+        assert_eq!(tasks.unwrap().len() + more.unwrap().len(), 0);
+    }
+    #[tokio::test]
+    async fn unauthenticated() {
+        let _global = GLOBAL_CLIENT.lock().await;
+        client().set();
+        match crate::get_project_tasks(Id::new()).await {
+            Err(ApiError::Status(status)) => assert_eq!(status, 401),
+            _ => panic!("expected an unauthorized status"),
+        }
+    }
+    #[tokio::test]
+    async fn outdated() {
+        // clients built with a different api are told to reload
+        let response = client().post("/api/v1/call/get_project_tasks")
+            .header(app_model::api_schema::HEADER, "outdated")
+            .json(&serde_json::json!({ "id": Id::<User>::new() }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), app_model::api_schema::OUTDATED);
+        assert_eq!(response.text().await.unwrap(), app_model::api_schema::OUTDATED_MESSAGE);
     }
 }
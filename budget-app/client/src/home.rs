@@ -1,3 +1,4 @@
+use app_model::ApiError;
 use components::{
 	Component,
 	Viewable,
@@ -10,7 +11,10 @@ use seqraph::{
 	mapping::Sequenced,
 	NodeInfo,
 };
-use std::result::Result;
+use std::{
+	convert::Infallible,
+	result::Result,
+};
 
 #[derive(Debug, Clone, Default)]
 pub struct Model {
@@ -38,7 +42,7 @@ impl Component for Model {
 			Msg::InterpretText => {
 				self.interpreter_response.clear();
 				orders.perform_cmd(api::interpret_text(self.text.clone()).map(
-					|result: Result<String, FetchError>| {
+					|result: Result<String, ApiError<Infallible>>| {
 						Msg::InterpreterResponse(result.map_err(|e| format!("{:?}", e)))
 					},
 				));
@@ -61,7 +65,7 @@ impl Component for Model {
 			Msg::Query => {
 				self.query_response = None;
 				orders.perform_cmd(api::query_text(self.query.clone()).map(
-					|result: Result<_, FetchError>| {
+					|result: Result<_, ApiError<Infallible>>| {
 						Msg::QueryResponse(result.map_err(|e| format!("{:?}", e)))
 					},
 				));
@@ -27,6 +27,10 @@ use seed::{
 #[wasm_bindgen(start)]
 pub fn render() {
 	std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+	if let Some(prefix) = option_env!("BUDGET_APP_API_PREFIX") {
+		app_model::ApiClient::new().prefix(prefix).set();
+	}
+	auth::set_login(|credentials| api::login(credentials).boxed_local());
 	App::start(
 		"app",
 		|url, orders| Model::init(url, orders),
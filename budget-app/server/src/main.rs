@@ -9,6 +9,7 @@ use rocket::{
 	get,
 };
 use define_api::rest_handlers;
+use database_table::SnapshotSchedule;
 use anyhow::anyhow;
 use std::io::Result;
 use std::path::Path;
@@ -96,6 +97,28 @@ fn token_valid(token: JWT) {
 	let _ = token;
 }
 fn main() {
+	// the client is built with the hash, see its `Makefile.toml`
+	if std::env::args().any(|arg| arg == "--api-schema-hash") {
+		println!("{}", api::api_schema_hash());
+		return;
+	}
+	if let Some(code) = api::run_command(std::env::args()) {
+		std::process::exit(code);
+	}
+	if let Err(e) = api::open() {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	}
+	match SnapshotSchedule::from_env(api::SNAPSHOT_ENV) {
+		Ok(Some(schedule)) => {
+			api::snapshot().schedule(schedule);
+		}
+		Ok(None) => {}
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	}
 	rocket::custom(
 		rocket::Config::build(rocket::config::Environment::Staging)
 			.address("0.0.0.0")
@@ -105,30 +128,29 @@ fn main() {
 	)
 	.mount(
 		"/",
-		vec![
-			routes![
-				get_root_html,
-				get_html,
-				user_page,
-				project_page,
-				task_page,
-				token_valid,
-				get_style_css,
-				get_pkg_js,
-				get_img_file,
-				api::login,
-				api::register,
-				api::handlers::get_user_projects,
-				api::handlers::get_project_tasks,
-				//api::handlers::project_create_subtask,
-				api::handlers::interpret_text,
-				api::handlers::query_text,
-			],
-			rest_handlers!(Task),
-			rest_handlers!(Project),
-			rest_handlers!(User),
-		]
-		.concat(),
+		routes![
+			get_root_html,
+			get_html,
+			user_page,
+			project_page,
+			task_page,
+			token_valid,
+			get_style_css,
+			get_pkg_js,
+			get_img_file,
+			api::register,
+			api::handlers::login,
+			api::handlers::get_user_projects,
+			api::handlers::get_project_tasks,
+			//api::handlers::project_create_subtask,
+			api::handlers::interpret_text,
+			api::handlers::interpret_lines,
+			api::handlers::query_text,
+			api::handlers::batch,
+			api::handlers::openapi,
+		],
 	)
+	.mount("/", api::schema_tables!(rest_handlers).concat())
+	.register(rocket::catchers![api::handlers::outdated])
 	.launch();
 }
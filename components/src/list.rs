@@ -5,51 +5,115 @@ use crate::{
     Viewable,
 };
 use database_table::{
+    Change,
     Entry,
+    ListQuery,
+    Page,
     RemoteTable,
 };
+use rql::Id;
 use seed::{
     prelude::*,
     *,
 };
+use futures::stream::StreamExt;
 use std::fmt::Debug;
 use std::default::Default;
+use std::rc::Rc;
 use std::result::Result;
 
-#[derive(Debug, Clone, Default)]
+/// Number of items requested per page by default
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[derive(Debug, Clone)]
 pub struct List<D: RemoteTable, T: Component + RemoteTable<D> = D> {
-    items: Vec<T>,
+    items: Vec<(Id<D>, T)>,
+    query: ListQuery,
+    total: usize,
+    /// Subscription to the changes of the table, closed with the last clone
+    changes: Option<Rc<StreamHandle>>,
     _ty: std::marker::PhantomData<D>,
 }
 impl<D: RemoteTable, T: Component + RemoteTable<D>> List<D, T> {
     pub fn new() -> Self {
+        Self::with_query(ListQuery::page(0, DEFAULT_PAGE_SIZE))
+    }
+    pub fn with_query(query: ListQuery) -> Self {
         Self {
             items: Vec::new(),
+            query,
+            total: 0,
+            changes: None,
             _ty: Default::default(),
         }
     }
+    fn offset(&self) -> usize {
+        self.query.offset.unwrap_or(0)
+    }
+    fn has_prev_page(&self) -> bool {
+        self.offset() > 0
+    }
+    fn has_next_page(&self) -> bool {
+        self.query.limit
+            .map(|limit| self.offset() + limit < self.total)
+            .unwrap_or(false)
+    }
+    /// Apply a change to the current page, or return true if the page has to
+    /// be fetched again
+    ///
+    /// Only updates of a list without filters and sorting keep every row in its
+    /// place, other changes can move rows into, out of or across the page.
+    fn apply_change(&mut self, change: Change<D>) -> bool {
+        match change {
+            Change::Updated(entry) if self.query.filters.is_empty() && self.query.sort_by.is_none() => {
+                if let Some(item) = self.items.iter_mut().find(|(id, _)| id == entry.id()) {
+                    *item = into_item(entry);
+                }
+                false
+            }
+            _ => true,
+        }
+    }
+}
+impl<D: RemoteTable, T: Component + RemoteTable<D>> Default for List<D, T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl<D: RemoteTable, T: Component + RemoteTable<D>> Init<Msg<D, T>> for List<D, T> {
     fn init(msg: Msg<D, T>, orders: &mut impl Orders<Msg<D, T>>) -> Self {
         orders.send_msg(msg);
+        orders.send_msg(Msg::Watch);
         Self::new()
     }
 }
 impl<D: RemoteTable, T: Component + RemoteTable<D>> From<Vec<Entry<D>>> for List<D, T> {
     fn from(entries: Vec<Entry<D>>) -> Self {
         Self {
+            total: entries.len(),
             items: into_items(entries),
+            query: ListQuery::default(),
+            changes: None,
             _ty: Default::default(),
         }
     }
 }
-fn into_items<D: RemoteTable, T: Component + RemoteTable<D>>(entries: Vec<Entry<D>>) -> Vec<T> {
-    entries.into_iter().map(|e| e.into_inner().into()).collect()
+fn into_item<D: RemoteTable, T: Component + RemoteTable<D>>(entry: Entry<D>) -> (Id<D>, T) {
+    (entry.id, entry.into_inner().into())
+}
+fn into_items<D: RemoteTable, T: Component + RemoteTable<D>>(entries: Vec<Entry<D>>) -> Vec<(Id<D>, T)> {
+    entries.into_iter().map(into_item).collect()
 }
 #[derive(Debug, Clone)]
 pub enum Msg<D: RemoteTable, T: Component + RemoteTable<D> = D> {
     GetAll,
-    All(Result<Vec<Entry<D>>, <T as RemoteTable<D>>::Error>),
+    All(Result<Page<D>, <T as RemoteTable<D>>::Error>),
+    SetQuery(ListQuery),
+    NextPage,
+    PrevPage,
+    /// Apply changes pushed by the server
+    Watch,
+    Changed(Change<D>),
     Item(usize, <T as Component>::Msg),
 }
 impl<D: RemoteTable, T: Component + RemoteTable<D>> Component for List<D, T> {
@@ -58,22 +122,56 @@ impl<D: RemoteTable, T: Component + RemoteTable<D>> Component for List<D, T> {
         match msg {
             Msg::GetAll => {
                 orders.perform_cmd(
-                    T::get_all().map(Msg::<D, T>::All)
+                    T::get_all(self.query.clone()).map(Msg::<D, T>::All)
                     );
             }
             Msg::All(res) => {
                 match res {
-                    Ok(entries) => self.items = into_items(entries),
+                    Ok(page) => {
+                        self.total = page.total;
+                        self.items = into_items(page.items);
+                    }
+                    Err(e) => {
+                        seed::log(e);
+                    }
+                }
+            }
+            Msg::SetQuery(query) => {
+                self.query = query;
+                orders.send_msg(Msg::GetAll);
+            }
+            Msg::NextPage => {
+                if let (true, Some(limit)) = (self.has_next_page(), self.query.limit) {
+                    self.query.offset = Some(self.offset() + limit);
+                    orders.send_msg(Msg::GetAll);
+                }
+            }
+            Msg::PrevPage => {
+                if let (true, Some(limit)) = (self.has_prev_page(), self.query.limit) {
+                    self.query.offset = Some(self.offset().saturating_sub(limit));
+                    orders.send_msg(Msg::GetAll);
+                }
+            }
+            Msg::Watch => {
+                match T::watch() {
+                    Ok(changes) => {
+                        self.changes = Some(Rc::new(orders.stream_with_handle(changes.map(Msg::<D, T>::Changed))));
+                    }
                     Err(e) => {
                         seed::log(e);
                     }
                 }
             }
+            Msg::Changed(change) => {
+                if self.apply_change(change) {
+                    orders.send_msg(Msg::GetAll);
+                }
+            }
             Msg::Item(index, msg) => {
                 //if let preview::Msg::Entry(entry::Msg::Deleted(_)) = msg {
                 //	self.previews.remove(index);
                 //} else {
-                self.items[index].update(
+                self.items[index].1.update(
                     msg,
                     &mut orders.proxy(move |msg| Msg::Item(index.clone(), msg)),
                     );
@@ -84,9 +182,21 @@ impl<D: RemoteTable, T: Component + RemoteTable<D>> Component for List<D, T> {
 }
 impl<D: RemoteTable, T: Component + preview::Previewable + RemoteTable<D>> Viewable for List<D, T> {
     fn view(&self) -> Node<Msg<D, T>> {
-        div![ul![self.items.iter().enumerate().map(|(i, item)| {
-            li![item.preview()
-                .map_msg(move |msg| Msg::Item(i.clone(), msg))]
-        })]]
+        div![
+            ul![self.items.iter().enumerate().map(|(i, (_, item))| {
+                li![item.preview()
+                    .map_msg(move |msg| Msg::Item(i.clone(), msg))]
+            })],
+            if self.has_prev_page() {
+                button![ev(Ev::Click, |_| Msg::<D, T>::PrevPage), "Previous"]
+            } else {
+                empty![]
+            },
+            if self.has_next_page() {
+                button![ev(Ev::Click, |_| Msg::<D, T>::NextPage), "Next"]
+            } else {
+                empty![]
+            },
+        ]
     }
 }
@@ -9,6 +9,7 @@ use crate::{
     Viewable,
 };
 use database_table::{
+    Change,
     Entry,
     Routable,
     RemoteTable,
@@ -18,28 +19,59 @@ use seed::{
     prelude::*,
     *,
 };
-use std::fmt::Debug;
+use futures::stream::StreamExt;
+use std::{
+    fmt::Debug,
+    rc::Rc,
+};
 
 #[derive(Debug, Clone)]
-pub enum Remote<T: RemoteTable> {
+pub enum State<T> {
     Loading(Id<T>),
     Ready(Entry<T>),
 }
+#[derive(Debug, Clone)]
+pub struct Remote<T: RemoteTable> {
+    state: State<T>,
+    /// Subscription to the changes of the table, closed with the last clone
+    changes: Option<Rc<StreamHandle>>,
+}
 impl<T: RemoteTable + Component + Debug> Init<Id<T>> for Remote<T> {
     fn init(id: Id<T>, orders: &mut impl Orders<Msg<T>>) -> Self {
         orders.send_msg(Msg::Get);
-        Self::Loading(id)
+        orders.send_msg(Msg::Watch);
+        Self {
+            state: State::Loading(id),
+            changes: None,
+        }
+    }
+}
+impl<T: RemoteTable> Remote<T> {
+    pub fn id(&self) -> Id<T> {
+        match &self.state {
+            State::Ready(entry) => *entry.id(),
+            State::Loading(id) => *id,
+        }
+    }
+    pub fn state(&self) -> &State<T> {
+        &self.state
     }
 }
 impl<T: RemoteTable + Component> From<Entry<T>> for Remote<T> {
     fn from(entry: Entry<T>) -> Self {
-        Self::Ready(entry)
+        Self {
+            state: State::Ready(entry),
+            changes: None,
+        }
     }
 }
 #[derive(Debug, Clone)]
 pub enum Msg<T: Component + RemoteTable> {
     Get,
     Got(Option<Entry<T>>),
+    /// Apply changes pushed by the server
+    Watch,
+    Changed(Change<T>),
     Entry(entry::Msg<T>),
 }
 impl<T: Component + RemoteTable> Msg<T> {
@@ -53,8 +85,31 @@ impl<T: Component + RemoteTable> Msg<T> {
 impl<T: RemoteTable + Component + Debug> Component for Remote<T> {
     type Msg = Msg<T>;
     fn update(&mut self, msg: Self::Msg, orders: &mut impl Orders<Self::Msg>) {
-        match self {
-            Self::Loading(id) => {
+        match msg {
+            Msg::Watch => {
+                match T::watch() {
+                    Ok(changes) => {
+                        self.changes = Some(Rc::new(orders.stream_with_handle(changes.map(Msg::Changed))));
+                    }
+                    Err(e) => {
+                        seed::log(e);
+                    }
+                }
+                return;
+            }
+            Msg::Changed(change) => {
+                if change.id() == self.id() {
+                    self.state = match change {
+                        Change::Inserted(entry) | Change::Updated(entry) => State::Ready(entry),
+                        Change::Deleted(id) => State::Loading(id),
+                    };
+                }
+                return;
+            }
+            _ => {}
+        }
+        match &mut self.state {
+            State::Loading(id) => {
                 match msg {
                     Msg::Get => {
                         let id = id.clone();
@@ -66,13 +121,13 @@ impl<T: RemoteTable + Component + Debug> Component for Remote<T> {
                     }
                     Msg::Got(opt) => {
                         if let Some(entry) = opt {
-                            *self = Self::Ready(entry);
+                            self.state = State::Ready(entry);
                         }
                     }
                     _ => {}
                 }
             }
-            Self::Ready(entry) => {
+            State::Ready(entry) => {
                 match msg {
                     Msg::Entry(msg) => {
                         entry.update(msg, &mut orders.proxy(Msg::Entry));
@@ -88,34 +143,34 @@ impl<T: RemoteTable + Component + Debug> Component for Remote<T> {
 }
 impl<T: RemoteTable + Previewable + Debug> Previewable for Remote<T> {
     fn preview(&self) -> Node<Self::Msg> {
-        match self {
-            Self::Ready(entry) => entry.preview().map_msg(Msg::Entry),
-            Self::Loading(_) => div![h1!["Preview"], p!["Loading..."],],
+        match &self.state {
+            State::Ready(entry) => entry.preview().map_msg(Msg::Entry),
+            State::Loading(_) => div![h1!["Preview"], p!["Loading..."],],
         }
     }
 }
 impl<T: RemoteTable + Viewable + Debug> Viewable for Remote<T> {
     fn view(&self) -> Node<Self::Msg> {
-        match self {
-            Self::Ready(entry) => entry.view().map_msg(Msg::Entry),
-            Self::Loading(_) => div![h1!["Viewable"], p!["Loading..."],],
+        match &self.state {
+            State::Ready(entry) => entry.view().map_msg(Msg::Entry),
+            State::Loading(_) => div![h1!["Viewable"], p!["Loading..."],],
         }
     }
 }
 impl<T: RemoteTable + Edit + Debug> Edit for Remote<T> {
     fn edit(&self) -> Node<Self::Msg> {
-        match self {
-            Self::Ready(entry) => entry.edit().map_msg(Msg::Entry),
-            Self::Loading(_) => div![h1!["Editor"], p!["Loading..."],],
+        match &self.state {
+            State::Ready(entry) => entry.edit().map_msg(Msg::Entry),
+            State::Loading(_) => div![h1!["Editor"], p!["Loading..."],],
         }
     }
 }
 impl<T: RemoteTable> Routable for Remote<T> {
     type Route = T::Route;
     fn route(&self) -> Self::Route {
-        match self {
-            Self::Ready(entry) => entry.route(),
-            Self::Loading(id) => id.route(),
+        match &self.state {
+            State::Ready(entry) => entry.route(),
+            State::Loading(id) => id.route(),
         }
     }
 }
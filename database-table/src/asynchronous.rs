@@ -0,0 +1,194 @@
+use crate::{
+    entry::*,
+    policy::*,
+    query::*,
+    Database,
+    DatabaseTable,
+    Error,
+};
+use async_trait::async_trait;
+use blocking::unblock;
+use rql::*;
+
+/// Async access to the tables of a database, for async request handlers
+///
+/// The default methods run the `Database` methods on a blocking thread pool,
+/// so slow storage does not stall the executor. Databases with async storage
+/// can override them.
+#[async_trait]
+pub trait AsyncDatabase<T>: Database<'static, T> + Send + Sync
+    where T: DatabaseTable<'static, Self> + Send + 'static
+{
+    async fn insert(obj: T) -> Result<Id<T>, Error> {
+        unblock(move || <Self as Database<'static, T>>::insert(obj)).await
+    }
+    async fn get(id: Id<T>) -> Result<Option<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::get(id)).await
+    }
+    async fn update<F>(id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where F: FnOnce(&mut T) + Send + 'static
+    {
+        unblock(move || <Self as Database<'static, T>>::update(id, f)).await
+    }
+    async fn delete(id: Id<T>) -> Result<Option<T>, Error> {
+        unblock(move || <Self as Database<'static, T>>::delete(id)).await
+    }
+    async fn get_all() -> Result<Vec<Entry<T>>, Error> {
+        unblock(<Self as Database<'static, T>>::get_all).await
+    }
+    async fn list(query: ListQuery) -> Result<Page<T>, Error> {
+        unblock(move || <Self as Database<'static, T>>::list(query)).await
+    }
+    async fn get_list(ids: Vec<Id<T>>) -> Result<Vec<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::get_list(ids)).await
+    }
+    async fn filter_by_index(index: String, key: String) -> Result<Vec<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::filter_by_index(&index, key)).await
+    }
+    async fn find_by_index(index: String, key: String) -> Result<Option<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::find_by_index(&index, key)).await
+    }
+    async fn search(query: String) -> Result<Vec<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::search(&query)).await
+    }
+    async fn get_as(principal: Principal, id: Id<T>) -> Result<Option<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::get_as(&principal, id)).await
+    }
+    async fn get_list_as(principal: Principal, ids: Vec<Id<T>>) -> Result<Vec<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::get_list_as(&principal, ids)).await
+    }
+    async fn list_as(principal: Principal, query: ListQuery) -> Result<Page<T>, Error> {
+        unblock(move || <Self as Database<'static, T>>::list_as(&principal, query)).await
+    }
+    async fn search_as(principal: Principal, query: String) -> Result<Vec<Entry<T>>, Error> {
+        unblock(move || <Self as Database<'static, T>>::search_as(&principal, &query)).await
+    }
+    async fn insert_as(principal: Principal, obj: T) -> Result<Id<T>, Error> {
+        unblock(move || <Self as Database<'static, T>>::insert_as(&principal, obj)).await
+    }
+    async fn update_as<F>(principal: Principal, id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where F: FnOnce(&mut T) + Send + 'static
+    {
+        unblock(move || <Self as Database<'static, T>>::update_as(&principal, id, f)).await
+    }
+    async fn delete_as(principal: Principal, id: Id<T>) -> Result<Option<T>, Error> {
+        unblock(move || <Self as Database<'static, T>>::delete_as(&principal, id)).await
+    }
+}
+/// Async access to the table of Self in database D, see `AsyncDatabase`
+#[async_trait]
+pub trait AsyncDatabaseTable<D>: DatabaseTable<'static, D> + Send + 'static
+    where D: AsyncDatabase<Self>,
+{
+    async fn insert(obj: Self) -> Result<Id<Self>, Error> {
+        <D as AsyncDatabase<Self>>::insert(obj).await
+    }
+    async fn get(id: Id<Self>) -> Result<Option<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::get(id).await
+    }
+    async fn update<F>(id: Id<Self>, f: F) -> Result<Option<Entry<Self>>, Error>
+        where F: FnOnce(&mut Self) + Send + 'static
+    {
+        <D as AsyncDatabase<Self>>::update(id, f).await
+    }
+    async fn delete(id: Id<Self>) -> Result<Option<Self>, Error> {
+        <D as AsyncDatabase<Self>>::delete(id).await
+    }
+    async fn get_all() -> Result<Vec<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::get_all().await
+    }
+    async fn list(query: ListQuery) -> Result<Page<Self>, Error> {
+        <D as AsyncDatabase<Self>>::list(query).await
+    }
+    async fn get_list(ids: Vec<Id<Self>>) -> Result<Vec<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::get_list(ids).await
+    }
+    async fn filter_by_index(index: String, key: String) -> Result<Vec<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::filter_by_index(index, key).await
+    }
+    async fn find_by_index(index: String, key: String) -> Result<Option<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::find_by_index(index, key).await
+    }
+    async fn search(query: String) -> Result<Vec<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::search(query).await
+    }
+    async fn get_as(principal: Principal, id: Id<Self>) -> Result<Option<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::get_as(principal, id).await
+    }
+    async fn get_list_as(principal: Principal, ids: Vec<Id<Self>>) -> Result<Vec<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::get_list_as(principal, ids).await
+    }
+    async fn list_as(principal: Principal, query: ListQuery) -> Result<Page<Self>, Error> {
+        <D as AsyncDatabase<Self>>::list_as(principal, query).await
+    }
+    async fn search_as(principal: Principal, query: String) -> Result<Vec<Entry<Self>>, Error> {
+        <D as AsyncDatabase<Self>>::search_as(principal, query).await
+    }
+    async fn insert_as(principal: Principal, obj: Self) -> Result<Id<Self>, Error> {
+        <D as AsyncDatabase<Self>>::insert_as(principal, obj).await
+    }
+    async fn update_as<F>(principal: Principal, id: Id<Self>, f: F) -> Result<Option<Entry<Self>>, Error>
+        where F: FnOnce(&mut Self) + Send + 'static
+    {
+        <D as AsyncDatabase<Self>>::update_as(principal, id, f).await
+    }
+    async fn delete_as(principal: Principal, id: Id<Self>) -> Result<Option<Self>, Error> {
+        <D as AsyncDatabase<Self>>::delete_as(principal, id).await
+    }
+}
+impl<T, D> AsyncDatabaseTable<D> for T
+    where D: AsyncDatabase<T>,
+          T: DatabaseTable<'static, D> + Send + 'static,
+{}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::*,
+        relation::*,
+        storage::*,
+    };
+    use futures::executor::block_on;
+    use lazy_static::lazy_static;
+    use serde::{
+        Serialize,
+        Deserialize,
+    };
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+    }
+    impl Indexed for Note {}
+    lazy_static! {
+        static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+        static ref NOTES: SqliteTable<Note> = DB.table("note").unwrap();
+    }
+    struct Schema;
+    impl Database<'static, Note> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Note>, Error> {
+            Ok(&*NOTES)
+        }
+    }
+    impl AsyncDatabase<Note> for Schema {}
+    impl Referenced<'static, Schema> for Note {}
+    impl Authorized<Schema> for Note {}
+    fn note(text: &str) -> Note {
+        Note {
+            text: text.to_string(),
+        }
+    }
+    #[test]
+    fn unblocked() {
+        block_on(async {
+            let id = <Note as AsyncDatabaseTable<Schema>>::insert(note("a")).await.unwrap();
+            <Note as AsyncDatabaseTable<Schema>>::update(id, |n| n.text = "b".into()).await.unwrap();
+            assert_eq!(
+                <Note as AsyncDatabaseTable<Schema>>::get(id).await.unwrap().map(|entry| entry.into_inner()),
+                Some(note("b")),
+            );
+            assert_eq!(<Note as AsyncDatabaseTable<Schema>>::delete(id).await, Ok(Some(note("b"))));
+            assert!(<Note as AsyncDatabaseTable<Schema>>::get_all().await.unwrap().is_empty());
+        })
+    }
+}
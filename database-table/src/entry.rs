@@ -6,7 +6,9 @@ use rql::*;
     serde::Serialize,
     serde::Deserialize,
 )]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
 pub struct Entry<T> {
+    #[cfg_attr(not(target_arch = "wasm32"), schemars(with = "String"))]
     pub id: Id<T>,
     pub data: T,
 }
@@ -0,0 +1,144 @@
+use crate::{
+    index::IndexError,
+    migration::MigrationError,
+    policy::Access,
+    relation::ReferenceError,
+};
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use std::fmt::{
+    self,
+    Display,
+};
+
+/// Error of a table operation, locally or on a remote table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+pub enum Error {
+    /// The addressed index or route does not exist
+    NotFound(String),
+    /// A unique index already contains a key, or the row is still referenced
+    Conflict(String),
+    /// The storage backend or the connection to the server failed
+    Storage(String),
+    /// A row could not be serialized or deserialized
+    Serialization(String),
+    /// The policy of the table denies the access
+    Unauthorized(Access),
+    /// The request has no valid credentials
+    Unauthenticated,
+}
+impl Error {
+    /// HTTP status code of responses failing with this error
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound(_) => 404,
+            Self::Conflict(_) => 409,
+            Self::Storage(_) => 500,
+            Self::Serialization(_) => 500,
+            Self::Unauthorized(_) => 403,
+            Self::Unauthenticated => 401,
+        }
+    }
+    /// Error of a response with an error status and message
+    ///
+    /// The access of a `403 Forbidden` is read from the message, see `Display`.
+    pub fn from_status(status: u16, message: String) -> Self {
+        match status {
+            401 => Self::Unauthenticated,
+            403 => match message.strip_prefix("Not authorized to ")
+                .and_then(|access| access.split(' ').next())
+                .and_then(|access| access.parse().ok())
+            {
+                Some(access) => Self::Unauthorized(access),
+                None => Self::Storage(format!("{} {}", status, message)),
+            },
+            404 => Self::NotFound(message),
+            409 => Self::Conflict(message),
+            _ => Self::Storage(format!("{} {}", status, message)),
+        }
+    }
+}
+impl ErrorStatus for Error {
+    fn status(&self) -> u16 {
+        Error::status(self)
+    }
+}
+/// HTTP status of the responses of api functions failing with an error
+///
+/// The error types of `Result`s returned by `api!` functions implement this,
+/// `500 Internal Server Error` by default.
+pub trait ErrorStatus {
+    fn status(&self) -> u16 {
+        500
+    }
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound(message) => write!(f, "Not found: {}", message),
+            Self::Conflict(message) => write!(f, "{}", message),
+            Self::Storage(message) => write!(f, "Storage error: {}", message),
+            Self::Serialization(message) => write!(f, "Serialization error: {}", message),
+            Self::Unauthorized(access) => write!(f, "Not authorized to {} this row", access),
+            Self::Unauthenticated => write!(f, "Not authenticated"),
+        }
+    }
+}
+impl std::error::Error for Error {}
+impl From<IndexError> for Error {
+    fn from(e: IndexError) -> Self {
+        match e {
+            IndexError::Conflict { .. } => Self::Conflict(e.to_string()),
+            IndexError::UnknownIndex(_) => Self::NotFound(e.to_string()),
+        }
+    }
+}
+impl From<ReferenceError> for Error {
+    fn from(e: ReferenceError) -> Self {
+        Self::Conflict(e.to_string())
+    }
+}
+impl From<MigrationError> for Error {
+    fn from(e: MigrationError) -> Self {
+        Self::Storage(e.to_string())
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serialization(e.to_string())
+    }
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Storage(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn status_round_trip() {
+        for error in &[
+            Error::NotFound("row".into()),
+            Error::Conflict("row".into()),
+            Error::Unauthorized(Access::Write),
+            Error::Unauthorized(Access::Delete),
+            Error::Unauthenticated,
+        ] {
+            let message = match error {
+                Error::NotFound(message) | Error::Conflict(message) => message.clone(),
+                _ => error.to_string(),
+            };
+            assert_eq!(&Error::from_status(error.status(), message), error);
+        }
+        assert_eq!(
+            Error::from_status(403, "Forbidden".into()),
+            Error::Storage("403 Forbidden".into())
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use rql::*;
+use crate::{
+    search::SearchField,
+    Database,
+    DatabaseTable,
+    Error,
+};
+use lazy_static::lazy_static;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use std::{
+    any::{
+        Any,
+        TypeId,
+    },
+    collections::{
+        hash_map,
+        HashMap,
+        HashSet,
+    },
+    fmt::{
+        self,
+        Display,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+};
+
+/// Returns all keys a row is indexed by
+pub type IndexKeys<T> = fn(&T) -> Vec<String>;
+
+/// Declaration of a secondary index on a table type
+#[derive(Clone)]
+pub struct IndexDef<T> {
+    name: &'static str,
+    unique: bool,
+    keys: IndexKeys<T>,
+}
+impl<T> IndexDef<T> {
+    /// Index where every key refers to at most one row
+    pub fn unique(name: &'static str, keys: IndexKeys<T>) -> Self {
+        Self {
+            name,
+            unique: true,
+            keys,
+        }
+    }
+    /// Index where every key may refer to any number of rows
+    pub fn multi(name: &'static str, keys: IndexKeys<T>) -> Self {
+        Self {
+            name,
+            unique: false,
+            keys,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+    pub fn keys(&self, data: &T) -> Vec<String> {
+        (self.keys)(data)
+    }
+}
+/// Secondary indexes and search fields declared for a table type
+///
+/// Indexes are maintained by `Database::insert`, `Database::update` and `Database::delete`.
+/// Rows changed directly through a `TableStorage`, e.g. by migrations or `restore`,
+/// are reindexed after `reset_table_index` and `reset_search_index`.
+pub trait Indexed: Sized + Send + Sync + 'static {
+    fn indexes() -> Vec<IndexDef<Self>> {
+        Vec::new()
+    }
+    /// Fields included in `Database::search`
+    fn search_fields() -> Vec<SearchField<Self>> {
+        Vec::new()
+    }
+}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IndexError {
+    /// A unique index already contains the key
+    Conflict {
+        index: String,
+        key: String,
+    },
+    /// The table type declares no index with this name
+    UnknownIndex(String),
+}
+impl Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Conflict { index, key } => write!(f, "Key \"{}\" already exists in unique index \"{}\".", key, index),
+            Self::UnknownIndex(index) => write!(f, "Unknown index \"{}\".", index),
+        }
+    }
+}
+impl std::error::Error for IndexError {}
+
+/// Index maps of all indexes declared for a table type
+pub struct TableIndex<T> {
+    defs: Vec<IndexDef<T>>,
+    maps: Vec<HashMap<String, HashSet<Id<T>>>>,
+}
+impl<T: Indexed> Default for TableIndex<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: Indexed> TableIndex<T> {
+    pub fn new() -> Self {
+        let defs = T::indexes();
+        let maps = defs.iter().map(|_| HashMap::new()).collect();
+        Self {
+            defs,
+            maps,
+        }
+    }
+    fn position(&self, index: &str) -> Result<usize, IndexError> {
+        self.defs.iter()
+            .position(|def| def.name() == index)
+            .ok_or_else(|| IndexError::UnknownIndex(index.to_string()))
+    }
+    /// Check that data does not violate any unique index
+    pub fn check(&self, data: &T) -> Result<(), IndexError> {
+        for (def, map) in self.defs.iter().zip(self.maps.iter()) {
+            if !def.is_unique() {
+                continue;
+            }
+            for key in def.keys(data) {
+                if map.get(&key).map(|ids| !ids.is_empty()).unwrap_or(false) {
+                    return Err(IndexError::Conflict {
+                        index: def.name().to_string(),
+                        key,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn insert(&mut self, id: Id<T>, data: &T) {
+        for (def, map) in self.defs.iter().zip(self.maps.iter_mut()) {
+            for key in def.keys(data) {
+                map.entry(key).or_default().insert(id);
+            }
+        }
+    }
+    pub fn remove(&mut self, id: Id<T>, data: &T) {
+        for (def, map) in self.defs.iter().zip(self.maps.iter_mut()) {
+            for key in def.keys(data) {
+                if let Some(ids) = map.get_mut(&key) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        map.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+    /// Ids of all rows indexed by key in index
+    pub fn lookup(&self, index: &str, key: &str) -> Result<Vec<Id<T>>, IndexError> {
+        let i = self.position(index)?;
+        Ok(self.maps[i]
+            .get(key)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+lazy_static! {
+    static ref INDEXES: RwLock<HashMap<(TypeId, TypeId), Arc<dyn Any + Send + Sync>>> = RwLock::new(HashMap::new());
+}
+/// Get the index of table T in database D
+///
+/// The index is built from the stored rows on first access.
+pub fn table_index<'db, D, T>() -> Result<Arc<RwLock<TableIndex<T>>>, Error>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    let key = (TypeId::of::<D>(), TypeId::of::<T>());
+    if let Some(index) = INDEXES.read().unwrap().get(&key) {
+        return Ok(index.clone()
+            .downcast()
+            .expect("Index type mismatch"));
+    }
+    // opening the storage may migrate the tables, which resets the indexes
+    let entries = D::storage()?.entries()?;
+    let mut indexes = INDEXES.write().unwrap();
+    let index = match indexes.entry(key) {
+        hash_map::Entry::Occupied(entry) => entry.get().clone(),
+        hash_map::Entry::Vacant(entry) => {
+            let mut index = TableIndex::<T>::new();
+            for entry in entries {
+                index.insert(entry.id, entry.data());
+            }
+            entry.insert(Arc::new(RwLock::new(index))).clone()
+        }
+    };
+    Ok(index
+        .downcast()
+        .expect("Index type mismatch"))
+}
+/// Drop the index of table T in database D, so it is rebuilt from the stored rows on next access
+pub fn reset_table_index<'db, D, T>()
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    INDEXES.write().unwrap().remove(&(TypeId::of::<D>(), TypeId::of::<T>()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Person {
+        name: String,
+        groups: Vec<String>,
+    }
+    impl Indexed for Person {
+        fn indexes() -> Vec<IndexDef<Self>> {
+            vec![
+                IndexDef::unique("name", |p: &Person| vec![p.name.clone()]),
+                IndexDef::multi("groups", |p: &Person| p.groups.clone()),
+            ]
+        }
+    }
+    fn person(name: &str, groups: &[&str]) -> Person {
+        Person {
+            name: name.to_string(),
+            groups: groups.iter().map(ToString::to_string).collect(),
+        }
+    }
+    #[test]
+    fn unique_conflict() {
+        let mut index = TableIndex::<Person>::new();
+        let alice = person("alice", &[]);
+        index.insert(Id::new(), &alice);
+        assert_eq!(
+            index.check(&alice),
+            Err(IndexError::Conflict {
+                index: "name".into(),
+                key: "alice".into(),
+            })
+        );
+        assert_eq!(index.check(&person("bob", &[])), Ok(()));
+    }
+    #[test]
+    fn multi_lookup() {
+        let mut index = TableIndex::<Person>::new();
+        let a = Id::new();
+        let b = Id::new();
+        let alice = person("alice", &["admin", "dev"]);
+        let bob = person("bob", &["dev"]);
+        index.insert(a, &alice);
+        index.insert(b, &bob);
+        assert_eq!(index.lookup("groups", "dev").unwrap().len(), 2);
+        assert_eq!(index.lookup("groups", "admin").unwrap(), vec![a]);
+        index.remove(a, &alice);
+        assert_eq!(index.lookup("groups", "dev").unwrap(), vec![b]);
+        assert!(index.lookup("groups", "admin").unwrap().is_empty());
+        assert_eq!(index.lookup("email", "x"), Err(IndexError::UnknownIndex("email".into())));
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn reset_reindexes_stored_rows() {
+        use crate::{
+            policy::Authorized,
+            relation::Referenced,
+            storage::*,
+        };
+        lazy_static! {
+            static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+            static ref PEOPLE: SqliteTable<Person> = DB.table("person").unwrap();
+        }
+        struct Schema;
+        impl Database<'static, Person> for Schema {
+            fn storage() -> Result<&'static dyn TableStorage<Person>, Error> {
+                Ok(&*PEOPLE)
+            }
+        }
+        impl Referenced<'static, Schema> for Person {}
+        impl Authorized<Schema> for Person {}
+
+        <Person as DatabaseTable<'_, Schema>>::insert(person("alice", &[])).unwrap();
+        // e.g. written by a migration or a restore
+        let id = PEOPLE.insert(person("bob", &[])).unwrap();
+        assert!(<Person as DatabaseTable<'_, Schema>>::find_by_index("name", "bob").unwrap().is_none());
+        reset_table_index::<Schema, Person>();
+        assert_eq!(
+            <Person as DatabaseTable<'_, Schema>>::find_by_index("name", "bob").unwrap().map(|entry| *entry.id()),
+            Some(id)
+        );
+    }
+}
@@ -1,54 +1,269 @@
 pub mod entry;
 pub use entry::*;
+pub mod error;
+pub use error::{
+    Error,
+    ErrorStatus,
+};
 pub mod table;
 pub use table::*;
 pub mod route;
 pub use route::*;
+pub mod index;
+pub use index::*;
+pub mod query;
+pub use query::*;
+pub mod storage;
+pub use storage::*;
+pub mod migration;
+pub use migration::*;
+pub mod transaction;
+pub use transaction::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod snapshot;
+#[cfg(not(target_arch = "wasm32"))]
+pub use snapshot::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod asynchronous;
+#[cfg(not(target_arch = "wasm32"))]
+pub use asynchronous::*;
+pub mod relation;
+pub use relation::*;
+pub mod policy;
+pub use policy::*;
+pub mod search;
+pub use search::*;
+pub mod watch;
+pub use watch::{
+    Change,
+    Changes,
+};
 
+use futures::stream::{
+    BoxStream,
+    StreamExt,
+};
 use rql::*;
 
-pub trait Database<'db, T: DatabaseTable<'db, Self>> : Sized {
-    fn table() -> TableGuard<'db, T>;
-    fn table_mut() -> TableGuardMut<'db, T>;
-    fn insert(obj: T) -> Id<T> {
-        Self::table_mut()
-            .insert(obj)
-    }
-    fn get(id: Id<T>) -> Option<Entry<T>> {
-        Self::table()
-            .get(id)
-            .map(|data| Entry::from((id, data.clone())))
-    }
-    fn delete(id: Id<T>) -> Option<T> {
-        Self::table_mut()
-          .delete_one(id)
-    }
-    fn get_all() -> Vec<Entry<T>> {
-        Self::table()
-            .rows()
-            .map(|row| row.into())
-            .collect()
-    }
-    fn get_list(ids: Vec<Id<T>>) -> Vec<Entry<T>> {
-        ids.iter()
-            .filter_map(|id|
-                 Self::get(*id)
-            )
-            .collect()
-    }
-    fn filter<F>(f: F) -> Vec<Entry<T>>
+pub trait Database<'db, T: DatabaseTable<'db, Self>> : Sized + 'static {
+    /// Storage of the table, failing if the backend could not open it
+    fn storage() -> Result<&'db dyn TableStorage<T>, Error>;
+    fn insert(obj: T) -> Result<Id<T>, Error> {
+        let _write = write_guard();
+        insert_row::<Self, T>(obj)
+    }
+    fn get(id: Id<T>) -> Result<Option<Entry<T>>, Error> {
+        Ok(Self::storage()?
+            .get(id)?
+            .map(|data| Entry::new(id, data)))
+    }
+    /// Change the data of a row, keeping its indexes up to date
+    fn update<F>(id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where F: FnOnce(&mut T)
+    {
+        let _write = write_guard();
+        update_row::<Self, T, _>(id, |_, data| {
+            f(data);
+            Ok(())
+        })
+    }
+    /// Delete a row, applying the on-delete policies of its references
+    ///
+    /// The policies are applied in a transaction with the delete, see `Transaction::delete`.
+    fn delete(id: Id<T>) -> Result<Option<T>, Error> {
+        Self::transaction(|tx| tx.delete(id))
+    }
+    /// Ids in other tables referring to missing rows of T
+    fn dangling_references() -> Result<Vec<DanglingReference>, Error> {
+        let mut dangling = Vec::new();
+        for reference in <T as Referenced<'db, Self>>::references() {
+            dangling.extend(reference.dangling()?);
+        }
+        Ok(dangling)
+    }
+    fn get_all() -> Result<Vec<Entry<T>>, Error> {
+        Self::storage()?
+            .entries()
+    }
+    fn list(query: ListQuery) -> Result<Page<T>, Error> {
+        query.apply(Self::get_all()?)
+    }
+    /// Rows with ids, leaving out missing rows
+    fn get_list(ids: Vec<Id<T>>) -> Result<Vec<Entry<T>>, Error> {
+        let mut entries = Vec::new();
+        for id in ids {
+            entries.extend(Self::get(id)?);
+        }
+        Ok(entries)
+    }
+    fn filter<F>(f: F) -> Result<Vec<Entry<T>>, Error>
         where F: Fn(&T) -> bool
     {
-        Self::table()
-            .wher(|row| f(row.data))
-            .map(|row| row.into())
-            .collect()
+        Self::storage()?
+            .filter(&f)
     }
-    fn find<F>(f: F) -> Option<Entry<T>>
+    fn find<F>(f: F) -> Result<Option<Entry<T>>, Error>
         where F: Fn(&T) -> bool
     {
-        Self::table()
-            .find(|row| f(row.data))
-            .map(|row| row.into())
+        Self::storage()?
+            .find(&f)
+    }
+    fn filter_by_index<K: ToString>(index: &str, key: K) -> Result<Vec<Entry<T>>, Error> {
+        let ids = table_index::<Self, T>()?
+            .read()
+            .unwrap()
+            .lookup(index, &key.to_string())?;
+        Self::get_list(ids)
+    }
+    fn find_by_index<K: ToString>(index: &str, key: K) -> Result<Option<Entry<T>>, Error> {
+        Self::filter_by_index(index, key)
+            .map(|entries| entries.into_iter().next())
+    }
+    /// Rows matching all words of query in their search fields, best match first
+    ///
+    /// Words also match the beginning of longer words, with a lower score.
+    fn search(query: &str) -> Result<Vec<Entry<T>>, Error> {
+        let ids = search_index::<Self, T>()?
+            .read()
+            .unwrap()
+            .search(query)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        Self::get_list(ids)
+    }
+    /// Stream of all following inserts, updates and deletes in the table
+    fn watch() -> Changes<T> {
+        watch::watch::<Self, T>()
+    }
+    /// Run f in a Transaction over the tables of this database
+    fn transaction<R, E, F>(f: F) -> Result<R, E>
+        where F: FnOnce(&mut Transaction<'db, Self>) -> Result<R, E>,
+              E: From<Error>,
+    {
+        Transaction::run(f)
+    }
+    /// Row id as seen by principal, see `Authorized`
+    fn get_as(principal: &Principal, id: Id<T>) -> Result<Option<Entry<T>>, Error> {
+        match Self::get(id)? {
+            Some(entry) => readable::<Self, T>(principal, entry)
+                .map(Some)
+                .ok_or(Error::Unauthorized(Access::Read)),
+            None => Ok(None),
+        }
+    }
+    /// Rows with ids as seen by principal, leaving out missing rows
+    ///
+    /// Fails if principal may not read one of the rows.
+    fn get_list_as(principal: &Principal, ids: Vec<Id<T>>) -> Result<Vec<Entry<T>>, Error> {
+        let mut entries = Vec::new();
+        for id in ids {
+            entries.extend(Self::get_as(principal, id)?);
+        }
+        Ok(entries)
+    }
+    /// Page of the rows readable by principal
+    ///
+    /// Rows are redacted before the query is applied, so redacted fields can not be filtered.
+    fn list_as(principal: &Principal, query: ListQuery) -> Result<Page<T>, Error> {
+        query.apply(
+            Self::get_all()?
+                .into_iter()
+                .filter_map(|entry| readable::<Self, T>(principal, entry))
+                .collect()
+        )
+    }
+    fn search_as(principal: &Principal, query: &str) -> Result<Vec<Entry<T>>, Error> {
+        Ok(Self::search(query)?
+            .into_iter()
+            .filter_map(|entry| readable::<Self, T>(principal, entry))
+            .collect())
+    }
+    fn insert_as(principal: &Principal, obj: T) -> Result<Id<T>, Error> {
+        authorize(T::can_write(principal, None, &obj), Access::Write)?;
+        Self::insert(obj)
+    }
+    /// Update a row if principal may write both the stored and the changed row
+    fn update_as<F>(principal: &Principal, id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where F: FnOnce(&mut T)
+    {
+        let _write = write_guard();
+        Ok(update_row::<Self, T, _>(id, |old, data| {
+                f(data);
+                authorize(T::can_write(principal, Some(old), data), Access::Write)
+            })?
+            .and_then(|entry| readable::<Self, T>(principal, entry)))
+    }
+    /// Delete a row if principal may, returning its redacted data
+    ///
+    /// Principal must also be allowed to delete the rows the delete cascades to
+    /// and to write the rows it removes the id from. Other writes wait until
+    /// the delete is finished.
+    fn delete_as(principal: &Principal, id: Id<T>) -> Result<Option<T>, Error> {
+        Self::transaction(|tx| tx.delete_as(principal, id))
+    }
+    /// Changes of the rows readable by principal, redacted
+    fn watch_as(principal: &Principal) -> BoxStream<'static, Change<T>>
+        where T: Send + 'static
+    {
+        let principal = principal.clone();
+        Self::watch()
+            .filter_map(move |change| {
+                let change = match change {
+                    Change::Inserted(entry) => readable::<Self, T>(&principal, entry).map(Change::Inserted),
+                    Change::Updated(entry) => readable::<Self, T>(&principal, entry).map(Change::Updated),
+                    Change::Deleted(id) => Some(Change::Deleted(id)),
+                };
+                futures::future::ready(change)
+            })
+            .boxed()
+    }
+}
+/// Insert a row without taking the write lock, see `Database::insert`
+pub(crate) fn insert_row<'db, D, T>(obj: T) -> Result<Id<T>, Error>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    let index = table_index::<D, T>()?;
+    let mut index = index.write().unwrap();
+    index.check(&obj)?;
+    let id = D::storage()?
+        .insert(obj.clone())?;
+    index.insert(id, &obj);
+    update_search_index::<D, T>(id, Some(&obj));
+    watch::publish::<D, T>(Change::Inserted(Entry::new(id, obj)));
+    Ok(id)
+}
+/// Update a row without taking the write lock, see `Database::update`
+///
+/// f changes a copy of the stored row, which is kept if f fails. Other writes
+/// to the table wait until the row is written.
+pub(crate) fn update_row<'db, D, T, F>(id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+          F: FnOnce(&Entry<T>, &mut T) -> Result<(), Error>,
+{
+    let index = table_index::<D, T>()?;
+    let mut index = index.write().unwrap();
+    let old = match D::storage()?.get(id)? {
+        Some(old) => Entry::new(id, old),
+        None => return Ok(None),
+    };
+    let mut data = old.data().clone();
+    f(&old, &mut data)?;
+    let old = old.into_inner();
+    index.remove(id, &old);
+    if let Err(e) = index.check(&data) {
+        index.insert(id, &old);
+        return Err(e.into());
+    }
+    if let Err(e) = D::storage()?.update(id, data.clone()) {
+        index.insert(id, &old);
+        return Err(e);
     }
+    index.insert(id, &data);
+    update_search_index::<D, T>(id, Some(&data));
+    let entry = Entry::new(id, data);
+    watch::publish::<D, T>(Change::Updated(entry.clone()));
+    Ok(Some(entry))
 }
@@ -0,0 +1,544 @@
+use rql::*;
+use serde::{
+    Serialize,
+    de::DeserializeOwned,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{
+        self,
+        Display,
+    },
+    marker::PhantomData,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// Migrates a serialized row of a table from version N to N + 1
+pub type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// Untyped access to stored tables and their schema versions
+pub trait RawTables {
+    /// Ids and serialized data of all rows in table
+    fn rows(&self, table: &str) -> Result<Vec<(String, Value)>, String>;
+    /// Replace the data of the rows with the given ids
+    fn write_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String>;
+    /// Replace all rows of table, keeping the given ids
+    fn replace_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String>;
+    /// Replace the rows of several tables and record their versions, all or nothing
+    fn replace_tables(&self, tables: Vec<TableRows>) -> Result<(), String>;
+    /// Check that rows could replace the rows of table, without changing it
+    fn check_rows(&self, _table: &str, _rows: &[(String, Value)]) -> Result<(), String> {
+        Ok(())
+    }
+    /// Recorded schema version of table, 0 if none was recorded
+    fn version(&self, table: &str) -> Result<u32, String>;
+    fn set_version(&self, table: &str, version: u32) -> Result<(), String>;
+}
+/// Rows replacing the rows of a table, see `RawTables::replace_tables`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableRows {
+    pub table: String,
+    /// Version of the rows, recorded as the version of the table
+    pub version: u32,
+    pub rows: Vec<(String, Value)>,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationMode {
+    /// Migrate rows and record the new versions
+    Apply,
+    /// Only report what would change
+    DryRun,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationError {
+    pub table: String,
+    pub id: Option<String>,
+    pub message: String,
+}
+impl MigrationError {
+    fn new<S: ToString>(table: &str, id: Option<String>, message: S) -> Self {
+        Self {
+            table: table.to_string(),
+            id,
+            message: message.to_string(),
+        }
+    }
+}
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "Failed to migrate row {} of table {}: {}", id, self.table, self.message),
+            None => write!(f, "Failed to migrate table {}: {}", self.table, self.message),
+        }
+    }
+}
+impl std::error::Error for MigrationError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowChange {
+    pub id: String,
+    pub before: Value,
+    pub after: Value,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableReport {
+    pub table: String,
+    pub from: u32,
+    pub to: u32,
+    pub rows: usize,
+    pub changes: Vec<RowChange>,
+}
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MigrationReport {
+    pub tables: Vec<TableReport>,
+}
+impl MigrationReport {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "All tables are up to date.");
+        }
+        for table in &self.tables {
+            writeln!(
+                f,
+                "{}: version {} -> {}, {} of {} rows changed",
+                table.table,
+                table.from,
+                table.to,
+                table.changes.len(),
+                table.rows,
+            )?;
+            for change in &table.changes {
+                writeln!(f, "  {}:", change.id)?;
+                writeln!(f, "    - {}", change.before)?;
+                writeln!(f, "    + {}", change.after)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Registry of migrations for the tables of a database
+///
+/// The current version of a table is the number of migrations registered for it.
+#[derive(Clone, Default)]
+pub struct Migrations {
+    tables: Vec<(&'static str, Vec<Migration>)>,
+}
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Register migration of table from version `from` to `from + 1`
+    pub fn register(mut self, table: &'static str, from: u32, migration: Migration) -> Self {
+        let migrations = match self.tables.iter_mut().find(|(name, _)| *name == table) {
+            Some((_, migrations)) => migrations,
+            None => {
+                self.tables.push((table, Vec::new()));
+                &mut self.tables.last_mut().unwrap().1
+            }
+        };
+        assert_eq!(
+            migrations.len() as u32,
+            from,
+            "Migrations of table {} must be registered in order",
+            table,
+        );
+        migrations.push(migration);
+        self
+    }
+    /// Current schema version of table
+    pub fn version(&self, table: &str) -> u32 {
+        self.tables.iter()
+            .find(|(name, _)| *name == table)
+            .map(|(_, migrations)| migrations.len() as u32)
+            .unwrap_or(0)
+    }
+    /// Migrate rows of table from version `from` to the current version
+    pub fn migrate_rows(&self, table: &str, from: u32, rows: Vec<(String, Value)>) -> Result<Vec<(String, Value)>, MigrationError> {
+        let to = self.version(table);
+        if from > to {
+            return Err(MigrationError::new(
+                table,
+                None,
+                format!("Stored version {} is newer than version {}", from, to),
+            ));
+        }
+        let migrations = self.tables.iter()
+            .find(|(name, _)| *name == table)
+            .map(|(_, migrations)| &migrations[from as usize..])
+            .unwrap_or(&[]);
+        rows.into_iter()
+            .map(|(id, mut data)| {
+                for migration in migrations {
+                    migration(&mut data)
+                        .map_err(|e| MigrationError::new(table, Some(id.clone()), e))?;
+                }
+                Ok((id, data))
+            })
+            .collect()
+    }
+    /// Migrate all tables from their recorded version to the current version
+    ///
+    /// Each table is migrated completely in memory before it is written back.
+    pub fn run(&self, tables: &dyn RawTables, mode: MigrationMode) -> Result<MigrationReport, MigrationError> {
+        let mut report = MigrationReport::default();
+        for (table, migrations) in &self.tables {
+            let to = migrations.len() as u32;
+            let from = tables.version(table)
+                .map_err(|e| MigrationError::new(table, None, e))?;
+            if from > to {
+                return Err(MigrationError::new(
+                    table,
+                    None,
+                    format!("Stored version {} is newer than version {}", from, to),
+                ));
+            } else if from == to {
+                continue;
+            }
+            let rows = tables.rows(table)
+                .map_err(|e| MigrationError::new(table, None, e))?;
+            let mut changes = Vec::new();
+            let mut migrated = Vec::with_capacity(rows.len());
+            for (id, before) in rows {
+                let mut after = before.clone();
+                for migration in &migrations[from as usize..] {
+                    migration(&mut after)
+                        .map_err(|e| MigrationError::new(table, Some(id.clone()), e))?;
+                }
+                if after != before {
+                    changes.push(RowChange {
+                        id: id.clone(),
+                        before,
+                        after: after.clone(),
+                    });
+                }
+                migrated.push((id, after));
+            }
+            let count = migrated.len();
+            if mode == MigrationMode::Apply {
+                tables.write_rows(table, migrated)
+                    .and_then(|_| tables.set_version(table, to))
+                    .map_err(|e| MigrationError::new(table, None, e))?;
+            }
+            report.tables.push(TableReport {
+                table: table.to_string(),
+                from,
+                to,
+                rows: count,
+                changes,
+            });
+        }
+        Ok(report)
+    }
+}
+
+/// Replaces the rows of a table with rows which were decoded already
+type Replace<'t> = Box<dyn FnOnce() + 't>;
+/// Rows of an rql table, exported and imported through its row type
+trait RqlRawTable {
+    fn name(&self) -> &'static str;
+    fn rows(&self) -> Result<Vec<(String, Value)>, String>;
+    fn write_rows(&self, rows: Vec<(String, Value)>) -> Result<(), String>;
+    /// Decode rows, to replace the rows of the table with them
+    fn decode_rows(&self, rows: Vec<(String, Value)>) -> Result<Replace<'_>, String>;
+    fn check_rows(&self, rows: &[(String, Value)]) -> Result<(), String>;
+}
+struct TypedRqlTable<T, F, G> {
+    name: &'static str,
+    table: F,
+    table_mut: G,
+    _ty: PhantomData<fn() -> T>,
+}
+impl<'a, T, F, G> RqlRawTable for TypedRqlTable<T, F, G>
+    where T: Serialize + DeserializeOwned + 'a,
+          F: Fn() -> TableGuard<'a, T>,
+          G: Fn() -> TableGuardMut<'a, T>,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn rows(&self) -> Result<Vec<(String, Value)>, String> {
+        (self.table)()
+            .rows()
+            .map(|row| {
+                serde_json::to_value(row.data)
+                    .map(|data| (row.id.to_string(), data))
+                    .map_err(|e| e.to_string())
+            })
+            .collect()
+    }
+    fn write_rows(&self, rows: Vec<(String, Value)>) -> Result<(), String> {
+        // decode all rows before changing any
+        let mut rows: HashMap<String, T> = rows.into_iter()
+            .map(|(id, data)| {
+                serde_json::from_value(data)
+                    .map(|data| (id.clone(), data))
+                    .map_err(|e| format!("Row {}: {}", id, e))
+            })
+            .collect::<Result<_, String>>()?;
+        let mut table = (self.table_mut)();
+        for row in table.rows_mut() {
+            if let Some(data) = rows.remove(&row.id.to_string()) {
+                *row.data = data;
+            }
+        }
+        Ok(())
+    }
+    fn decode_rows(&self, rows: Vec<(String, Value)>) -> Result<Replace<'_>, String> {
+        // rql tables are deserialized from a map of row ids to data
+        let rows: serde_json::Map<String, Value> = rows.into_iter().collect();
+        let rows: Table<T> = serde_json::from_value(Value::Object(rows))
+            .map_err(|e| e.to_string())?;
+        Ok(Box::new(move || *(self.table_mut)() = rows))
+    }
+    fn check_rows(&self, rows: &[(String, Value)]) -> Result<(), String> {
+        rows.iter()
+            .try_for_each(|(id, data)| {
+                T::deserialize(data)
+                    .map(|_| ())
+                    .map_err(|e| format!("Row {}: {}", id, e))
+            })
+    }
+}
+/// RawTables of an rql Schema
+///
+/// Rows are exported and imported through the row types of the tables,
+/// because rql stores them in a format which is not self-describing
+/// (`rql::BinaryStable` writes structs as field lists without names).
+/// Stored rows must still deserialize into the current type, e.g. new fields
+/// need `#[serde(default)]`, and the migrations fill in their values.
+///
+/// Versions are recorded in a separate JSON file.
+pub struct RqlRawTables<'a> {
+    versions: PathBuf,
+    tables: Vec<Box<dyn RqlRawTable + 'a>>,
+}
+impl<'a> RqlRawTables<'a> {
+    pub fn new<P: AsRef<Path>>(versions: P) -> Self {
+        Self {
+            versions: versions.as_ref().to_path_buf(),
+            tables: Vec::new(),
+        }
+    }
+    pub fn table<T, F, G>(mut self, name: &'static str, table: F, table_mut: G) -> Self
+        where T: Serialize + DeserializeOwned + 'a,
+              F: Fn() -> TableGuard<'a, T> + 'a,
+              G: Fn() -> TableGuardMut<'a, T> + 'a,
+    {
+        self.tables.push(Box::new(TypedRqlTable {
+            name,
+            table,
+            table_mut,
+            _ty: PhantomData,
+        }));
+        self
+    }
+    fn get(&self, table: &str) -> Result<&dyn RqlRawTable, String> {
+        self.tables.iter()
+            .find(|t| t.name() == table)
+            .map(|t| t.as_ref() as &dyn RqlRawTable)
+            .ok_or_else(|| format!("Unknown table {}", table))
+    }
+    fn read_versions(&self) -> Result<HashMap<String, u32>, String> {
+        match std::fs::read_to_string(&self.versions) {
+            Ok(s) => serde_json::from_str(&s).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    fn write_versions(&self, versions: &HashMap<String, u32>) -> Result<(), String> {
+        let s = serde_json::to_string_pretty(versions).map_err(|e| e.to_string())?;
+        std::fs::write(&self.versions, s).map_err(|e| e.to_string())
+    }
+}
+impl<'a> RawTables for RqlRawTables<'a> {
+    fn rows(&self, table: &str) -> Result<Vec<(String, Value)>, String> {
+        self.get(table)?.rows()
+    }
+    fn write_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String> {
+        self.get(table)?.write_rows(rows)
+    }
+    fn replace_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String> {
+        self.get(table)?.decode_rows(rows).map(|replace| replace())
+    }
+    /// Decodes the rows of all tables and records the versions before any table is replaced
+    fn replace_tables(&self, tables: Vec<TableRows>) -> Result<(), String> {
+        let mut versions = self.read_versions()?;
+        let mut replaces = Vec::with_capacity(tables.len());
+        for TableRows { table, version, rows } in tables {
+            replaces.push(self.get(&table)?
+                .decode_rows(rows)
+                .map_err(|e| format!("Table {}: {}", table, e))?);
+            versions.insert(table, version);
+        }
+        self.write_versions(&versions)?;
+        for replace in replaces {
+            replace();
+        }
+        Ok(())
+    }
+    fn check_rows(&self, table: &str, rows: &[(String, Value)]) -> Result<(), String> {
+        self.get(table)?.check_rows(rows)
+    }
+    fn version(&self, table: &str) -> Result<u32, String> {
+        self.read_versions()
+            .map(|versions| versions.get(table).cloned().unwrap_or(0))
+    }
+    fn set_version(&self, table: &str, version: u32) -> Result<(), String> {
+        let mut versions = self.read_versions()?;
+        versions.insert(table.to_string(), version);
+        self.write_versions(&versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::cell::RefCell;
+    #[derive(Default)]
+    struct MemoryTables {
+        rows: RefCell<HashMap<String, Vec<(String, Value)>>>,
+        versions: RefCell<HashMap<String, u32>>,
+    }
+    impl RawTables for MemoryTables {
+        fn rows(&self, table: &str) -> Result<Vec<(String, Value)>, String> {
+            Ok(self.rows.borrow().get(table).cloned().unwrap_or_default())
+        }
+        fn write_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String> {
+            self.rows.borrow_mut().insert(table.to_string(), rows);
+            Ok(())
+        }
+        fn replace_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String> {
+            self.write_rows(table, rows)
+        }
+        fn replace_tables(&self, tables: Vec<TableRows>) -> Result<(), String> {
+            for TableRows { table, version, rows } in tables {
+                self.replace_rows(&table, rows)?;
+                self.set_version(&table, version)?;
+            }
+            Ok(())
+        }
+        fn version(&self, table: &str) -> Result<u32, String> {
+            Ok(self.versions.borrow().get(table).cloned().unwrap_or(0))
+        }
+        fn set_version(&self, table: &str, version: u32) -> Result<(), String> {
+            self.versions.borrow_mut().insert(table.to_string(), version);
+            Ok(())
+        }
+    }
+    fn migrations() -> Migrations {
+        Migrations::new()
+            .register("user", 0, |user| {
+                user["full_name"] = Value::Null;
+                Ok(())
+            })
+            .register("user", 1, |user| {
+                user["followers"] = Value::Array(vec![]);
+                Ok(())
+            })
+    }
+    fn tables() -> MemoryTables {
+        let tables = MemoryTables::default();
+        tables.write_rows("user", vec![("1".into(), serde_json::json!({ "name": "alice" }))]).unwrap();
+        tables
+    }
+    #[test]
+    fn dry_run() {
+        let tables = tables();
+        let report = migrations().run(&tables, MigrationMode::DryRun).unwrap();
+        assert_eq!(report.tables[0].from, 0);
+        assert_eq!(report.tables[0].to, 2);
+        assert_eq!(
+            report.tables[0].changes[0].after,
+            serde_json::json!({ "name": "alice", "full_name": null, "followers": [] })
+        );
+        assert_eq!(tables.version("user"), Ok(0));
+        assert_eq!(tables.rows("user").unwrap()[0].1, serde_json::json!({ "name": "alice" }));
+    }
+    #[test]
+    fn apply_from_recorded_version() {
+        let tables = tables();
+        tables.set_version("user", 1).unwrap();
+        let report = migrations().run(&tables, MigrationMode::Apply).unwrap();
+        assert_eq!(report.tables[0].from, 1);
+        assert_eq!(tables.version("user"), Ok(2));
+        assert_eq!(
+            tables.rows("user").unwrap()[0].1,
+            serde_json::json!({ "name": "alice", "followers": [] })
+        );
+        assert!(migrations().run(&tables, MigrationMode::Apply).unwrap().is_empty());
+    }
+    mod v0 {
+        use serde::{
+            Serialize,
+            Deserialize,
+        };
+        #[derive(Serialize, Deserialize)]
+        pub struct Account {
+            pub name: String,
+        }
+        rql::schema! {
+            pub Accounts {
+                account: Account,
+            }
+        }
+    }
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    pub struct Account {
+        name: String,
+        #[serde(default)]
+        full_name: Option<String>,
+    }
+    rql::schema! {
+        pub Accounts {
+            account: Account,
+        }
+    }
+    #[test]
+    fn rql_binary_stable() {
+        let dir = std::env::temp_dir().join(format!("database_table_migration_{}", std::process::id()));
+        let name = dir.to_str().unwrap();
+        let id = v0::Accounts::new(name, rql::BinaryStable)
+            .unwrap()
+            .account_mut()
+            .insert(v0::Account {
+                name: "alice".into(),
+            });
+        let id: Id<Account> = id.to_string().parse().unwrap();
+        let migrations = Migrations::new()
+            .register("account", 0, |account| {
+                account["full_name"] = account["name"].clone();
+                Ok(())
+            });
+        {
+            let db = Accounts::new(name, rql::BinaryStable).unwrap();
+            let tables = RqlRawTables::new(dir.join("versions.json"))
+                .table("account", || db.account(), || db.account_mut());
+            let report = migrations.run(&tables, MigrationMode::Apply).unwrap();
+            assert_eq!(
+                report.tables[0].changes[0].after,
+                serde_json::json!({ "name": "alice", "full_name": "alice" })
+            );
+            assert_eq!(tables.version("account"), Ok(1));
+        }
+        let db = Accounts::new(name, rql::BinaryStable).unwrap();
+        assert_eq!(
+            db.account().get(id),
+            Some(&Account {
+                name: "alice".into(),
+                full_name: Some("alice".into()),
+            })
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    entry::*,
+    Error,
+};
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use std::{
+    fmt::{
+        self,
+        Display,
+    },
+    str::FromStr,
+};
+
+/// Caller of a table operation
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Principal {
+    Anonymous,
+    /// Authenticated caller, e.g. the subject of a JWT
+    Subject(String),
+}
+impl Principal {
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            Self::Anonymous => None,
+            Self::Subject(subject) => Some(subject),
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+pub enum Access {
+    Read,
+    Write,
+    Delete,
+}
+impl Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+            Self::Delete => write!(f, "delete"),
+        }
+    }
+}
+impl FromStr for Access {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "delete" => Ok(Self::Delete),
+            _ => Err(format!("Unknown access {}", s)),
+        }
+    }
+}
+/// Access policy of a table type in database D
+///
+/// Policies are enforced by the `*_as` methods of `Database`, which are meant
+/// for serving clients. The default allows everything and redacts nothing.
+pub trait Authorized<D>: Sized {
+    fn can_read(_principal: &Principal, _entry: &Entry<Self>) -> bool {
+        true
+    }
+    /// Whether principal may replace row old, or insert a new row if None, with data
+    fn can_write(_principal: &Principal, _old: Option<&Entry<Self>>, _data: &Self) -> bool {
+        true
+    }
+    fn can_delete(_principal: &Principal, _entry: &Entry<Self>) -> bool {
+        true
+    }
+    /// Clear the fields principal must not see, before a row is sent out
+    fn redact(&mut self, _principal: &Principal) {}
+}
+/// Entry as seen by principal, None if it may not be read
+pub(crate) fn readable<D, T>(principal: &Principal, entry: Entry<T>) -> Option<Entry<T>>
+    where T: Authorized<D>,
+{
+    if T::can_read(principal, &entry) {
+        let id = *entry.id();
+        let mut data = entry.into_inner();
+        data.redact(principal);
+        Some(Entry::new(id, data))
+    } else {
+        None
+    }
+}
+pub(crate) fn authorize(allowed: bool, access: Access) -> Result<(), Error> {
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized(access))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::{
+        index::*,
+        relation::*,
+        storage::*,
+        Database,
+        DatabaseTable,
+    };
+    use lazy_static::lazy_static;
+    use rql::*;
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        owner: String,
+        secret: String,
+    }
+    impl Indexed for Account {}
+    /// Note on an account, deleted with it
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        account: Id<Account>,
+        owner: String,
+    }
+    impl Indexed for Note {}
+    lazy_static! {
+        static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+        static ref ACCOUNTS: SqliteTable<Account> = DB.table("account").unwrap();
+        static ref NOTES: SqliteTable<Note> = DB.table("note").unwrap();
+    }
+    struct Schema;
+    impl Database<'static, Account> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Account>, Error> {
+            Ok(&*ACCOUNTS)
+        }
+    }
+    impl Database<'static, Note> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Note>, Error> {
+            Ok(&*NOTES)
+        }
+    }
+    impl Referenced<'static, Schema> for Account {
+        fn references() -> Vec<Reference<'static, Schema, Self>> {
+            vec![Reference::cascade("note.account", |note: &Note| vec![note.account])]
+        }
+    }
+    impl Referenced<'static, Schema> for Note {}
+    impl Authorized<Schema> for Note {
+        fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+            principal.subject() == Some(&entry.data().owner)
+        }
+    }
+    impl Authorized<Schema> for Account {
+        fn can_write(principal: &Principal, old: Option<&Entry<Self>>, data: &Self) -> bool {
+            principal.subject() == Some(&data.owner)
+                && old.map(|old| old.data().owner == data.owner).unwrap_or(true)
+        }
+        fn can_delete(principal: &Principal, entry: &Entry<Self>) -> bool {
+            principal.subject() == Some(&entry.data().owner)
+        }
+        fn redact(&mut self, principal: &Principal) {
+            if principal.subject() != Some(&self.owner) {
+                self.secret.clear();
+            }
+        }
+    }
+    fn account(owner: &str) -> Account {
+        Account {
+            owner: owner.to_string(),
+            secret: "secret".to_string(),
+        }
+    }
+    #[test]
+    fn policies() {
+        let alice = Principal::Subject("alice".into());
+        let bob = Principal::Subject("bob".into());
+        assert_eq!(
+            <Account as DatabaseTable<'_, Schema>>::insert_as(&Principal::Anonymous, account("alice")),
+            Err(Error::Unauthorized(Access::Write)),
+        );
+        let id = <Account as DatabaseTable<'_, Schema>>::insert_as(&alice, account("alice")).unwrap();
+
+        let own = <Account as DatabaseTable<'_, Schema>>::get_as(&alice, id).unwrap().unwrap();
+        assert_eq!(own.data().secret, "secret");
+        let other = <Account as DatabaseTable<'_, Schema>>::get_as(&bob, id).unwrap().unwrap();
+        assert_eq!(other.data().secret, "");
+        let others = <Account as DatabaseTable<'_, Schema>>::get_list_as(&bob, vec![id, Id::new()]).unwrap();
+        assert_eq!(others.len(), 1);
+        assert_eq!(others[0].data().secret, "");
+
+        assert_eq!(
+            <Account as DatabaseTable<'_, Schema>>::update_as(&bob, id, |a| a.owner = "bob".into()).err(),
+            Some(Error::Unauthorized(Access::Write)),
+        );
+        assert_eq!(
+            <Account as DatabaseTable<'_, Schema>>::delete_as(&bob, id),
+            Err(Error::Unauthorized(Access::Delete)),
+        );
+        assert_eq!(
+            <Account as DatabaseTable<'_, Schema>>::delete_as(&alice, id),
+            Ok(Some(account("alice"))),
+        );
+    }
+    #[test]
+    fn cascade_policies() {
+        let alice = Principal::Subject("alice".into());
+        let id = <Account as DatabaseTable<'_, Schema>>::insert(account("alice")).unwrap();
+        let note = <Note as DatabaseTable<'_, Schema>>::insert(Note {
+            account: id,
+            owner: "bob".into(),
+        }).unwrap();
+        // the delete would cascade to a note alice may not delete
+        assert_eq!(
+            <Account as DatabaseTable<'_, Schema>>::delete_as(&alice, id),
+            Err(Error::Unauthorized(Access::Delete)),
+        );
+        assert!(<Account as DatabaseTable<'_, Schema>>::get(id).unwrap().is_some());
+
+        <Note as DatabaseTable<'_, Schema>>::update(note, |note| note.owner = "alice".into()).unwrap();
+        assert!(<Account as DatabaseTable<'_, Schema>>::delete_as(&alice, id).unwrap().is_some());
+        assert!(<Note as DatabaseTable<'_, Schema>>::get(note).unwrap().is_none());
+    }
+}
@@ -0,0 +1,347 @@
+use crate::{
+    entry::*,
+    error::Error,
+};
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    fmt::Write,
+};
+
+/// Paging, sorting and filtering of table lists
+///
+/// Fields are addressed by their serialized names, nested fields by dotted paths
+/// (e.g. `credentials.username`).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+pub struct ListQuery {
+    /// Number of entries to skip
+    #[serde(default)]
+    pub offset: Option<usize>,
+    /// Start after the entry with this id (takes precedence over offset)
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Maximum number of entries in a page
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Field to sort entries by, sorted by id if not given
+    #[serde(default)]
+    pub sort_by: Option<String>,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    /// Substring of a string field or element of an array field
+    Contains,
+}
+impl FilterOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eq => "eq",
+            Self::Ne => "ne",
+            Self::Lt => "lt",
+            Self::Gt => "gt",
+            Self::Contains => "contains",
+        }
+    }
+}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+pub struct Filter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: String,
+}
+impl Filter {
+    pub fn new<S1: ToString, S2: ToString>(field: S1, op: FilterOp, value: S2) -> Self {
+        Self {
+            field: field.to_string(),
+            op,
+            value: value.to_string(),
+        }
+    }
+    fn matches(&self, value: &Value) -> bool {
+        let field = match field(value, &self.field) {
+            Some(field) => field,
+            None => return false,
+        };
+        match self.op {
+            FilterOp::Eq => eq(field, &self.value),
+            FilterOp::Ne => !eq(field, &self.value),
+            FilterOp::Lt => cmp_str(field, &self.value) == Some(Ordering::Less),
+            FilterOp::Gt => cmp_str(field, &self.value) == Some(Ordering::Greater),
+            FilterOp::Contains => match field {
+                Value::String(s) => s.contains(&self.value),
+                Value::Array(values) => values.iter().any(|v| eq(v, &self.value)),
+                _ => false,
+            },
+        }
+    }
+}
+/// One page of a table list
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(schemars::JsonSchema))]
+pub struct Page<T> {
+    pub items: Vec<Entry<T>>,
+    /// Number of entries matching the filters
+    pub total: usize,
+    /// Cursor for the next page, if there are more entries
+    pub next: Option<String>,
+}
+impl<T> Default for Page<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            total: 0,
+            next: None,
+        }
+    }
+}
+
+fn field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, name| value.get(name))
+}
+fn eq(value: &Value, s: &str) -> bool {
+    match value {
+        Value::String(v) => v == s,
+        Value::Null => s == "null",
+        _ => cmp_str(value, s) == Some(Ordering::Equal),
+    }
+}
+fn cmp_str(value: &Value, s: &str) -> Option<Ordering> {
+    match value {
+        Value::String(v) => Some(v.as_str().cmp(s)),
+        Value::Number(n) => n.as_f64()
+            .zip(s.parse::<f64>().ok())
+            .and_then(|(a, b)| a.partial_cmp(&b)),
+        Value::Bool(b) => s.parse::<bool>().ok().map(|s| b.cmp(&s)),
+        _ => None,
+    }
+}
+fn cmp_values(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(Value::Number(a)), Some(Value::Number(b))) =>
+            a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        (Some(Value::Bool(a)), Some(Value::Bool(b))) => a.cmp(b),
+        (Some(Value::Array(a)), Some(Value::Array(b))) => a.len().cmp(&b.len()),
+        (Some(Value::Null), _) | (None, _) => Ordering::Less,
+        (_, Some(Value::Null)) | (_, None) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+/// Percent-encode a query string component
+pub(crate) fn encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' =>
+                encoded.push(b as char),
+            _ => write!(encoded, "%{:02X}", b).unwrap(),
+        }
+    }
+    encoded
+}
+
+impl ListQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn page(offset: usize, limit: usize) -> Self {
+        Self {
+            offset: Some(offset),
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+    pub fn cursor<S: ToString>(mut self, cursor: S) -> Self {
+        self.cursor = Some(cursor.to_string());
+        self
+    }
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    pub fn sort_by<S: ToString>(mut self, field: S, descending: bool) -> Self {
+        self.sort_by = Some(field.to_string());
+        self.descending = descending;
+        self
+    }
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+    /// Encode as URL query string, compatible with serde_qs
+    pub fn to_query_string(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(offset) = self.offset {
+            params.push(format!("offset={}", offset));
+        }
+        if let Some(cursor) = &self.cursor {
+            params.push(format!("cursor={}", encode(cursor)));
+        }
+        if let Some(limit) = self.limit {
+            params.push(format!("limit={}", limit));
+        }
+        if let Some(sort_by) = &self.sort_by {
+            params.push(format!("sort_by={}", encode(sort_by)));
+        }
+        if self.descending {
+            params.push("descending=true".to_string());
+        }
+        for (i, filter) in self.filters.iter().enumerate() {
+            params.push(format!("filters[{}][field]={}", i, encode(&filter.field)));
+            params.push(format!("filters[{}][op]={}", i, filter.op.as_str()));
+            params.push(format!("filters[{}][value]={}", i, encode(&filter.value)));
+        }
+        params.join("&")
+    }
+    /// Filter, sort and page a list of entries
+    ///
+    /// Fails with `Error::NotFound` if the cursor is not in the filtered list,
+    /// e.g. because its entry was deleted since the previous page.
+    pub fn apply<T: Serialize>(&self, entries: Vec<Entry<T>>) -> Result<Page<T>, Error> {
+        let mut entries: Vec<(Value, Entry<T>)> = entries
+            .into_iter()
+            .map(|entry| (serde_json::to_value(entry.data()).unwrap_or(Value::Null), entry))
+            .filter(|(value, _)| self.filters.iter().all(|filter| filter.matches(value)))
+            .collect();
+        let total = entries.len();
+        entries.sort_by(|(a, ea), (b, eb)| {
+            let ord = match &self.sort_by {
+                Some(path) => cmp_values(field(a, path), field(b, path)),
+                None => Ordering::Equal,
+            }
+            .then_with(|| ea.id().to_string().cmp(&eb.id().to_string()));
+            if self.descending {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+        let start = match &self.cursor {
+            Some(cursor) => entries.iter()
+                .position(|(_, entry)| entry.id().to_string() == *cursor)
+                .map(|pos| pos + 1)
+                .ok_or_else(|| Error::NotFound(format!("list cursor {}", cursor)))?,
+            None => self.offset.unwrap_or(0),
+        };
+        let end = self.limit
+            .map(|limit| start.saturating_add(limit))
+            .unwrap_or(total)
+            .min(total);
+        let items: Vec<Entry<T>> = entries
+            .into_iter()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .map(|(_, entry)| entry)
+            .collect();
+        let next = if end < total {
+            items.last().map(|entry| entry.id().to_string())
+        } else {
+            None
+        };
+        Ok(Page {
+            items,
+            total,
+            next,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rql::Id;
+    #[derive(Clone, Debug, Serialize)]
+    struct Item {
+        name: String,
+        count: u32,
+        tags: Vec<String>,
+    }
+    fn entries() -> Vec<Entry<Item>> {
+        vec![
+            ("b", 2, vec!["x"]),
+            ("a", 3, vec!["x", "y"]),
+            ("c", 1, vec![]),
+        ]
+        .into_iter()
+        .map(|(name, count, tags)| Entry::new(Id::new(), Item {
+            name: name.to_string(),
+            count,
+            tags: tags.into_iter().map(ToString::to_string).collect(),
+        }))
+        .collect()
+    }
+    fn names(page: &Page<Item>) -> Vec<String> {
+        page.items.iter().map(|entry| entry.data().name.clone()).collect()
+    }
+    #[test]
+    fn sort_and_page() {
+        let entries = entries();
+        let query = ListQuery::page(0, 2).sort_by("name", false);
+        let page = query.apply(entries.clone()).unwrap();
+        assert_eq!(names(&page), vec!["a", "b"]);
+        assert_eq!(page.total, 3);
+        let page = ListQuery::new()
+            .limit(2)
+            .sort_by("name", false)
+            .cursor(page.next.unwrap())
+            .apply(entries.clone())
+            .unwrap();
+        assert_eq!(names(&page), vec!["c"]);
+        assert_eq!(page.next, None);
+        let page = ListQuery::new().sort_by("count", true).apply(entries).unwrap();
+        assert_eq!(names(&page), vec!["a", "b", "c"]);
+    }
+    #[test]
+    fn stale_cursor() {
+        let entries = entries();
+        let stale = Id::<Item>::new().to_string();
+        let result = ListQuery::page(1, 2).cursor(&stale).apply(entries.clone());
+        assert!(matches!(result, Err(Error::NotFound(_))));
+        // a cursor filtered out of the list is stale as well
+        let page = ListQuery::new()
+            .filter(Filter::new("count", FilterOp::Gt, 1))
+            .cursor(entries[2].id())
+            .apply(entries);
+        assert!(matches!(page, Err(Error::NotFound(_))));
+    }
+    #[test]
+    fn filters() {
+        let page = ListQuery::new()
+            .filter(Filter::new("tags", FilterOp::Contains, "x"))
+            .filter(Filter::new("count", FilterOp::Gt, 2))
+            .apply(entries())
+            .unwrap();
+        assert_eq!(names(&page), vec!["a"]);
+    }
+    #[test]
+    fn query_string() {
+        let query = ListQuery::page(20, 10)
+            .sort_by("name", true)
+            .filter(Filter::new("name", FilterOp::Eq, "a b"));
+        assert_eq!(
+            query.to_query_string(),
+            "offset=20&limit=10&sort_by=name&descending=true&filters[0][field]=name&filters[0][op]=eq&filters[0][value]=a%20b"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use rql::*;
+use crate::{
+    index::table_index,
+    policy::{
+        authorize,
+        Access,
+        Principal,
+    },
+    search::update_search_index,
+    transaction::Transaction,
+    watch::*,
+    Database,
+    DatabaseTable,
+    Error,
+};
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use std::{
+    any::TypeId,
+    collections::HashSet,
+    marker::PhantomData,
+    fmt::{
+        self,
+        Display,
+    },
+};
+
+/// What happens to referencing rows when a referenced row is deleted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnDelete {
+    /// Refuse to delete a row while it is referenced
+    Restrict,
+    /// Delete the referencing rows as well
+    Cascade,
+    /// Remove the id from the referencing rows
+    Nullify,
+}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReferenceError {
+    /// The row is referenced through a reference with `OnDelete::Restrict`
+    Restricted {
+        reference: String,
+        referrers: Vec<String>,
+    },
+}
+impl Display for ReferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Restricted { reference, referrers } =>
+                write!(f, "Row is still referenced through \"{}\" by {}.", reference, referrers.join(", ")),
+        }
+    }
+}
+impl std::error::Error for ReferenceError {}
+
+/// An id in a referencing row without a referenced row
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DanglingReference {
+    pub reference: String,
+    /// Id of the referencing row
+    pub row: String,
+    /// Id of the missing referenced row
+    pub target: String,
+}
+impl Display for DanglingReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} refers to missing row {}", self.reference, self.row, self.target)
+    }
+}
+
+/// Rows already checked or deleted, so cyclic references are followed once
+pub(crate) type Visited = HashSet<(TypeId, String)>;
+type CheckDelete<'db, T> = Box<dyn Fn(Id<T>, Option<&Principal>, &mut Visited) -> Result<(), Error> + 'db>;
+type ApplyDelete<'db, D, T> = Box<dyn Fn(&mut Transaction<'db, D>, Id<T>, &mut Visited) -> Result<(), Error> + 'db>;
+
+/// Reference from rows of another table in database D to rows of T
+pub struct Reference<'db, D, T> {
+    name: &'static str,
+    on_delete: OnDelete,
+    check: CheckDelete<'db, T>,
+    apply: ApplyDelete<'db, D, T>,
+    dangling: Box<dyn Fn() -> Result<Vec<DanglingReference>, Error> + 'db>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<'db, D, T> Reference<'db, D, T>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    /// Deleting a row of T fails while rows of R refer to it
+    pub fn restrict<R>(name: &'static str, ids: fn(&R) -> Vec<Id<T>>) -> Self
+        where D: Database<'db, R>,
+              R: DatabaseTable<'db, D>,
+    {
+        Self::new(name, OnDelete::Restrict, ids, |_, _| {})
+    }
+    /// Deleting a row of T also deletes the rows of R referring to it
+    pub fn cascade<R>(name: &'static str, ids: fn(&R) -> Vec<Id<T>>) -> Self
+        where D: Database<'db, R>,
+              R: DatabaseTable<'db, D>,
+    {
+        Self::new(name, OnDelete::Cascade, ids, |_, _| {})
+    }
+    /// Deleting a row of T removes its id from the rows of R with remove
+    pub fn nullify<R>(name: &'static str, ids: fn(&R) -> Vec<Id<T>>, remove: fn(&mut R, Id<T>)) -> Self
+        where D: Database<'db, R>,
+              R: DatabaseTable<'db, D>,
+    {
+        Self::new(name, OnDelete::Nullify, ids, remove)
+    }
+    fn new<R>(name: &'static str, on_delete: OnDelete, ids: fn(&R) -> Vec<Id<T>>, remove: fn(&mut R, Id<T>)) -> Self
+        where D: Database<'db, R>,
+              R: DatabaseTable<'db, D>,
+    {
+        let referrers = move |id: Id<T>| <D as Database<'db, R>>::filter(|row| ids(row).contains(&id));
+        Self {
+            name,
+            on_delete,
+            check: Box::new(move |id, principal, visited| match on_delete {
+                OnDelete::Restrict => {
+                    let referrers: Vec<String> = referrers(id)?
+                        .iter()
+                        .map(|entry| entry.id().to_string())
+                        .collect();
+                    if referrers.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(ReferenceError::Restricted {
+                            reference: name.to_string(),
+                            referrers,
+                        }.into())
+                    }
+                }
+                OnDelete::Cascade => referrers(id)?
+                    .into_iter()
+                    .try_for_each(|entry| {
+                        if let Some(principal) = principal {
+                            authorize(R::can_delete(principal, &entry), Access::Delete)?;
+                        }
+                        check_delete::<D, R>(entry.id, principal, visited)
+                    }),
+                OnDelete::Nullify => match principal {
+                    Some(principal) => referrers(id)?
+                        .into_iter()
+                        .try_for_each(|entry| {
+                            let mut data = entry.data().clone();
+                            remove(&mut data, id);
+                            authorize(R::can_write(principal, Some(&entry), &data), Access::Write)
+                        }),
+                    None => Ok(()),
+                },
+            }),
+            apply: Box::new(move |tx, id, visited| {
+                match on_delete {
+                    OnDelete::Restrict => {}
+                    OnDelete::Cascade => for entry in referrers(id)? {
+                        tx.delete_referenced::<R>(entry.id, visited)?;
+                    },
+                    OnDelete::Nullify => for entry in referrers(id)? {
+                        tx.update(entry.id, |row: &mut R| remove(row, id))?;
+                    },
+                }
+                Ok(())
+            }),
+            dangling: Box::new(move || {
+                let mut dangling = Vec::new();
+                for entry in <D as Database<'db, R>>::get_all()? {
+                    for target in ids(entry.data()) {
+                        if <D as Database<'db, T>>::storage()?.get(target)?.is_none() {
+                            dangling.push(DanglingReference {
+                                reference: name.to_string(),
+                                row: entry.id().to_string(),
+                                target: target.to_string(),
+                            });
+                        }
+                    }
+                }
+                Ok(dangling)
+            }),
+            _db: PhantomData,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn on_delete(&self) -> OnDelete {
+        self.on_delete
+    }
+    /// Ids in referencing rows without a row of T
+    pub fn dangling(&self) -> Result<Vec<DanglingReference>, Error> {
+        (self.dangling)()
+    }
+    /// Apply the on-delete policy to the rows referring to row id in tx
+    pub(crate) fn apply(&self, tx: &mut Transaction<'db, D>, id: Id<T>, visited: &mut Visited) -> Result<(), Error> {
+        (self.apply)(tx, id, visited)
+    }
+}
+/// References to a table type from other tables of database D
+///
+/// The on-delete policies are enforced by `Database::delete`.
+pub trait Referenced<'db, D>: Sized {
+    fn references() -> Vec<Reference<'db, D, Self>> {
+        Vec::new()
+    }
+}
+
+/// Check that the row and all rows it cascades to may be deleted
+///
+/// With a principal, it must also be allowed to delete the rows the delete
+/// cascades to and to write the rows it removes the id from.
+pub(crate) fn check_delete<'db, D, T>(id: Id<T>, principal: Option<&Principal>, visited: &mut Visited) -> Result<(), Error>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    if !visited.insert((TypeId::of::<T>(), id.to_string())) {
+        return Ok(());
+    }
+    <T as Referenced<'db, D>>::references()
+        .iter()
+        .try_for_each(|reference| (reference.check)(id, principal, visited))
+}
+/// Delete the row, without applying the on-delete policies of its references
+///
+/// See `Transaction::delete`, which applies them.
+pub(crate) fn delete_row<'db, D, T>(id: Id<T>) -> Result<Option<T>, Error>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    let data = {
+        let index = table_index::<D, T>()?;
+        let mut index = index.write().unwrap();
+        let data = match D::storage()?.delete(id)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        index.remove(id, &data);
+        data
+    };
+    update_search_index::<D, T>(id, None);
+    publish::<D, T>(Change::Deleted(id));
+    Ok(Some(data))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::{
+        index::*,
+        policy::*,
+        storage::*,
+    };
+    use lazy_static::lazy_static;
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Node {
+        parent: Option<Id<Node>>,
+        owner: Id<Owner>,
+    }
+    impl Indexed for Node {}
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Owner {
+        name: String,
+    }
+    impl Indexed for Owner {}
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Pet {
+        owner: Id<Owner>,
+    }
+    impl Indexed for Pet {}
+    /// Row with a unique set of owners
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Label {
+        owners: Vec<Id<Owner>>,
+    }
+    impl Indexed for Label {
+        fn indexes() -> Vec<IndexDef<Self>> {
+            vec![IndexDef::unique("owners", |label: &Label| {
+                vec![label.owners.iter().map(ToString::to_string).collect::<Vec<_>>().join(",")]
+            })]
+        }
+    }
+    lazy_static! {
+        static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+        static ref NODES: SqliteTable<Node> = DB.table("node").unwrap();
+        static ref OWNERS: SqliteTable<Owner> = DB.table("owner").unwrap();
+        static ref PETS: SqliteTable<Pet> = DB.table("pet").unwrap();
+        static ref LABELS: SqliteTable<Label> = DB.table("label").unwrap();
+    }
+    struct Schema;
+    impl Database<'static, Node> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Node>, Error> {
+            Ok(&*NODES)
+        }
+    }
+    impl Database<'static, Owner> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Owner>, Error> {
+            Ok(&*OWNERS)
+        }
+    }
+    impl Database<'static, Pet> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Pet>, Error> {
+            Ok(&*PETS)
+        }
+    }
+    impl Database<'static, Label> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Label>, Error> {
+            Ok(&*LABELS)
+        }
+    }
+    impl Referenced<'static, Schema> for Node {
+        fn references() -> Vec<Reference<'static, Schema, Self>> {
+            vec![Reference::cascade("node.parent", |node: &Node| node.parent.into_iter().collect())]
+        }
+    }
+    impl Referenced<'static, Schema> for Owner {
+        fn references() -> Vec<Reference<'static, Schema, Self>> {
+            vec![
+                Reference::restrict("node.owner", |node: &Node| vec![node.owner]),
+                Reference::cascade("pet.owner", |pet: &Pet| vec![pet.owner]),
+                Reference::nullify("label.owners", |label: &Label| label.owners.clone(), |label, id| label.owners.retain(|o| *o != id)),
+            ]
+        }
+    }
+    impl Referenced<'static, Schema> for Pet {}
+    impl Referenced<'static, Schema> for Label {}
+    impl Authorized<Schema> for Node {}
+    impl Authorized<Schema> for Owner {}
+    impl Authorized<Schema> for Pet {}
+    impl Authorized<Schema> for Label {}
+    fn node(parent: Option<Id<Node>>, owner: Id<Owner>) -> Id<Node> {
+        <Node as DatabaseTable<'_, Schema>>::insert(Node { parent, owner }).unwrap()
+    }
+    #[test]
+    fn restrict_and_cascade() {
+        let owner = <Owner as DatabaseTable<'_, Schema>>::insert(Owner { name: "a".into() }).unwrap();
+        let root = node(None, owner);
+        let child = node(Some(root), owner);
+        node(Some(child), owner);
+        assert!(matches!(
+            <Owner as DatabaseTable<'_, Schema>>::delete(owner),
+            Err(Error::Conflict(_))
+        ));
+        assert!(<Node as DatabaseTable<'_, Schema>>::delete(root).unwrap().is_some());
+        assert!(<Node as DatabaseTable<'_, Schema>>::get_all().unwrap().is_empty());
+
+        // rows written around Database::delete are reported as dangling
+        let child = node(Some(root), owner);
+        let dangling = <Node as DatabaseTable<'_, Schema>>::dangling_references().unwrap();
+        assert_eq!(dangling, vec![DanglingReference {
+            reference: "node.parent".into(),
+            row: child.to_string(),
+            target: root.to_string(),
+        }]);
+        assert!(<Owner as DatabaseTable<'_, Schema>>::dangling_references().unwrap().is_empty());
+    }
+    #[test]
+    fn failing_policies_roll_back() {
+        let owner = |name: &str| <Owner as DatabaseTable<'_, Schema>>::insert(Owner { name: name.into() }).unwrap();
+        let label = |owners: Vec<Id<Owner>>| <Label as DatabaseTable<'_, Schema>>::insert(Label { owners }).unwrap();
+        let (a, b, c) = (owner("a"), owner("b"), owner("c"));
+        let pet = <Pet as DatabaseTable<'_, Schema>>::insert(Pet { owner: a }).unwrap();
+        let nullified = label(vec![a, c]);
+        let conflicting = label(vec![a, b]);
+        label(vec![b]);
+        // removing a from the second label conflicts with the third
+        assert!(matches!(
+            <Owner as DatabaseTable<'_, Schema>>::delete(a),
+            Err(Error::Conflict(_))
+        ));
+        assert!(<Owner as DatabaseTable<'_, Schema>>::get(a).unwrap().is_some());
+        assert!(<Pet as DatabaseTable<'_, Schema>>::get(pet).unwrap().is_some());
+        assert_eq!(<Label as DatabaseTable<'_, Schema>>::get(nullified).unwrap().unwrap().data().owners, vec![a, c]);
+        assert_eq!(<Label as DatabaseTable<'_, Schema>>::get(conflicting).unwrap().unwrap().data().owners, vec![a, b]);
+
+        <Label as DatabaseTable<'_, Schema>>::delete(conflicting).unwrap();
+        assert!(<Owner as DatabaseTable<'_, Schema>>::delete(a).unwrap().is_some());
+        assert!(<Pet as DatabaseTable<'_, Schema>>::get(pet).unwrap().is_none());
+        assert_eq!(<Label as DatabaseTable<'_, Schema>>::get(nullified).unwrap().unwrap().data().owners, vec![c]);
+    }
+}
@@ -0,0 +1,254 @@
+use rql::*;
+use crate::{
+    index::Indexed,
+    Database,
+    DatabaseTable,
+    Error,
+};
+use lazy_static::lazy_static;
+use std::{
+    any::{
+        Any,
+        TypeId,
+    },
+    cmp::Ordering,
+    collections::{
+        hash_map,
+        BTreeMap,
+        HashMap,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+};
+
+/// Score factor of a query token matching only the prefix of a token
+const PREFIX_FACTOR: f32 = 0.5;
+
+/// Returns the searchable text of a row
+pub type SearchText<T> = fn(&T) -> String;
+
+/// Declaration of a field included in full-text search
+#[derive(Clone)]
+pub struct SearchField<T> {
+    name: &'static str,
+    weight: f32,
+    text: SearchText<T>,
+}
+impl<T> SearchField<T> {
+    pub fn new(name: &'static str, text: SearchText<T>) -> Self {
+        Self {
+            name,
+            weight: 1.0,
+            text,
+        }
+    }
+    /// Scale the score of matches in this field
+    pub fn weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn text(&self, data: &T) -> String {
+        (self.text)(data)
+    }
+}
+/// Split text into lowercase alphanumeric tokens
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Inverted index over the search fields of a table type
+pub struct SearchIndex<T> {
+    fields: Vec<SearchField<T>>,
+    tokens: BTreeMap<String, HashMap<Id<T>, f32>>,
+    rows: HashMap<Id<T>, Vec<String>>,
+}
+impl<T: Indexed> Default for SearchIndex<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T: Indexed> SearchIndex<T> {
+    pub fn new() -> Self {
+        Self {
+            fields: T::search_fields(),
+            tokens: BTreeMap::new(),
+            rows: HashMap::new(),
+        }
+    }
+    /// Index data of row id, replacing a previous version of the row
+    pub fn insert(&mut self, id: Id<T>, data: &T) {
+        self.remove(id);
+        let mut row = Vec::new();
+        for field in &self.fields {
+            for token in tokenize(&field.text(data)) {
+                *self.tokens.entry(token.clone())
+                    .or_default()
+                    .entry(id)
+                    .or_default() += field.weight;
+                row.push(token);
+            }
+        }
+        self.rows.insert(id, row);
+    }
+    pub fn remove(&mut self, id: Id<T>) {
+        for token in self.rows.remove(&id).unwrap_or_default() {
+            if let Some(ids) = self.tokens.get_mut(&token) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.tokens.remove(&token);
+                }
+            }
+        }
+    }
+    /// Scores of the rows matching token exactly or by prefix
+    fn matches(&self, token: &str) -> HashMap<Id<T>, f32> {
+        let mut scores: HashMap<Id<T>, f32> = HashMap::new();
+        for (key, ids) in self.tokens.range(token.to_string()..) {
+            if !key.starts_with(token) {
+                break;
+            }
+            let factor = if key == token { 1.0 } else { PREFIX_FACTOR };
+            for (id, score) in ids {
+                *scores.entry(*id).or_default() += score * factor;
+            }
+        }
+        scores
+    }
+    /// Ids of the rows matching all tokens of query, best match first
+    pub fn search(&self, query: &str) -> Vec<(Id<T>, f32)> {
+        let mut tokens = tokenize(query).into_iter();
+        let mut scores = match tokens.next() {
+            Some(token) => self.matches(&token),
+            None => return Vec::new(),
+        };
+        for token in tokens {
+            let matches = self.matches(&token);
+            scores = scores.into_iter()
+                .filter_map(|(id, score)| matches.get(&id).map(|s| (id, score + s)))
+                .collect();
+        }
+        let mut results: Vec<(Id<T>, f32)> = scores.into_iter().collect();
+        results.sort_by(|(a, sa), (b, sb)| {
+            sb.partial_cmp(sa)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.to_string().cmp(&b.to_string()))
+        });
+        results
+    }
+}
+
+lazy_static! {
+    static ref SEARCH_INDEXES: RwLock<HashMap<(TypeId, TypeId), Arc<dyn Any + Send + Sync>>> = RwLock::new(HashMap::new());
+}
+fn built_search_index<T: Indexed>(key: &(TypeId, TypeId)) -> Option<Arc<RwLock<SearchIndex<T>>>> {
+    SEARCH_INDEXES.read().unwrap()
+        .get(key)
+        .map(|index| index.clone()
+            .downcast()
+            .expect("Search index type mismatch"))
+}
+/// Get the search index of table T in database D
+///
+/// The index is built from the stored rows on first access.
+pub fn search_index<'db, D, T>() -> Result<Arc<RwLock<SearchIndex<T>>>, Error>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    let key = (TypeId::of::<D>(), TypeId::of::<T>());
+    if let Some(index) = built_search_index(&key) {
+        return Ok(index);
+    }
+    // opening the storage may migrate the tables, which resets the indexes
+    let entries = D::storage()?.entries()?;
+    let mut indexes = SEARCH_INDEXES.write().unwrap();
+    let index = match indexes.entry(key) {
+        hash_map::Entry::Occupied(entry) => entry.get().clone(),
+        hash_map::Entry::Vacant(entry) => {
+            let mut index = SearchIndex::<T>::new();
+            for entry in entries {
+                index.insert(entry.id, entry.data());
+            }
+            entry.insert(Arc::new(RwLock::new(index))).clone()
+        }
+    };
+    Ok(index
+        .downcast()
+        .expect("Search index type mismatch"))
+}
+/// Drop the search index of table T in database D, so it is rebuilt from the stored rows on next access
+pub fn reset_search_index<'db, D, T>()
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    SEARCH_INDEXES.write().unwrap().remove(&(TypeId::of::<D>(), TypeId::of::<T>()));
+}
+/// Update the search index of table T in database D, if it was built already
+pub(crate) fn update_search_index<'db, D, T>(id: Id<T>, data: Option<&T>)
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    if let Some(index) = built_search_index::<T>(&(TypeId::of::<D>(), TypeId::of::<T>())) {
+        let mut index = index.write().unwrap();
+        match data {
+            Some(data) => index.insert(id, data),
+            None => index.remove(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    struct Doc {
+        title: String,
+        body: String,
+    }
+    impl Indexed for Doc {
+        fn search_fields() -> Vec<SearchField<Self>> {
+            vec![
+                SearchField::new("title", |doc: &Doc| doc.title.clone()).weight(2.0),
+                SearchField::new("body", |doc: &Doc| doc.body.clone()),
+            ]
+        }
+    }
+    fn doc(title: &str, body: &str) -> Doc {
+        Doc {
+            title: title.to_string(),
+            body: body.to_string(),
+        }
+    }
+    fn ids(index: &SearchIndex<Doc>, query: &str) -> Vec<Id<Doc>> {
+        index.search(query).into_iter().map(|(id, _)| id).collect()
+    }
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("Pay the rent, by 1st-May!"), vec!["pay", "the", "rent", "by", "1st", "may"]);
+    }
+    #[test]
+    fn ranked_prefix_search() {
+        let mut index = SearchIndex::<Doc>::new();
+        let a = Id::new();
+        let b = Id::new();
+        let c = Id::new();
+        index.insert(a, &doc("Budget report", "monthly numbers"));
+        index.insert(b, &doc("Groceries", "budget for the week"));
+        index.insert(c, &doc("Holidays", "plan the trip"));
+        assert_eq!(ids(&index, "budget"), vec![a, b]);
+        assert_eq!(ids(&index, "bud"), vec![a, b]);
+        assert_eq!(ids(&index, "budget week"), vec![b]);
+        assert!(ids(&index, "").is_empty());
+
+        index.insert(a, &doc("Report", "monthly numbers"));
+        assert_eq!(ids(&index, "budget"), vec![b]);
+        index.remove(b);
+        assert!(ids(&index, "budget").is_empty());
+    }
+}
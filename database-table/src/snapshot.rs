@@ -0,0 +1,527 @@
+use crate::{
+    migration::{
+        Migrations,
+        RawTables,
+        TableRows,
+    },
+    transaction::exclusive,
+    Database,
+    DatabaseTable,
+};
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{
+        self,
+        Display,
+    },
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Write,
+    },
+    marker::PhantomData,
+    path::{
+        Path,
+        PathBuf,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+#[allow(unused)]
+use tracing::{
+    error,
+    info,
+};
+
+const MANIFEST: &str = "manifest.json";
+const DEFAULT_INTERVAL: u64 = 60 * 60;
+const DEFAULT_KEEP: usize = 24;
+
+/// Ids and serialized data of the rows of a table
+type Rows = Vec<(String, Value)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotError {
+    pub table: Option<String>,
+    pub message: String,
+}
+impl SnapshotError {
+    fn new<S: ToString>(table: Option<&str>, message: S) -> Self {
+        Self {
+            table: table.map(ToString::to_string),
+            message: message.to_string(),
+        }
+    }
+}
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.table {
+            Some(table) => write!(f, "Snapshot of table {} failed: {}", table, self.message),
+            None => write!(f, "Snapshot failed: {}", self.message),
+        }
+    }
+}
+impl std::error::Error for SnapshotError {}
+
+/// Table of contents of a snapshot archive
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Seconds since the unix epoch
+    pub created: u64,
+    pub tables: Vec<SnapshotTable>,
+}
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotTable {
+    pub name: String,
+    /// Schema version of the rows, see `Migrations`
+    pub version: u32,
+    pub rows: usize,
+}
+impl Display for SnapshotManifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Snapshot created at {}:", self.created)?;
+        for table in &self.tables {
+            writeln!(f, "  {}: {} rows, version {}", table.name, table.rows, table.version)?;
+        }
+        Ok(())
+    }
+}
+/// Line of a table file in a snapshot archive
+#[derive(Serialize, Deserialize)]
+struct SnapshotRow {
+    id: String,
+    data: Value,
+}
+
+struct TableSource {
+    name: &'static str,
+    version: u32,
+    rows: fn() -> Result<Rows, String>,
+}
+fn table_rows<D, T>() -> Result<Rows, String>
+    where D: Database<'static, T>,
+          T: DatabaseTable<'static, D>,
+{
+    D::storage()
+        .and_then(|storage| storage.entries())
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|entry| {
+            serde_json::to_value(entry.data())
+                .map(|data| (entry.id().to_string(), data))
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+fn append<W: Write>(archive: &mut tar::Builder<W>, name: &str, data: &[u8], mtime: u64) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    archive.append_data(&mut header, name, data)
+}
+
+/// Point-in-time export of the tables of database D
+///
+/// The archive is a tar file with a `manifest.json` and a `<table>.jsonl` file
+/// for every table, with one row per line.
+pub struct Snapshot<D> {
+    tables: Vec<TableSource>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D: 'static> Default for Snapshot<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<D: 'static> Snapshot<D> {
+    pub fn new() -> Self {
+        Self {
+            tables: Vec::new(),
+            _db: PhantomData,
+        }
+    }
+    /// Include the table of T, stored under name with rows at schema version
+    pub fn table<T>(mut self, name: &'static str, version: u32) -> Self
+        where D: Database<'static, T>,
+              T: DatabaseTable<'static, D>,
+    {
+        self.tables.push(TableSource {
+            name,
+            version,
+            rows: table_rows::<D, T>,
+        });
+        self
+    }
+    /// Write all tables to an archive at path
+    ///
+    /// Writes and transactions wait until all tables are read, so the archive
+    /// never contains half of a transaction. The archive is written to a
+    /// temporary file first and replaces path when it is complete.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<SnapshotManifest, SnapshotError> {
+        let tables = {
+            let _exclusive = exclusive();
+            self.tables.iter()
+                .map(|table| {
+                    (table.rows)()
+                        .map(|rows| (table, rows))
+                        .map_err(|e| SnapshotError::new(Some(table.name), e))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let manifest = SnapshotManifest {
+            created: now(),
+            tables: tables.iter()
+                .map(|(table, rows)| SnapshotTable {
+                    name: table.name.to_string(),
+                    version: table.version,
+                    rows: rows.len(),
+                })
+                .collect(),
+        };
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+        let file = File::create(&partial)
+            .map_err(|e| SnapshotError::new(None, e))?;
+        let mut archive = tar::Builder::new(file);
+        let data = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| SnapshotError::new(None, e))?;
+        append(&mut archive, MANIFEST, &data, manifest.created)
+            .map_err(|e| SnapshotError::new(None, e))?;
+        for (table, rows) in tables {
+            let mut lines = Vec::new();
+            for (id, data) in rows {
+                serde_json::to_writer(&mut lines, &SnapshotRow { id, data })
+                    .map_err(|e| SnapshotError::new(Some(table.name), e))?;
+                lines.push(b'\n');
+            }
+            append(&mut archive, &format!("{}.jsonl", table.name), &lines, manifest.created)
+                .map_err(|e| SnapshotError::new(Some(table.name), e))?;
+        }
+        archive.into_inner()
+            .and_then(|mut file| file.flush())
+            .and_then(|_| fs::rename(&partial, path))
+            .map_err(|e| SnapshotError::new(None, e))?;
+        Ok(manifest)
+    }
+    /// Write a snapshot to the directory of schedule after every interval
+    pub fn schedule(self, schedule: SnapshotSchedule) -> JoinHandle<()> {
+        thread::spawn(move || {
+            if let Err(e) = fs::create_dir_all(&schedule.dir) {
+                error!("Failed to create snapshot directory {}: {}", schedule.dir.display(), e);
+                return;
+            }
+            loop {
+                thread::sleep(schedule.interval);
+                let path = schedule.dir.join(format!("snapshot-{}.tar", now()));
+                match self.write(&path) {
+                    Ok(_) => info!("Wrote snapshot {}", path.display()),
+                    Err(e) => error!("{}", e),
+                }
+                if let Err(e) = schedule.prune() {
+                    error!("Failed to remove old snapshots: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// Where and how often `Snapshot::schedule` writes snapshots
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotSchedule {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// Number of snapshots to keep, older ones are removed
+    pub keep: usize,
+}
+impl SnapshotSchedule {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            interval: Duration::from_secs(DEFAULT_INTERVAL),
+            keep: DEFAULT_KEEP,
+        }
+    }
+    /// Read the schedule from `<prefix>_SNAPSHOT_DIR`, `<prefix>_SNAPSHOT_INTERVAL`
+    /// in seconds and `<prefix>_SNAPSHOT_KEEP`
+    ///
+    /// Returns None if no directory is set.
+    pub fn from_env(prefix: &str) -> Result<Option<Self>, String> {
+        let var = |name: &str| std::env::var(format!("{}_SNAPSHOT_{}", prefix, name)).ok();
+        let mut schedule = match var("DIR") {
+            Some(dir) => Self::new(dir),
+            None => return Ok(None),
+        };
+        if let Some(interval) = var("INTERVAL") {
+            schedule.interval = interval.parse()
+                .map(Duration::from_secs)
+                .map_err(|e| format!("Invalid snapshot interval {}: {}", interval, e))?;
+        }
+        if let Some(keep) = var("KEEP") {
+            schedule.keep = keep.parse()
+                .map_err(|e| format!("Invalid snapshot count {}: {}", keep, e))?;
+        }
+        Ok(Some(schedule))
+    }
+    /// Snapshots in the directory, oldest first
+    pub fn snapshots(&self) -> io::Result<Vec<PathBuf>> {
+        let mut snapshots: Vec<(u64, PathBuf)> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let created = name.strip_prefix("snapshot-")?
+                    .strip_suffix(".tar")?
+                    .parse()
+                    .ok()?;
+                Some((created, entry.path()))
+            })
+            .collect();
+        snapshots.sort();
+        Ok(snapshots.into_iter().map(|(_, path)| path).collect())
+    }
+    fn prune(&self) -> io::Result<()> {
+        let snapshots = self.snapshots()?;
+        let remove = snapshots.len().saturating_sub(self.keep);
+        for path in &snapshots[..remove] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_archive(path: &Path) -> Result<(SnapshotManifest, HashMap<String, Rows>), SnapshotError> {
+    let file = File::open(path)
+        .map_err(|e| SnapshotError::new(None, e))?;
+    let mut archive = tar::Archive::new(file);
+    let mut manifest = None;
+    let mut tables = HashMap::new();
+    for entry in archive.entries().map_err(|e| SnapshotError::new(None, e))? {
+        let entry = entry.map_err(|e| SnapshotError::new(None, e))?;
+        let name = entry.path()
+            .map_err(|e| SnapshotError::new(None, e))?
+            .to_string_lossy()
+            .to_string();
+        if name == MANIFEST {
+            manifest = Some(serde_json::from_reader(entry).map_err(|e| SnapshotError::new(None, e))?);
+        } else if let Some(table) = name.strip_suffix(".jsonl") {
+            let rows = BufReader::new(entry)
+                .lines()
+                .map(|line| {
+                    let line = line.map_err(|e| e.to_string())?;
+                    serde_json::from_str::<SnapshotRow>(&line)
+                        .map(|row| (row.id, row.data))
+                        .map_err(|e| e.to_string())
+                })
+                .collect::<Result<Rows, _>>()
+                .map_err(|e| SnapshotError::new(Some(table), e))?;
+            tables.insert(table.to_string(), rows);
+        }
+    }
+    let manifest = manifest.ok_or_else(|| SnapshotError::new(None, "Missing manifest"))?;
+    Ok((manifest, tables))
+}
+/// Replace the contents of tables with the snapshot at path
+///
+/// The rows of all tables are migrated to their current versions and checked
+/// against tables, then all tables are replaced at once, see `RawTables::replace_tables`.
+/// Restore while the server is stopped, a running server does not see the restored rows.
+pub fn restore<P: AsRef<Path>>(tables: &dyn RawTables, migrations: &Migrations, path: P) -> Result<SnapshotManifest, SnapshotError> {
+    let (manifest, mut rows) = read_archive(path.as_ref())?;
+    let replacements = manifest.tables.iter()
+        .map(|table| {
+            let rows = match rows.remove(&table.name) {
+                Some(rows) if rows.len() == table.rows => rows,
+                Some(rows) => return Err(SnapshotError::new(
+                    Some(&table.name),
+                    format!("Found {} of {} rows", rows.len(), table.rows),
+                )),
+                None => return Err(SnapshotError::new(Some(&table.name), "Missing table file")),
+            };
+            let rows = migrations.migrate_rows(&table.name, table.version, rows)
+                .map_err(|e| SnapshotError::new(Some(&table.name), e))?;
+            tables.check_rows(&table.name, &rows)
+                .map_err(|e| SnapshotError::new(Some(&table.name), e))?;
+            Ok(TableRows {
+                table: table.name.clone(),
+                version: migrations.version(&table.name),
+                rows,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    tables.replace_tables(replacements)
+        .map_err(|e| SnapshotError::new(None, e))?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::*,
+        migration::RqlRawTables,
+        policy::*,
+        relation::*,
+        storage::*,
+        Error,
+    };
+    use lazy_static::lazy_static;
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+    }
+    impl Indexed for Note {}
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Tag {
+        name: String,
+    }
+    impl Indexed for Tag {}
+    lazy_static! {
+        static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+        static ref NOTES: SqliteTable<Note> = DB.table("note").unwrap();
+        static ref TAGS: SqliteTable<Tag> = DB.table("tag").unwrap();
+    }
+    struct Schema;
+    impl Database<'static, Note> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Note>, Error> {
+            Ok(&*NOTES)
+        }
+    }
+    impl Database<'static, Tag> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Tag>, Error> {
+            Ok(&*TAGS)
+        }
+    }
+    impl Referenced<'static, Schema> for Note {}
+    impl Authorized<Schema> for Note {}
+    impl Referenced<'static, Schema> for Tag {}
+    impl Authorized<Schema> for Tag {}
+    /// Current rows of the tables, stored by rql
+    mod stored {
+        use serde::{
+            Serialize,
+            Deserialize,
+        };
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        pub struct Note {
+            pub text: String,
+            pub words: u32,
+        }
+        #[derive(Serialize, Deserialize)]
+        pub struct Tag {
+            pub name: String,
+            pub color: String,
+        }
+        rql::schema! {
+            pub Notes {
+                note: Note,
+                tag: Tag,
+            }
+        }
+    }
+    fn migrations() -> Migrations {
+        Migrations::new()
+            .register("note", 0, |_| Ok(()))
+            .register("note", 1, |note| {
+                note["words"] = serde_json::json!(1);
+                Ok(())
+            })
+    }
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("database-table-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    #[test]
+    fn write_and_restore() {
+        let id = <Note as DatabaseTable<'_, Schema>>::insert(Note { text: "a".into() }).unwrap();
+        <Tag as DatabaseTable<'_, Schema>>::insert(Tag { name: "b".into() }).unwrap();
+        let dir = temp_dir("snapshot");
+        let path = dir.join("snapshot.tar");
+        let manifest = Snapshot::<Schema>::new()
+            .table::<Note>("note", 1)
+            .table::<Tag>("tag", 0)
+            .write(&path)
+            .unwrap();
+        assert_eq!(manifest.tables[0].rows, 1);
+
+        // rows are migrated from the versions in the snapshot
+        let restored = SqliteDatabase::open_in_memory().unwrap();
+        restored.replace_rows("note", vec![("old".into(), Value::Null)]).unwrap();
+        assert_eq!(restore(&restored, &migrations(), &path).unwrap(), manifest);
+        assert_eq!(
+            restored.rows("note").unwrap(),
+            vec![(id.to_string(), serde_json::json!({ "text": "a", "words": 1 }))]
+        );
+        assert_eq!(restored.version("note"), Ok(2));
+
+        // rql tables are only replaced when the rows of all tables decode
+        let name = dir.join("rql");
+        let name = name.to_str().unwrap();
+        {
+            let db = stored::Notes::new(name, rql::BinaryStable).unwrap();
+            let tables = RqlRawTables::new(dir.join("versions.json"))
+                .table("note", || db.note(), || db.note_mut())
+                .table("tag", || db.tag(), || db.tag_mut());
+            let error = restore(&tables, &migrations(), &path).unwrap_err();
+            assert_eq!(error.table, Some("tag".into()));
+            assert_eq!(db.note().rows().count(), 0);
+
+            let migrations = migrations()
+                .register("tag", 0, |tag| {
+                    tag["color"] = serde_json::json!("red");
+                    Ok(())
+                });
+            restore(&tables, &migrations, &path).unwrap();
+            assert_eq!(tables.version("tag"), Ok(1));
+        }
+        let db = stored::Notes::new(name, rql::BinaryStable).unwrap();
+        assert_eq!(
+            db.note().get(id.to_string().parse().unwrap()),
+            Some(&stored::Note {
+                text: "a".into(),
+                words: 1,
+            })
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn prune_oldest() {
+        let dir = temp_dir("prune");
+        for created in &[3, 1, 20] {
+            File::create(dir.join(format!("snapshot-{}.tar", created))).unwrap();
+        }
+        let schedule = SnapshotSchedule {
+            keep: 2,
+            ..SnapshotSchedule::new(&dir)
+        };
+        schedule.prune().unwrap();
+        assert_eq!(
+            schedule.snapshots().unwrap(),
+            vec![dir.join("snapshot-3.tar"), dir.join("snapshot-20.tar")]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use rql::*;
+use crate::{
+    entry::*,
+    Error,
+};
+use serde::{
+    Serialize,
+    de::DeserializeOwned,
+};
+
+pub mod rql_storage;
+pub use rql_storage::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sqlite;
+#[cfg(not(target_arch = "wasm32"))]
+pub use sqlite::*;
+
+/// Row storage of a table of T
+pub trait TableStorage<T>: Send + Sync {
+    fn insert(&self, data: T) -> Result<Id<T>, Error>;
+    fn get(&self, id: Id<T>) -> Result<Option<T>, Error>;
+    /// Replace the data of an existing row, returning the previous data
+    fn update(&self, id: Id<T>, data: T) -> Result<Option<T>, Error>;
+    fn delete(&self, id: Id<T>) -> Result<Option<T>, Error>;
+    fn entries(&self) -> Result<Vec<Entry<T>>, Error>;
+    fn filter(&self, f: &dyn Fn(&T) -> bool) -> Result<Vec<Entry<T>>, Error> {
+        Ok(self.entries()?
+            .into_iter()
+            .filter(|entry| f(entry.data()))
+            .collect())
+    }
+    fn find(&self, f: &dyn Fn(&T) -> bool) -> Result<Option<Entry<T>>, Error> {
+        Ok(self.entries()?
+            .into_iter()
+            .find(|entry| f(entry.data())))
+    }
+}
+
+/// Storage backend selected at startup
+pub enum Backend {
+    /// Tables of an rql Schema
+    Rql,
+    /// Tables in a SQLite database file
+    #[cfg(not(target_arch = "wasm32"))]
+    Sqlite(SqliteDatabase),
+}
+impl Backend {
+    /// Open a backend from its configuration, either `rql` or `sqlite:<path>`
+    pub fn open(config: &str) -> Result<Self, Error> {
+        match config.split_once(':') {
+            None if config == "rql" => Ok(Self::Rql),
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(("sqlite", path)) => SqliteDatabase::open(path)
+                .map(Self::Sqlite)
+                .map_err(|e| Error::Storage(format!("Failed to open SQLite database {}: {}", path, e))),
+            _ => Err(Error::Storage(format!("Unknown database backend \"{}\"", config))),
+        }
+    }
+    /// Open the backend configured in environment variable var, rql if unset
+    pub fn from_env(var: &str) -> Result<Self, Error> {
+        match std::env::var(var) {
+            Ok(config) => Self::open(&config),
+            Err(_) => Ok(Self::Rql),
+        }
+    }
+    /// Storage for the table with name, using the rql table when the rql backend is selected
+    pub fn table<T>(&self, name: &str, rql: RqlStorage<'static, T>) -> Result<Box<dyn TableStorage<T>>, Error>
+        where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static
+    {
+        match self {
+            Self::Rql => {
+                let _ = name;
+                Ok(Box::new(rql))
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Sqlite(db) => db.table(name)
+                .map(|table| Box::new(table) as Box<dyn TableStorage<T>>)
+                .map_err(|e| Error::Storage(format!("Failed to create SQLite table {}: {}", name, e))),
+        }
+    }
+}
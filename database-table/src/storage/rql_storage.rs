@@ -0,0 +1,65 @@
+use rql::*;
+use crate::{
+    entry::*,
+    storage::TableStorage,
+    Error,
+};
+
+/// TableStorage for a table of an rql Schema
+pub struct RqlStorage<'db, T> {
+    table: fn() -> TableGuard<'db, T>,
+    table_mut: fn() -> TableGuardMut<'db, T>,
+}
+impl<'db, T> RqlStorage<'db, T> {
+    pub fn new(table: fn() -> TableGuard<'db, T>, table_mut: fn() -> TableGuardMut<'db, T>) -> Self {
+        Self {
+            table,
+            table_mut,
+        }
+    }
+    pub fn table(&self) -> TableGuard<'db, T> {
+        (self.table)()
+    }
+    pub fn table_mut(&self) -> TableGuardMut<'db, T> {
+        (self.table_mut)()
+    }
+}
+impl<'db, T> TableStorage<T> for RqlStorage<'db, T>
+    where T: Clone + Send + Sync
+{
+    fn insert(&self, data: T) -> Result<Id<T>, Error> {
+        Ok(self.table_mut()
+            .insert(data))
+    }
+    fn get(&self, id: Id<T>) -> Result<Option<T>, Error> {
+        Ok(self.table()
+            .get(id)
+            .cloned())
+    }
+    fn update(&self, id: Id<T>, data: T) -> Result<Option<T>, Error> {
+        Ok(self.table_mut()
+            .get_mut(id)
+            .map(|row| std::mem::replace(row, data)))
+    }
+    fn delete(&self, id: Id<T>) -> Result<Option<T>, Error> {
+        Ok(self.table_mut()
+            .delete_one(id))
+    }
+    fn entries(&self) -> Result<Vec<Entry<T>>, Error> {
+        Ok(self.table()
+            .rows()
+            .map(|row| row.into())
+            .collect())
+    }
+    fn filter(&self, f: &dyn Fn(&T) -> bool) -> Result<Vec<Entry<T>>, Error> {
+        Ok(self.table()
+            .wher(|row| f(row.data))
+            .map(|row| row.into())
+            .collect())
+    }
+    fn find(&self, f: &dyn Fn(&T) -> bool) -> Result<Option<Entry<T>>, Error> {
+        Ok(self.table()
+            .find(|row| f(row.data))
+            .map(|row| row.into()))
+    }
+}
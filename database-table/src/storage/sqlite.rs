@@ -0,0 +1,316 @@
+use rql::*;
+use crate::{
+    entry::*,
+    migration::{
+        RawTables,
+        TableRows,
+    },
+    storage::TableStorage,
+    Error,
+};
+use rusqlite::{
+    params,
+    Connection,
+    OptionalExtension,
+    TransactionBehavior,
+};
+use serde::{
+    Serialize,
+    de::DeserializeOwned,
+};
+use serde_json::Value;
+use std::{
+    marker::PhantomData,
+    path::Path,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// Connection to a SQLite database file
+///
+/// Every table type is stored in its own SQL table, with rows serialized to JSON.
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    connection: Arc<Mutex<Connection>>,
+}
+impl SqliteDatabase {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Connection::open(path).map(Self::from)
+    }
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Connection::open_in_memory().map(Self::from)
+    }
+    /// Open table with name, creating it if it does not exist
+    pub fn table<T>(&self, name: &str) -> rusqlite::Result<SqliteTable<T>> {
+        Ok(SqliteTable {
+            db: self.clone(),
+            name: self.create_table(name)?,
+            _ty: PhantomData,
+        })
+    }
+    fn create_table(&self, name: &str) -> rusqlite::Result<String> {
+        let name: String = name.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+        self.connection().execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS \"{}\" (id TEXT PRIMARY KEY NOT NULL, data TEXT NOT NULL)",
+                name,
+            ),
+            params![],
+        )?;
+        Ok(name)
+    }
+    fn create_versions_table(&self) -> rusqlite::Result<()> {
+        self.connection().execute(
+            "CREATE TABLE IF NOT EXISTS schema_versions (name TEXT PRIMARY KEY NOT NULL, version INTEGER NOT NULL)",
+            params![],
+        )
+        .map(|_| ())
+    }
+    pub fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().expect("SQLite connection poisoned")
+    }
+    /// Replace all rows of table name in transaction
+    fn replace_in(transaction: &Connection, name: &str, rows: Vec<(String, Value)>) -> rusqlite::Result<()> {
+        transaction.execute(&format!("DELETE FROM \"{}\"", name), params![])?;
+        for (id, data) in rows {
+            transaction.execute(
+                &format!("INSERT INTO \"{}\" (id, data) VALUES (?1, ?2)", name),
+                params![id, data.to_string()],
+            )?;
+        }
+        Ok(())
+    }
+}
+impl From<Connection> for SqliteDatabase {
+    fn from(connection: Connection) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(connection)),
+        }
+    }
+}
+/// TableStorage for a table in a SqliteDatabase
+pub struct SqliteTable<T> {
+    db: SqliteDatabase,
+    name: String,
+    _ty: PhantomData<fn() -> T>,
+}
+impl<T> SqliteTable<T>
+    where T: Serialize + DeserializeOwned
+{
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_json(data: &T) -> Result<String, Error> {
+        Ok(serde_json::to_string(data)?)
+    }
+    fn from_json(data: &str) -> Result<T, Error> {
+        Ok(serde_json::from_str(data)?)
+    }
+    fn select(&self, connection: &Connection, id: Id<T>) -> Result<Option<T>, Error> {
+        connection.query_row(
+            &format!("SELECT data FROM \"{}\" WHERE id = ?1", self.name),
+            params![id.to_string()],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|data| Self::from_json(&data))
+        .transpose()
+    }
+    /// Run f in a transaction holding the write lock of the database file
+    /// from its first read, so other connections can't write in between
+    fn write<R>(&self, f: impl FnOnce(&Connection) -> Result<R, Error>) -> Result<R, Error> {
+        let mut connection = self.db.connection();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let result = f(&transaction)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+}
+impl<T> TableStorage<T> for SqliteTable<T>
+    where T: Serialize + DeserializeOwned
+{
+    fn insert(&self, data: T) -> Result<Id<T>, Error> {
+        let id = Id::new();
+        self.db.connection().execute(
+            &format!("INSERT INTO \"{}\" (id, data) VALUES (?1, ?2)", self.name),
+            params![id.to_string(), Self::to_json(&data)?],
+        )?;
+        Ok(id)
+    }
+    fn get(&self, id: Id<T>) -> Result<Option<T>, Error> {
+        self.select(&self.db.connection(), id)
+    }
+    fn update(&self, id: Id<T>, data: T) -> Result<Option<T>, Error> {
+        let data = Self::to_json(&data)?;
+        self.write(|connection| {
+            let old = match self.select(connection, id)? {
+                Some(old) => old,
+                None => return Ok(None),
+            };
+            connection.execute(
+                &format!("UPDATE \"{}\" SET data = ?2 WHERE id = ?1", self.name),
+                params![id.to_string(), data],
+            )?;
+            Ok(Some(old))
+        })
+    }
+    fn delete(&self, id: Id<T>) -> Result<Option<T>, Error> {
+        self.write(|connection| {
+            let data = match self.select(connection, id)? {
+                Some(data) => data,
+                None => return Ok(None),
+            };
+            connection.execute(
+                &format!("DELETE FROM \"{}\" WHERE id = ?1", self.name),
+                params![id.to_string()],
+            )?;
+            Ok(Some(data))
+        })
+    }
+    fn entries(&self) -> Result<Vec<Entry<T>>, Error> {
+        let connection = self.db.connection();
+        let mut statement = connection
+            .prepare(&format!("SELECT id, data FROM \"{}\"", self.name))?;
+        let rows = statement
+            .query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        rows.map(|row| {
+                let (id, data) = row?;
+                let id = id.parse()
+                    .map_err(|_| Error::Serialization(format!("Invalid row id \"{}\"", id)))?;
+                Ok(Entry::new(id, Self::from_json(&data)?))
+            })
+            .collect()
+    }
+}
+impl RawTables for SqliteDatabase {
+    fn rows(&self, table: &str) -> Result<Vec<(String, Value)>, String> {
+        let name = self.create_table(table).map_err(|e| e.to_string())?;
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare(&format!("SELECT id, data FROM \"{}\"", name))
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.map(|row| {
+            let (id, data) = row.map_err(|e| e.to_string())?;
+            serde_json::from_str(&data)
+                .map(|data| (id, data))
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+    }
+    fn write_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String> {
+        let name = self.create_table(table).map_err(|e| e.to_string())?;
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        for (id, data) in rows {
+            transaction.execute(
+                &format!("UPDATE \"{}\" SET data = ?2 WHERE id = ?1", name),
+                params![id, data.to_string()],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+    fn replace_rows(&self, table: &str, rows: Vec<(String, Value)>) -> Result<(), String> {
+        let name = self.create_table(table).map_err(|e| e.to_string())?;
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        Self::replace_in(&transaction, &name, rows).map_err(|e| e.to_string())?;
+        transaction.commit().map_err(|e| e.to_string())
+    }
+    /// Replaces the rows and versions of all tables in one SQL transaction
+    fn replace_tables(&self, tables: Vec<TableRows>) -> Result<(), String> {
+        self.create_versions_table().map_err(|e| e.to_string())?;
+        let names = tables.iter()
+            .map(|table| self.create_table(&table.table))
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(|e| e.to_string())?;
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        for (name, TableRows { table, version, rows }) in names.into_iter().zip(tables) {
+            Self::replace_in(&transaction, &name, rows)
+                .and_then(|_| transaction.execute(
+                    "INSERT OR REPLACE INTO schema_versions (name, version) VALUES (?1, ?2)",
+                    params![table, version],
+                ))
+                .map_err(|e| format!("Table {}: {}", table, e))?;
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+    fn version(&self, table: &str) -> Result<u32, String> {
+        self.create_versions_table().map_err(|e| e.to_string())?;
+        self.connection().query_row(
+            "SELECT version FROM schema_versions WHERE name = ?1",
+            params![table],
+            |row| row.get::<_, u32>(0),
+        )
+        .optional()
+        .map(|version| version.unwrap_or(0))
+        .map_err(|e| e.to_string())
+    }
+    fn set_version(&self, table: &str, version: u32) -> Result<(), String> {
+        self.create_versions_table().map_err(|e| e.to_string())?;
+        self.connection().execute(
+            "INSERT OR REPLACE INTO schema_versions (name, version) VALUES (?1, ?2)",
+            params![table, version],
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+    }
+    #[test]
+    fn insert_get_delete() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let notes = db.table::<Note>("note").unwrap();
+        let note = Note {
+            text: "hello".into(),
+        };
+        let id = notes.insert(note.clone()).unwrap();
+        assert_eq!(notes.get(id), Ok(Some(note.clone())));
+        assert_eq!(notes.entries().unwrap().len(), 1);
+        assert_eq!(notes.find(&|n| n.text == "hello").unwrap().map(|e| e.id), Some(id));
+        let edited = Note {
+            text: "edited".into(),
+        };
+        assert_eq!(notes.update(id, edited.clone()), Ok(Some(note)));
+        assert_eq!(notes.update(Id::new(), edited.clone()), Ok(None));
+        assert_eq!(notes.delete(id), Ok(Some(edited)));
+        assert_eq!(notes.get(id), Ok(None));
+        assert!(notes.entries().unwrap().is_empty());
+    }
+    #[test]
+    fn replace_tables_atomically() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        let row = |id: &str| (id.to_string(), serde_json::json!({ "text": id }));
+        db.replace_rows("note", vec![row("a")]).unwrap();
+        // duplicate ids fail the second table
+        let result = db.replace_tables(vec![
+            TableRows {
+                table: "note".into(),
+                version: 1,
+                rows: vec![row("b")],
+            },
+            TableRows {
+                table: "tag".into(),
+                version: 1,
+                rows: vec![row("c"), row("c")],
+            },
+        ]);
+        assert!(result.is_err());
+        assert_eq!(db.rows("note"), Ok(vec![row("a")]));
+        assert_eq!(db.version("note"), Ok(0));
+    }
+}
@@ -2,6 +2,13 @@ use rql::*;
 use crate::{
     entry::*,
     route::*,
+    index::*,
+    policy::*,
+    query::*,
+    relation::*,
+    storage::*,
+    watch::*,
+    Error,
 };
 use async_trait::async_trait;
 use std::fmt::Debug;
@@ -26,7 +33,39 @@ use seed::{
         Method,
     },
 };
-use std::result::Result;
+use futures::{
+    channel::mpsc::{
+        unbounded,
+        UnboundedReceiver,
+        UnboundedSender,
+    },
+    stream::BoxStream,
+    Stream,
+    StreamExt,
+};
+use wasm_bindgen::{
+    closure::Closure,
+    JsCast,
+};
+use web_sys::{
+    EventSource,
+    MessageEvent,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    marker::PhantomData,
+    pin::Pin,
+    rc::{
+        Rc,
+        Weak,
+    },
+    result::Result,
+    task::{
+        Context,
+        Poll,
+    },
+};
 pub trait Routed : TableRoutable + Sized {
     type AbsoluteRoute: Route;
     fn to_absolute_route(route: <Self as TableRoutable>::Route) -> Self::AbsoluteRoute;
@@ -41,17 +80,112 @@ pub trait RemoteTable<T=Self>
     type Error: Debug + Clone;
     async fn get(id: Id<T>) -> Result<Option<Entry<T>>, Self::Error>;
     async fn delete(id: Id<T>) -> Result<Option<T>, Self::Error>;
-    async fn get_all() -> Result<Vec<Entry<T>>, Self::Error>;
+    async fn get_all(query: ListQuery) -> Result<Page<T>, Self::Error>;
     //async fn update(id: Id<Self>, update: <Self as Updatable>::Update) -> Result<Option<Self>, String>;
     async fn post(data: T) -> Result<Id<T>, Self::Error>;
+    /// Rows matching query by full-text search, best match first
+    async fn search(query: String) -> Result<Vec<Entry<T>>, Self::Error>;
+    /// Changes of the table, pushed by the server as they happen
+    fn watch() -> Result<RemoteChanges<T>, Self::Error>;
+}
+/// EventSource of a table, shared by all of its `RemoteChanges`
+struct SharedSource {
+    source: EventSource,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    /// Senders of the event data to the open streams
+    watchers: Rc<RefCell<Vec<UnboundedSender<String>>>>,
+}
+impl SharedSource {
+    fn open(url: &str) -> Result<Self, String> {
+        let source = EventSource::new(url)
+            .map_err(|e| format!("Failed to open EventSource {}: {:?}", url, e))?;
+        let watchers: Rc<RefCell<Vec<UnboundedSender<String>>>> = Default::default();
+        let senders = watchers.clone();
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            match event.data().as_string() {
+                Some(data) => senders.borrow_mut()
+                    .retain(|sender| sender.unbounded_send(data.clone()).is_ok()),
+                None => error!("Change event without data"),
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        source.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        Ok(Self {
+            source,
+            _on_message: on_message,
+            watchers,
+        })
+    }
+}
+impl Drop for SharedSource {
+    fn drop(&mut self) {
+        self.source.close();
+    }
 }
-async fn fetch<V>(request: Request<'_>) -> Result<V, String>
+thread_local! {
+    /// Open event sources by URL
+    static SOURCES: RefCell<HashMap<String, Weak<SharedSource>>> = RefCell::new(HashMap::new());
+}
+/// Stream of the changes of a remote table, received as server-sent events
+///
+/// All streams of a table share one event source, which is closed when the
+/// last of them is dropped.
+pub struct RemoteChanges<T> {
+    _source: Rc<SharedSource>,
+    events: UnboundedReceiver<String>,
+    _ty: PhantomData<fn() -> T>,
+}
+impl<T> RemoteChanges<T>
+    where T: for<'de> Deserialize<'de> + 'static
+{
+    pub fn open(url: &str) -> Result<Self, String> {
+        let source = SOURCES.with(|sources| {
+            let mut sources = sources.borrow_mut();
+            if let Some(source) = sources.get(url).and_then(Weak::upgrade) {
+                return Ok(source);
+            }
+            sources.retain(|_, source| source.strong_count() > 0);
+            let source = Rc::new(SharedSource::open(url)?);
+            sources.insert(url.to_string(), Rc::downgrade(&source));
+            Ok::<_, String>(source)
+        })?;
+        let (sender, events) = unbounded();
+        source.watchers.borrow_mut().push(sender);
+        Ok(Self {
+            _source: source,
+            events,
+            _ty: PhantomData,
+        })
+    }
+}
+impl<T> Stream for RemoteChanges<T>
+    where T: for<'de> Deserialize<'de>
+{
+    type Item = Change<T>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(data)) => match serde_json::from_str(&data) {
+                    Ok(change) => return Poll::Ready(Some(change)),
+                    Err(e) => error!("Failed to decode change event: {}", e),
+                },
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+/// Send request and decode the response, or the error of an error status
+async fn fetch<V>(request: Request<'_>) -> Result<V, Error>
     where V: 'static + for<'de> Deserialize<'de>,
 {
-    seed_fetch(request).await
-        .map_err(|e| format!("Fetch error: {:?}", e))?
-        .json().await
-        .map_err(|e| format!("Value error: {:?}", e))
+    let response = seed_fetch(request).await
+        .map_err(|e| Error::Storage(format!("Fetch error: {:?}", e)))?;
+    let status = response.status();
+    if !status.is_ok() {
+        return Err(Error::from_status(status.code, status.text));
+    }
+    response.json().await
+        .map_err(|e| Error::Serialization(format!("{:?}", e)))
 }
 #[async_trait(?Send)]
 impl<T> RemoteTable for T
@@ -61,8 +195,9 @@ impl<T> RemoteTable for T
           + for<'de> Deserialize<'de>
           + Serialize
           + Routed
+          + 'static
 {
-    type Error = String;
+    type Error = Error;
     async fn get(id: Id<Self>) -> Result<Option<Entry<Self>>, Self::Error> {
         let path = Self::to_absolute_route(Self::entry_route(id)).as_path();
         debug!("RemoteTable::get {}", path);
@@ -79,8 +214,12 @@ impl<T> RemoteTable for T
                 .method(Method::Delete)
         ).await
     }
-    async fn get_all() -> Result<Vec<Entry<Self>>, Self::Error> {
-        let path = Self::to_absolute_route(Self::table_route()).as_path();
+    async fn get_all(query: ListQuery) -> Result<Page<Self>, Self::Error> {
+        let path = format!(
+            "{}?{}",
+            Self::to_absolute_route(Self::table_route()).as_path(),
+            query.to_query_string(),
+        );
         debug!("RemoteTable::get_all {}", path);
         fetch(
             Request::new(path)
@@ -94,9 +233,27 @@ impl<T> RemoteTable for T
             Request::new(path)
                 .method(Method::Post)
                 .json(&data)
-                .map_err(|e| format!("{:?}", e))?
+                .map_err(|e| Error::Serialization(format!("{:?}", e)))?
         ).await
     }
+    async fn search(query: String) -> Result<Vec<Entry<Self>>, Self::Error> {
+        let path = format!(
+            "{}/search?q={}",
+            Self::to_absolute_route(Self::table_route()).as_path(),
+            encode(&query),
+        );
+        debug!("RemoteTable::search {}", path);
+        fetch(
+            Request::new(path)
+                .method(Method::Get)
+        ).await
+    }
+    fn watch() -> Result<RemoteChanges<Self>, Self::Error> {
+        let path = format!("{}/changes", Self::to_absolute_route(Self::table_route()).as_path());
+        debug!("RemoteTable::watch {}", path);
+        RemoteChanges::open(&path)
+            .map_err(Error::Storage)
+    }
 }
 // todo when specialization is stable
 //#[async_trait(?Send)]
@@ -108,8 +265,8 @@ impl<T> RemoteTable for T
 //  async fn delete(id: Id<T>) -> Result<Option<T>, Self::Error> {
 //      T::delete(id).await
 //  }
-//  async fn get_all() -> Result<Vec<Entry<T>>, Self::Error> {
-//      T::get_all().await
+//  async fn get_all(query: ListQuery) -> Result<Page<T>, Self::Error> {
+//      T::get_all(query).await
 //  }
 //  async fn post(data: T) -> Result<Id<T>, Self::Error> {
 //      T::post(data).await
@@ -120,40 +277,91 @@ pub trait DatabaseTable<'db, D>
     + Clone
     + Serialize
     + for<'de> Deserialize<'de>
+    + Indexed
+    + Referenced<'db, D>
+    + Authorized<D>
     + 'db
     where D: crate::Database<'db, Self>,
 {
-    fn table() -> TableGuard<'db, Self> {
-        D::table()
-    }
-    fn table_mut() -> TableGuardMut<'db, Self> {
-        D::table_mut()
+    fn storage() -> Result<&'db dyn TableStorage<Self>, Error> {
+        D::storage()
     }
-    fn insert(obj: Self) -> Id<Self> {
+    fn insert(obj: Self) -> Result<Id<Self>, Error> {
         D::insert(obj)
     }
-    fn get(id: Id<Self>) -> Option<Entry<Self>> {
+    fn get(id: Id<Self>) -> Result<Option<Entry<Self>>, Error> {
         D::get(id)
     }
-    fn delete(id: Id<Self>) -> Option<Self> {
+    fn update<F>(id: Id<Self>, f: F) -> Result<Option<Entry<Self>>, Error>
+        where F: FnOnce(&mut Self)
+    {
+        D::update(id, f)
+    }
+    fn delete(id: Id<Self>) -> Result<Option<Self>, Error> {
         D::delete(id)
     }
-    fn get_all() -> Vec<Entry<Self>> {
+    fn dangling_references() -> Result<Vec<DanglingReference>, Error> {
+        D::dangling_references()
+    }
+    fn search(query: &str) -> Result<Vec<Entry<Self>>, Error> {
+        D::search(query)
+    }
+    fn watch() -> Changes<Self> {
+        D::watch()
+    }
+    fn get_all() -> Result<Vec<Entry<Self>>, Error> {
         D::get_all()
     }
-    fn get_list(ids: Vec<Id<Self>>) -> Vec<Entry<Self>> {
+    fn list(query: ListQuery) -> Result<Page<Self>, Error> {
+        D::list(query)
+    }
+    fn get_list(ids: Vec<Id<Self>>) -> Result<Vec<Entry<Self>>, Error> {
         D::get_list(ids)
     }
-    fn filter<F>(f: F) -> Vec<Entry<Self>>
+    fn filter<F>(f: F) -> Result<Vec<Entry<Self>>, Error>
         where F: Fn(&Self) -> bool
     {
         D::filter(f)
     }
-    fn find<F>(f: F) -> Option<Entry<Self>>
+    fn find<F>(f: F) -> Result<Option<Entry<Self>>, Error>
         where F: Fn(&Self) -> bool
     {
         D::find(f)
     }
+    fn filter_by_index<K: ToString>(index: &str, key: K) -> Result<Vec<Entry<Self>>, Error> {
+        D::filter_by_index(index, key)
+    }
+    fn find_by_index<K: ToString>(index: &str, key: K) -> Result<Option<Entry<Self>>, Error> {
+        D::find_by_index(index, key)
+    }
+    fn get_as(principal: &Principal, id: Id<Self>) -> Result<Option<Entry<Self>>, Error> {
+        D::get_as(principal, id)
+    }
+    fn get_list_as(principal: &Principal, ids: Vec<Id<Self>>) -> Result<Vec<Entry<Self>>, Error> {
+        D::get_list_as(principal, ids)
+    }
+    fn list_as(principal: &Principal, query: ListQuery) -> Result<Page<Self>, Error> {
+        D::list_as(principal, query)
+    }
+    fn search_as(principal: &Principal, query: &str) -> Result<Vec<Entry<Self>>, Error> {
+        D::search_as(principal, query)
+    }
+    fn insert_as(principal: &Principal, obj: Self) -> Result<Id<Self>, Error> {
+        D::insert_as(principal, obj)
+    }
+    fn update_as<F>(principal: &Principal, id: Id<Self>, f: F) -> Result<Option<Entry<Self>>, Error>
+        where F: FnOnce(&mut Self)
+    {
+        D::update_as(principal, id, f)
+    }
+    fn delete_as(principal: &Principal, id: Id<Self>) -> Result<Option<Self>, Error> {
+        D::delete_as(principal, id)
+    }
+    fn watch_as(principal: &Principal) -> BoxStream<'static, Change<Self>>
+        where Self: Send + 'static
+    {
+        D::watch_as(principal)
+    }
 }
 impl<'db, T, D> DatabaseTable<'db, D> for T
     where D: crate::Database<'db, T>,
@@ -161,5 +369,8 @@ impl<'db, T, D> DatabaseTable<'db, D> for T
           + Clone
           + Serialize
           + for<'de> Deserialize<'de>
+          + Indexed
+          + Referenced<'db, D>
+          + Authorized<D>
           + 'db
 {}
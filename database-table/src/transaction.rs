@@ -0,0 +1,364 @@
+use rql::*;
+use crate::{
+    entry::*,
+    policy::*,
+    relation::*,
+    insert_row,
+    update_row,
+    Database,
+    DatabaseTable,
+    Error,
+};
+use lazy_static::lazy_static;
+use tracing::error;
+use std::{
+    any::TypeId,
+    marker::PhantomData,
+    sync::{
+        Mutex,
+        MutexGuard,
+        RwLock,
+        RwLockReadGuard,
+        RwLockWriteGuard,
+    },
+};
+
+lazy_static! {
+    static ref TRANSACTION: Mutex<()> = Mutex::new(());
+    static ref WRITES: RwLock<()> = RwLock::new(());
+}
+/// Shared lock held by a write to any table
+///
+/// Must not be taken again while held, writes nested in another write use the
+/// unlocked helpers like `update_row`.
+pub(crate) fn write_guard() -> RwLockReadGuard<'static, ()> {
+    WRITES.read().unwrap_or_else(|e| e.into_inner())
+}
+/// Waits for running transactions and writes to finish and blocks new ones while held
+pub(crate) struct Exclusive {
+    _transaction: MutexGuard<'static, ()>,
+    _writes: RwLockWriteGuard<'static, ()>,
+}
+pub(crate) fn exclusive() -> Exclusive {
+    Exclusive {
+        _transaction: TRANSACTION.lock().unwrap_or_else(|e| e.into_inner()),
+        _writes: WRITES.write().unwrap_or_else(|e| e.into_inner()),
+    }
+}
+
+/// Group of writes across the tables of database D, committed all or nothing
+///
+/// Inserts and updates are written immediately and undone when the transaction
+/// fails, deletes are deferred until it commits. Other writes wait until the
+/// transaction is finished, so a rollback never overwrites them.
+///
+/// Writes in a transaction must go through it, writing to the tables directly
+/// from inside waits forever. Transactions must not be nested.
+pub struct Transaction<'db, D> {
+    undo: Vec<Box<dyn FnOnce() -> Result<(), Error> + 'db>>,
+    deletes: Vec<Box<dyn FnOnce() -> Result<(), Error> + 'db>>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<'db, D: 'static> Transaction<'db, D> {
+    fn new() -> Self {
+        Self {
+            undo: Vec::new(),
+            deletes: Vec::new(),
+            _db: PhantomData,
+        }
+    }
+    /// Run f and commit its writes if it returns Ok, roll them back otherwise
+    ///
+    /// Writes are also rolled back if f panics. Fails with the error of the
+    /// commit if a deferred delete fails, after rolling back the inserts and
+    /// updates. If the rollback fails as well, its error is returned instead.
+    pub fn run<R, E, F>(f: F) -> Result<R, E>
+        where F: FnOnce(&mut Self) -> Result<R, E>,
+              E: From<Error>,
+    {
+        let _exclusive = exclusive();
+        let mut tx = Self::new();
+        match f(&mut tx) {
+            Ok(result) => match tx.commit() {
+                Ok(()) => Ok(result),
+                Err(e) => {
+                    tx.rollback()?;
+                    Err(e.into())
+                }
+            },
+            Err(e) => {
+                tx.rollback()?;
+                Err(e)
+            }
+        }
+    }
+    /// Apply the deferred deletes
+    ///
+    /// Deletes applied before a failing one can not be undone.
+    fn commit(&mut self) -> Result<(), Error> {
+        for delete in std::mem::take(&mut self.deletes) {
+            delete()?;
+        }
+        self.undo.clear();
+        Ok(())
+    }
+    pub fn get<T>(&self, id: Id<T>) -> Result<Option<Entry<T>>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        D::get(id)
+    }
+    pub fn insert<T>(&mut self, obj: T) -> Result<Id<T>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        let id = insert_row::<D, T>(obj)?;
+        self.undo.push(Box::new(move || {
+            delete_row::<D, T>(id).map(|_| ())
+        }));
+        Ok(id)
+    }
+    pub fn update<T, F>(&mut self, id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+              F: FnOnce(&mut T),
+    {
+        self.update_with(id, |_, data| {
+            f(data);
+            Ok(())
+        })
+    }
+    /// Update a row with f, see `update_row`, and undo it on rollback
+    fn update_with<T, F>(&mut self, id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+              F: FnOnce(&Entry<T>, &mut T) -> Result<(), Error>,
+    {
+        let mut old = None;
+        let entry = update_row::<D, T, _>(id, |entry, data| {
+            old = Some(entry.data().clone());
+            f(entry, data)
+        })?;
+        if let Some(old) = old {
+            self.undo.push(Box::new(move || {
+                update_row::<D, T, _>(id, move |_, data| {
+                    *data = old;
+                    Ok(())
+                })
+                .map(|_| ())
+            }));
+        }
+        Ok(entry)
+    }
+    /// Delete a row when the transaction commits
+    ///
+    /// Restricting references are checked immediately. Nullifying references are
+    /// updated immediately and the rows the delete cascades to are deleted on
+    /// commit as well, so the policies are undone with the transaction.
+    pub fn delete<T>(&mut self, id: Id<T>) -> Result<Option<T>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        self.delete_checked(id, None)
+    }
+    /// Check the delete of a row, see `check_delete`, and delete it on commit
+    fn delete_checked<T>(&mut self, id: Id<T>, principal: Option<&Principal>) -> Result<Option<T>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        let data = match D::get(id)? {
+            Some(entry) => entry.into_inner(),
+            None => return Ok(None),
+        };
+        check_delete::<D, T>(id, principal, &mut Default::default())?;
+        self.delete_referenced(id, &mut Default::default())?;
+        Ok(Some(data))
+    }
+    /// Apply the on-delete policies of the references to a row and delete it on commit
+    pub(crate) fn delete_referenced<T>(&mut self, id: Id<T>, visited: &mut Visited) -> Result<(), Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        if !visited.insert((TypeId::of::<T>(), id.to_string())) {
+            return Ok(());
+        }
+        for reference in <T as Referenced<'db, D>>::references() {
+            reference.apply(self, id, visited)?;
+        }
+        self.deletes.push(Box::new(move || {
+            delete_row::<D, T>(id).map(|_| ())
+        }));
+        Ok(())
+    }
+    /// Insert a row if principal may, see `Database::insert_as`
+    pub fn insert_as<T>(&mut self, principal: &Principal, obj: T) -> Result<Id<T>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        authorize(T::can_write(principal, None, &obj), Access::Write)?;
+        self.insert(obj)
+    }
+    /// Delete a row if principal may, see `Database::delete_as`
+    pub fn delete_as<T>(&mut self, principal: &Principal, id: Id<T>) -> Result<Option<T>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+    {
+        match D::get(id)? {
+            Some(entry) => authorize(T::can_delete(principal, &entry), Access::Delete)?,
+            None => return Ok(None),
+        }
+        Ok(self.delete_checked(id, Some(principal))?.map(|mut data| {
+            data.redact(principal);
+            data
+        }))
+    }
+    /// Update a row if principal may, see `Database::update_as`
+    pub fn update_as<T, F>(&mut self, principal: &Principal, id: Id<T>, f: F) -> Result<Option<Entry<T>>, Error>
+        where D: Database<'db, T>,
+              T: DatabaseTable<'db, D>,
+              F: FnOnce(&mut T),
+    {
+        Ok(self.update_with(id, |old, data| {
+                f(data);
+                authorize(T::can_write(principal, Some(old), data), Access::Write)
+            })?
+            .and_then(|entry| readable::<D, T>(principal, entry)))
+    }
+}
+impl<'db, D> Transaction<'db, D> {
+    /// Undo the inserts and updates and drop the deferred deletes
+    ///
+    /// Undoes as many writes as possible and returns the first error.
+    fn rollback(&mut self) -> Result<(), Error> {
+        self.deletes.clear();
+        let mut result = Ok(());
+        while let Some(undo) = self.undo.pop() {
+            if let Err(e) = undo() {
+                if result.is_ok() {
+                    result = Err(Error::Storage(format!("Rollback of transaction failed: {}", e)));
+                }
+            }
+        }
+        result
+    }
+}
+impl<'db, D> Drop for Transaction<'db, D> {
+    fn drop(&mut self) {
+        // only left to roll back if the transaction panicked
+        if let Err(e) = self.rollback() {
+            error!("{}", e);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::{
+        index::*,
+        storage::*,
+    };
+    use serde::{
+        Serialize,
+        Deserialize,
+    };
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Task {
+        name: String,
+    }
+    impl Indexed for Task {
+        fn indexes() -> Vec<IndexDef<Self>> {
+            vec![IndexDef::unique("name", |task: &Task| vec![task.name.clone()])]
+        }
+    }
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Project {
+        tasks: Vec<Id<Task>>,
+    }
+    impl Indexed for Project {}
+    impl Referenced<'static, Schema> for Task {
+        fn references() -> Vec<Reference<'static, Schema, Self>> {
+            vec![Reference::nullify("project.tasks", |project: &Project| project.tasks.clone(), |project, id| project.tasks.retain(|t| *t != id))]
+        }
+    }
+    impl Referenced<'static, Schema> for Project {}
+    impl Authorized<Schema> for Task {}
+    impl Authorized<Schema> for Project {}
+    lazy_static! {
+        static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+        static ref TASKS: SqliteTable<Task> = DB.table("task").unwrap();
+        static ref PROJECTS: SqliteTable<Project> = DB.table("project").unwrap();
+    }
+    struct Schema;
+    impl Database<'static, Task> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Task>, Error> {
+            Ok(&*TASKS)
+        }
+    }
+    impl Database<'static, Project> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Project>, Error> {
+            Ok(&*PROJECTS)
+        }
+    }
+    fn task(name: &str) -> Task {
+        Task {
+            name: name.to_string(),
+        }
+    }
+    fn add_task(project: Id<Project>, task: Task) -> Result<Id<Task>, Error> {
+        <Schema as Database<'_, Task>>::transaction(|tx| {
+            let id = tx.insert(task)?;
+            tx.update(project, |project: &mut Project| project.tasks.push(id))?
+                .ok_or_else(|| Error::NotFound("Project".into()))?;
+            Ok(id)
+        })
+    }
+    #[test]
+    fn commit_and_rollback() {
+        let project = <Project as DatabaseTable<'_, Schema>>::insert(Project::default()).unwrap();
+        let id = add_task(project, task("a")).unwrap();
+        assert_eq!(<Project as DatabaseTable<'_, Schema>>::get(project).unwrap().unwrap().tasks, vec![id]);
+
+        // project does not exist, the inserted task is removed again
+        assert!(add_task(Id::new(), task("b")).is_err());
+        assert!(<Task as DatabaseTable<'_, Schema>>::find_by_index("name", "b").unwrap().is_none());
+        assert_eq!(<Task as DatabaseTable<'_, Schema>>::get_all().unwrap().len(), 1);
+
+        // failing after an update and a delete restores both
+        let result: Result<(), Error> = <Schema as Database<'_, Task>>::transaction(|tx| {
+            tx.update(project, |project: &mut Project| project.tasks.clear()).unwrap();
+            tx.delete(id).unwrap();
+            Err(Error::Conflict("failed".into()))
+        });
+        assert!(result.is_err());
+        assert_eq!(<Project as DatabaseTable<'_, Schema>>::get(project).unwrap().unwrap().tasks, vec![id]);
+        assert!(<Task as DatabaseTable<'_, Schema>>::get(id).unwrap().is_some());
+
+        let result: Result<(), Error> = <Schema as Database<'_, Task>>::transaction(|tx| {
+            tx.delete(id).unwrap();
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert!(<Task as DatabaseTable<'_, Schema>>::get(id).unwrap().is_none());
+        assert!(<Project as DatabaseTable<'_, Schema>>::get(project).unwrap().unwrap().tasks.is_empty());
+    }
+    #[test]
+    fn writes_wait_for_transactions() {
+        let project = <Project as DatabaseTable<'_, Schema>>::insert(Project::default()).unwrap();
+        let (started, start) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            start.recv().unwrap();
+            <Project as DatabaseTable<'_, Schema>>::update(project, |project| project.tasks.push(Id::new())).unwrap();
+        });
+        let result: Result<(), Error> = <Schema as Database<'_, Task>>::transaction(|tx| {
+            tx.update(project, |project: &mut Project| project.tasks.clear())?;
+            started.send(()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            Err(Error::Conflict("failed".into()))
+        });
+        assert!(result.is_err());
+        writer.join().unwrap();
+        // the rollback did not overwrite the concurrent update
+        assert_eq!(<Project as DatabaseTable<'_, Schema>>::get(project).unwrap().unwrap().tasks.len(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use rql::*;
+use crate::{
+    entry::*,
+    Database,
+    DatabaseTable,
+};
+use futures::channel::mpsc::{
+    unbounded,
+    UnboundedReceiver,
+    UnboundedSender,
+};
+use lazy_static::lazy_static;
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use std::{
+    any::{
+        Any,
+        TypeId,
+    },
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+};
+
+/// A write to a table
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Change<T> {
+    Inserted(Entry<T>),
+    Updated(Entry<T>),
+    Deleted(Id<T>),
+}
+impl<T> Change<T> {
+    /// Id of the changed row
+    pub fn id(&self) -> Id<T> {
+        match self {
+            Self::Inserted(entry) | Self::Updated(entry) => *entry.id(),
+            Self::Deleted(id) => *id,
+        }
+    }
+}
+/// Stream of the changes to a table, see `Database::watch`
+pub type Changes<T> = UnboundedReceiver<Change<T>>;
+
+type Watchers<T> = Mutex<Vec<UnboundedSender<Change<T>>>>;
+lazy_static! {
+    static ref WATCHERS: RwLock<HashMap<(TypeId, TypeId), Arc<dyn Any + Send + Sync>>> = RwLock::new(HashMap::new());
+}
+fn watchers<D: 'static, T: Send + 'static>() -> Arc<Watchers<T>> {
+    let key = (TypeId::of::<D>(), TypeId::of::<T>());
+    if let Some(watchers) = WATCHERS.read().unwrap().get(&key) {
+        return watchers.clone()
+            .downcast()
+            .expect("Watchers type mismatch");
+    }
+    WATCHERS.write().unwrap()
+        .entry(key)
+        .or_insert_with(|| Arc::new(Watchers::<T>::default()))
+        .clone()
+        .downcast()
+        .expect("Watchers type mismatch")
+}
+/// Subscribe to the changes of table T in database D
+pub(crate) fn watch<'db, D, T>() -> Changes<T>
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    let (sender, receiver) = unbounded();
+    watchers::<D, T>()
+        .lock()
+        .unwrap()
+        .push(sender);
+    receiver
+}
+/// Send change to all watchers of table T in database D, dropping closed ones
+pub(crate) fn publish<'db, D, T>(change: Change<T>)
+    where D: Database<'db, T>,
+          T: DatabaseTable<'db, D>,
+{
+    watchers::<D, T>()
+        .lock()
+        .unwrap()
+        .retain(|sender| sender.unbounded_send(change.clone()).is_ok());
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::{
+        index::*,
+        policy::*,
+        relation::*,
+        storage::*,
+        Error,
+    };
+    use futures::{
+        executor::block_on,
+        StreamExt,
+    };
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+    }
+    impl Indexed for Note {}
+    lazy_static! {
+        static ref DB: SqliteDatabase = SqliteDatabase::open_in_memory().unwrap();
+        static ref NOTES: SqliteTable<Note> = DB.table("note").unwrap();
+    }
+    struct Schema;
+    impl Database<'static, Note> for Schema {
+        fn storage() -> Result<&'static dyn TableStorage<Note>, Error> {
+            Ok(&*NOTES)
+        }
+    }
+    impl Referenced<'static, Schema> for Note {}
+    impl Authorized<Schema> for Note {}
+    fn note(text: &str) -> Note {
+        Note {
+            text: text.to_string(),
+        }
+    }
+    #[test]
+    fn changes() {
+        let mut changes = <Note as DatabaseTable<'_, Schema>>::watch();
+        let id = <Note as DatabaseTable<'_, Schema>>::insert(note("a")).unwrap();
+        <Note as DatabaseTable<'_, Schema>>::update(id, |n| n.text = "b".into()).unwrap();
+        <Note as DatabaseTable<'_, Schema>>::delete(id).unwrap();
+        match block_on(changes.next()).unwrap() {
+            Change::Inserted(entry) => assert_eq!(entry.data(), &note("a")),
+            change => panic!("Unexpected {:?}", change),
+        }
+        match block_on(changes.next()).unwrap() {
+            Change::Updated(entry) => assert_eq!(entry.data(), &note("b")),
+            change => panic!("Unexpected {:?}", change),
+        }
+        assert_eq!(block_on(changes.next()).unwrap().id(), id);
+
+        // closed watchers are dropped on the next change
+        drop(changes);
+        <Note as DatabaseTable<'_, Schema>>::insert(note("c")).unwrap();
+        assert!(watchers::<Schema, Note>().lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use syn::{
+	*,
+};
+
+/// Who may call an api function, declared by an attribute on the function
+///
+/// ```ignore
+/// api! {
+///     #[public]
+///     fn interpret_text(text: String) -> String { .. }
+///     #[role("admin")]
+///     fn delete_all_tasks() { .. }
+/// }
+/// ```
+pub(crate) enum Access {
+	/// `#[public]`: callable without a token
+	Public,
+	/// `#[authenticated]`, the default: callable with a valid token
+	Authenticated,
+	/// `#[role("admin")]`: callable with the token of a user with the role
+	Role(LitStr),
+}
+impl Access {
+	pub(crate) fn of(item: &ItemFn) -> Self {
+		item.attrs
+			.iter()
+			.find_map(|attr| {
+				if attr.path.is_ident("public") {
+					Some(Self::Public)
+				} else if attr.path.is_ident("authenticated") {
+					Some(Self::Authenticated)
+				} else if attr.path.is_ident("role") {
+					Some(Self::Role(attr.parse_args().expect("expected #[role(\"name\")]")))
+				} else {
+					None
+				}
+			})
+			.unwrap_or(Self::Authenticated)
+	}
+}
+/// Whether attr declares the `Access` of a function
+pub(crate) fn is_access_attr(attr: &Attribute) -> bool {
+	attr.path.is_ident("public")
+		|| attr.path.is_ident("authenticated")
+		|| attr.path.is_ident("role")
+}
@@ -12,25 +12,37 @@ use syn::{
 	*,
 };
 
-pub fn define_client(fns: &Vec<ItemFn>) -> TokenStream2 {
-	let calls: Vec<TokenStream2> = fns.iter().map(|f| fetch_call(f.clone())).collect();
+pub fn define_client(fns: &Vec<ItemFn>, version: u32) -> TokenStream2 {
+	let calls: Vec<TokenStream2> = fns.iter().map(|f| fetch_call(f.clone(), version)).collect();
+	let batch_route = crate::schema::batch_route(version);
+	let methods: Vec<TokenStream2> = crate::dispatch::dispatched(fns)
+		.into_iter()
+		.map(crate::dispatch::client_method)
+		.collect();
 	quote! {
 		#[cfg(target_arch="wasm32")]
 		use seed::{
 			browser::fetch::{
-				Method,
-				FetchError,
 				Header,
-				Response,
 			},
 		};
 		use app_model::{
-			auth,
+			ApiClient,
+			ApiError,
 		};
 		#(#calls)*
+		/// Client batching the calls of the same tick, see `app_model::api_batch::BatchClient`
+		#[derive(Clone)]
+		pub struct ApiBatch(app_model::api_batch::BatchClient<ApiCall, ApiReturn>);
+		impl ApiBatch {
+			pub fn new() -> Self {
+				Self(app_model::api_batch::BatchClient::new(#batch_route, api_schema_hash()))
+			}
+			#(#methods)*
+		}
 	}
 }
-fn fetch_call(item: ItemFn) -> TokenStream2 {
+fn fetch_call(item: ItemFn, version: u32) -> TokenStream2 {
 	let Signature {
 		ident,  //: Ident
 		inputs, //: Punctuated<FnArg, Comma>
@@ -40,7 +52,11 @@ fn fetch_call(item: ItemFn) -> TokenStream2 {
 
 	let params_ident = format_ident!("{}Parameters", ident.clone());
 	let result_ident = format_ident!("{}Result", ident.clone());
-	let route = format!("/api/call/{}", ident);
+	let route = crate::schema::route(version, &ident);
+	let inputs: Punctuated<FnArg, Comma> = inputs
+		.into_iter()
+		.filter(|arg| !crate::is_injected(arg))
+		.collect();
 	let members: Punctuated<Ident, Comma> = inputs
 		.iter()
 		.map(|arg| {
@@ -55,29 +71,46 @@ fn fetch_call(item: ItemFn) -> TokenStream2 {
 			}
 		})
 		.collect();
-	let ret_ty: Type = match output {
+	let ret_ty: Type = match output.clone() {
 		ReturnType::Default => syn::parse_str("()").unwrap(),
 		ReturnType::Type(_arrow, ty) => *ty,
 	};
+	// streamed items are decoded from the lines of the response body
+	if let Some(item_ty) = crate::stream_item(&output) {
+		return quote! {
+			pub async fn #ident(#inputs) -> Result<impl futures::Stream<Item = Result<#item_ty, ApiError<std::convert::Infallible>>>, ApiError<std::convert::Infallible>> {
+				let items = app_model::api_stream::post(#route, api_schema_hash(), &#params_ident { #members }).await?;
+				Ok(futures::StreamExt::map(items, |item| item.map(|res: #result_ident| res.0)))
+			}
+		};
+	}
+	// error results are decoded from the response body, other functions can't fail
+	let (ok_ty, err_ty, result) = match crate::result_types(&output) {
+		Some((ok_ty, err_ty)) => (ok_ty, err_ty, quote! { res.0 }),
+		None => (ret_ty, syn::parse_quote! { std::convert::Infallible }, quote! { Ok(res.0) }),
+	};
 	quote! {
 		#[cfg(target_arch="wasm32")]
-		pub async fn #ident(#inputs) -> Result<#ret_ty, FetchError> {
-			let host = "http://localhost:8000";
-			let url = format!("{}{}", host, #route);
-			let mut req = seed::fetch::Request::new(&url)
-				.method(Method::Post);
-			// authentication
-			if let Some(session) = auth::session::get() {
-				req = req.header(Header::authorization(format!("{}", session.token)));
-			}
-			seed::fetch::fetch(
-				req.json(&#params_ident { #members })?
-			)
-			.await?
-			.check_status()?
-			.json()
-			.await
-			.map(|res: #result_ident| res.0)
+		pub async fn #ident(#inputs) -> Result<#ok_ty, ApiError<#err_ty>> {
+			let req = ApiClient::current()
+				.call(#route, &#params_ident { #members })
+				.header(Header::custom(app_model::api_schema::HEADER, api_schema_hash()));
+			let response = seed::fetch::fetch(req)
+				.await
+				.map_err(ApiError::Transport)?;
+			let status = response.status().code;
+			ApiError::result(status, app_model::api_client::decode(response).await.map(|res: #result_ident| #result))
+		}
+		#[cfg(not(target_arch="wasm32"))]
+		pub async fn #ident(#inputs) -> Result<#ok_ty, ApiError<#err_ty>> {
+			let response = ApiClient::current()
+				.call(#route, &#params_ident { #members })
+				.header(app_model::api_schema::HEADER, api_schema_hash())
+				.send()
+				.await
+				.map_err(ApiError::Transport)?;
+			let status = response.status().as_u16();
+			ApiError::result(status, app_model::api_client::decode(response).await.map(|res: #result_ident| #result))
 		}
 	}
 }
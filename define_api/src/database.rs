@@ -0,0 +1,280 @@
+use proc_macro::TokenStream;
+use proc_macro2::{
+	Span,
+	TokenStream as TokenStream2,
+};
+use quote::{
+	format_ident,
+	quote,
+};
+use syn::{
+	parse::{
+		Parse,
+		ParseStream,
+	},
+	punctuated::Punctuated,
+	*,
+};
+
+struct TableDef {
+	name: Ident,
+	ty: Type,
+}
+impl Parse for TableDef {
+	fn parse(input: ParseStream) -> Result<Self> {
+		let name = input.parse()?;
+		input.parse::<Token![:]>()?;
+		let ty = input.parse()?;
+		Ok(Self { name, ty })
+	}
+}
+struct DatabaseDef {
+	vis: Visibility,
+	schema: Ident,
+	tables: Vec<TableDef>,
+	name: LitStr,
+	env: LitStr,
+	snapshot_env: Option<LitStr>,
+	migrations: Option<Path>,
+}
+impl Parse for DatabaseDef {
+	fn parse(input: ParseStream) -> Result<Self> {
+		let vis = input.parse()?;
+		let schema = input.parse()?;
+		let content;
+		braced!(content in input);
+		let tables = Punctuated::<TableDef, Token![,]>::parse_terminated(&content)?
+			.into_iter()
+			.collect();
+		let mut name = None;
+		let mut env = None;
+		let mut snapshot_env = None;
+		let mut migrations = None;
+		while !input.is_empty() {
+			let key: Ident = input.parse()?;
+			input.parse::<Token![=]>()?;
+			match key.to_string().as_str() {
+				"name" => name = Some(input.parse()?),
+				"env" => env = Some(input.parse()?),
+				"snapshot_env" => snapshot_env = Some(input.parse()?),
+				"migrations" => migrations = Some(input.parse()?),
+				_ => return Err(Error::new(key.span(), format!("Unknown key `{}`", key))),
+			}
+			if !input.is_empty() {
+				input.parse::<Token![,]>()?;
+			}
+		}
+		Ok(Self {
+			vis,
+			schema,
+			tables,
+			name: name.ok_or_else(|| Error::new(Span::call_site(), "Missing `name = \"<database name>\"`"))?,
+			env: env.ok_or_else(|| Error::new(Span::call_site(), "Missing `env = \"<backend variable>\"`"))?,
+			snapshot_env,
+			migrations,
+		})
+	}
+}
+/// Define the Schema of a database with storage, Database impls and maintenance functions
+pub fn define_database(input: TokenStream) -> TokenStream {
+	let DatabaseDef {
+		vis,
+		schema,
+		tables,
+		name,
+		env,
+		snapshot_env,
+		migrations,
+	} = parse_macro_input!(input as DatabaseDef);
+	let names: Vec<&Ident> = tables.iter().map(|t| &t.name).collect();
+	let names_mut: Vec<Ident> = names.iter().map(|n| format_ident!("{}_mut", n)).collect();
+	let name_strs: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+	let statics: Vec<Ident> = names.iter().map(|n| format_ident!("{}_TABLE", n.to_string().to_uppercase())).collect();
+	let types: Vec<&Type> = tables.iter().map(|t| &t.ty).collect();
+	let migrations = match migrations {
+		Some(path) => quote! { #path() },
+		None => quote! { ::database_table::Migrations::new() },
+	};
+	let snapshot_env = snapshot_env.map(|env| quote! {
+		/// prefix of the periodic snapshot variables, see `SnapshotSchedule::from_env`
+		#vis const SNAPSHOT_ENV: &str = #env;
+	});
+	let routes = define_routes(&types);
+	TokenStream::from(quote! {
+		rql::schema! {
+			#vis #schema {
+				#(#names: #types,)*
+			}
+		}
+		#vis const DATABASE_NAME: &str = #name;
+		/// either `rql` (default) or `sqlite:<path>`
+		#vis const DATABASE_ENV: &str = #env;
+		#snapshot_env
+		::lazy_static::lazy_static! {
+			static ref BACKEND: Result<::database_table::Backend, ::database_table::Error> =
+				::database_table::Backend::from_env(DATABASE_ENV);
+			static ref DB: Result<#schema, ::database_table::Error> =
+				#schema::new(DATABASE_NAME, rql::BinaryStable)
+					.map_err(|e| ::database_table::Error::Storage(format!("Failed to open {}: {}", DATABASE_NAME, e)));
+			static ref OPENED: Result<::database_table::MigrationReport, ::database_table::Error> =
+				backend().and_then(|backend| Ok(migrate(backend, ::database_table::MigrationMode::Apply)?));
+			#(
+				static ref #statics: Result<Box<dyn ::database_table::TableStorage<#types>>, ::database_table::Error> =
+					open().and_then(|_| backend()?.table(
+						#name_strs,
+						// the rql schema is open when the rql backend was migrated
+						::database_table::RqlStorage::new(
+							|| rql_schema().expect("rql schema not open").#names(),
+							|| rql_schema().expect("rql schema not open").#names_mut(),
+						),
+					));
+			)*
+		}
+		/// Backend configured in `DATABASE_ENV`
+		#vis fn backend() -> Result<&'static ::database_table::Backend, ::database_table::Error> {
+			BACKEND.as_ref().map_err(Clone::clone)
+		}
+		fn rql_schema() -> Result<&'static #schema, ::database_table::Error> {
+			DB.as_ref().map_err(Clone::clone)
+		}
+		/// Open the configured backend and migrate its tables to their current versions
+		///
+		/// Call at startup to stop on errors, the tables are opened on first use
+		/// otherwise and fail every operation with the error.
+		#vis fn open() -> Result<::database_table::MigrationReport, ::database_table::Error> {
+			OPENED.clone()
+		}
+		#(
+			impl<'db> ::database_table::Database<'db, #types> for #schema {
+				fn storage() -> Result<&'db dyn ::database_table::TableStorage<#types>, ::database_table::Error> {
+					#statics.as_ref()
+						.map(|storage| storage.as_ref())
+						.map_err(Clone::clone)
+				}
+			}
+			impl ::database_table::AsyncDatabase<#types> for #schema {}
+		)*
+		/// Run f with untyped access to the tables in backend
+		fn with_raw_tables<R>(
+			backend: &::database_table::Backend,
+			f: impl FnOnce(&dyn ::database_table::RawTables) -> R,
+		) -> Result<R, ::database_table::Error> {
+			match backend {
+				::database_table::Backend::Rql => {
+					let db = rql_schema()?;
+					let tables = ::database_table::RqlRawTables::new(format!("{}.versions.json", DATABASE_NAME))
+						#(.table(#name_strs, move || db.#names(), move || db.#names_mut()))*;
+					Ok(f(&tables))
+				}
+				::database_table::Backend::Sqlite(db) => Ok(f(db)),
+			}
+		}
+		/// Drop the indexes of all tables, so they are rebuilt from the stored rows
+		fn reset_indexes() {
+			#(
+				::database_table::reset_table_index::<#schema, #types>();
+				::database_table::reset_search_index::<#schema, #types>();
+			)*
+		}
+		/// Migrate the tables in backend to their current versions
+		#vis fn migrate(
+			backend: &::database_table::Backend,
+			mode: ::database_table::MigrationMode,
+		) -> Result<::database_table::MigrationReport, ::database_table::MigrationError> {
+			let report = with_raw_tables(backend, |tables| #migrations.run(tables, mode))
+				.unwrap_or_else(|e| Err(::database_table::MigrationError {
+					table: DATABASE_NAME.to_string(),
+					id: None,
+					message: e.to_string(),
+				}));
+			if mode == ::database_table::MigrationMode::Apply {
+				reset_indexes();
+			}
+			report
+		}
+		/// Point-in-time export of all tables
+		#vis fn snapshot() -> ::database_table::Snapshot<#schema> {
+			let migrations = #migrations;
+			::database_table::Snapshot::new()
+				#(.table::<#types>(#name_strs, migrations.version(#name_strs)))*
+		}
+		/// Replace the tables in backend with the snapshot at path
+		///
+		/// Rows of older snapshots are migrated to the current versions.
+		#vis fn restore<P: AsRef<::std::path::Path>>(
+			backend: &::database_table::Backend,
+			path: P,
+		) -> Result<::database_table::SnapshotManifest, ::database_table::SnapshotError> {
+			let manifest = with_raw_tables(backend, |tables| ::database_table::restore(tables, &#migrations, path))
+				.unwrap_or_else(|e| Err(::database_table::SnapshotError {
+					table: None,
+					message: e.to_string(),
+				}));
+			reset_indexes();
+			manifest
+		}
+		/// Run the maintenance command in args, if there is one
+		///
+		/// `--migrate-dry-run` prints the pending migrations, `--check-references` the dangling
+		/// references, `--snapshot <path>` writes a snapshot and `--restore <path>` restores one.
+		/// Returns the exit code of the command, errors are printed to stderr.
+		#vis fn run_command<I: IntoIterator<Item = String>>(args: I) -> Option<i32> {
+			let args: Vec<String> = args.into_iter().collect();
+			let flag = |flag: &str| args.iter().any(|arg| arg == flag);
+			// value following flag
+			let value = |flag: &str| args.iter().skip_while(|arg| *arg != flag).nth(1).cloned();
+			let result = if flag("--migrate-dry-run") {
+				backend()
+					.map_err(|e| e.to_string())
+					.and_then(|backend| migrate(backend, ::database_table::MigrationMode::DryRun).map_err(|e| e.to_string()))
+					.map(|report| print!("{}", report))
+			} else if flag("--check-references") {
+				check_references()
+					.map(|dangling| if dangling.is_empty() {
+						println!("No dangling references.");
+					} else {
+						for reference in dangling {
+							println!("{}", reference);
+						}
+					})
+					.map_err(|e| e.to_string())
+			} else if let Some(path) = value("--snapshot") {
+				snapshot().write(&path)
+					.map(|manifest| print!("{}", manifest))
+					.map_err(|e| e.to_string())
+			} else if let Some(path) = value("--restore") {
+				backend()
+					.map_err(|e| e.to_string())
+					.and_then(|backend| restore(backend, &path).map_err(|e| e.to_string()))
+					.map(|manifest| print!("Restored {}", manifest))
+			} else {
+				return None;
+			};
+			Some(match result {
+				Ok(()) => 0,
+				Err(e) => {
+					eprintln!("{}", e);
+					1
+				}
+			})
+		}
+		/// Ids referring to missing rows in any table
+		#vis fn check_references() -> Result<Vec<::database_table::DanglingReference>, ::database_table::Error> {
+			let mut dangling = Vec::new();
+			#(dangling.extend(<#types as ::database_table::DatabaseTable<'_, #schema>>::dangling_references()?);)*
+			Ok(dangling)
+		}
+		#routes
+	})
+}
+/// Macro calling a macro for each table type, e.g. `schema_tables!(rest_handlers)`
+fn define_routes(types: &[&Type]) -> TokenStream2 {
+	quote! {
+		#[macro_export]
+		macro_rules! schema_tables {
+			($m:ident) => {
+				[#($m!(#types)),*]
+			};
+		}
+	}
+}
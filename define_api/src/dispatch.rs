@@ -0,0 +1,137 @@
+use quote::{
+	format_ident,
+	quote,
+};
+use proc_macro2::{
+	TokenStream as TokenStream2,
+};
+use syn::{
+	punctuated::Punctuated,
+	token::*,
+	Type,
+	*,
+};
+
+/// Functions which can be called by name, over the websocket or in batches
+///
+/// Streaming functions are only called over HTTP.
+pub(crate) fn dispatched(fns: &Vec<ItemFn>) -> Vec<ItemFn> {
+	fns.iter()
+		.filter(|f| crate::stream_item(&f.sig.output).is_none())
+		.cloned()
+		.collect()
+}
+/// Define the calls and returns of the api functions and the server dispatcher of calls
+///
+/// A call is serialized as `{"fn": name, "params": parameters}`, a return as
+/// `{"fn": name, "result": result}`.
+pub fn define_dispatch(fns: &Vec<ItemFn>) -> TokenStream2 {
+	let fns = dispatched(fns);
+	let idents: Vec<Ident> = fns.iter().map(|f| f.sig.ident.clone()).collect();
+	let params_idents: Vec<Ident> = idents.iter().map(|ident| format_ident!("{}Parameters", ident)).collect();
+	let result_idents: Vec<Ident> = idents.iter().map(|ident| format_ident!("{}Result", ident)).collect();
+	let calls: Vec<TokenStream2> = fns.iter().map(|f| server_call(f.clone())).collect();
+	quote! {
+		/// Call of an api function by name
+		#[allow(non_camel_case_types)]
+		#[derive(Deserialize, Serialize)]
+		#[serde(tag = "fn", content = "params")]
+		pub enum ApiCall {
+			#(#idents(#params_idents),)*
+		}
+		/// Return value of an api function called by name
+		#[allow(non_camel_case_types)]
+		#[derive(Deserialize, Serialize)]
+		#[serde(tag = "fn", content = "result")]
+		pub enum ApiReturn {
+			#(#idents(#result_idents),)*
+		}
+
+		#[cfg(not(target_arch="wasm32"))]
+		pub mod dispatch {
+			use super::*;
+			use jwt::{
+				*,
+			};
+			#(#calls)*
+			/// Return of call with token, or the error status of the call
+			pub async fn call(token: Option<JWT>, call: ApiCall) -> Result<ApiReturn, u16> {
+				match call {
+					#(ApiCall::#idents(parameters) => #idents(token, parameters).await,)*
+				}
+			}
+			/// Returns of calls with token, in the order of the calls
+			///
+			/// The calls are executed concurrently.
+			pub async fn batch(token: Option<JWT>, calls: Vec<ApiCall>) -> Vec<Result<ApiReturn, u16>> {
+				futures::future::join_all(calls.into_iter().map(|c| call(token.clone(), c))).await
+			}
+		}
+	}
+}
+/// Call of item from the server dispatcher, failing with the status of denied calls
+fn server_call(item: ItemFn) -> TokenStream2 {
+	let ident = item.sig.ident.clone();
+	let params_ident = format_ident!("{}Parameters", ident.clone());
+	let result_ident = format_ident!("{}Result", ident.clone());
+	let claims = crate::server::claims(&item, dispatch_error);
+	let call = crate::server::invoke(&item, crate::server::args(&item, dispatch_error), true);
+	quote! {
+		async fn #ident(token: Option<JWT>, parameters: #params_ident) -> Result<ApiReturn, u16> {
+			#claims
+			Ok(ApiReturn::#ident(#result_ident(#call)))
+		}
+	}
+}
+fn dispatch_error(status: u16) -> TokenStream2 {
+	quote! { #status }
+}
+/// Method calling item by name through client, a websocket or batch client
+pub(crate) fn client_method(item: ItemFn) -> TokenStream2 {
+	let Signature {
+		ident,
+		inputs,
+		output,
+		..
+	} = item.sig;
+	let params_ident = format_ident!("{}Parameters", ident.clone());
+	let result_ident = format_ident!("{}Result", ident.clone());
+	let inputs: Punctuated<FnArg, Comma> = inputs
+		.into_iter()
+		.filter(|arg| !crate::is_injected(arg))
+		.collect();
+	let members: Punctuated<Ident, Comma> = inputs
+		.iter()
+		.map(|arg| {
+			match arg {
+				FnArg::Typed(ty) => {
+					match *ty.pat.clone() {
+						Pat::Ident(pat) => pat.ident,
+						_ => panic!("api function params must have idents"),
+					}
+				}
+				_ => panic!("api functions may not take self parameter"),
+			}
+		})
+		.collect();
+	// error results are returned as domain errors
+	let (ret_ty, err_ty, value) = match crate::result_types(&output) {
+		Some((ok_ty, err_ty)) => (ok_ty, err_ty, quote! { result.map_err(ApiError::Domain) }),
+		None => {
+			let ret_ty: Type = match output {
+				ReturnType::Default => parse_quote! { () },
+				ReturnType::Type(_arrow, ty) => *ty,
+			};
+			(ret_ty, parse_quote! { std::convert::Infallible }, quote! { Ok(result) })
+		}
+	};
+	quote! {
+		pub async fn #ident(&self, #inputs) -> Result<#ret_ty, ApiError<#err_ty>> {
+			match self.0.call(ApiCall::#ident(#params_ident { #members })).await? {
+				ApiReturn::#ident(#result_ident(result)) => #value,
+				#[allow(unreachable_patterns)]
+				_ => Err(ApiError::Status(500)),
+			}
+		}
+	}
+}
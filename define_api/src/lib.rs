@@ -1,7 +1,13 @@
+mod access;
 mod client;
+mod database;
+mod dispatch;
+mod openapi;
 mod rest;
 mod rpc;
+mod schema;
 mod server;
+mod websocket;
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
@@ -16,6 +22,7 @@ use syn::{
 	*,
 };
 struct Items {
+	attrs: Vec<Attribute>,
 	items: Vec<Item>,
 }
 impl std::ops::Deref for Items {
@@ -26,11 +33,12 @@ impl std::ops::Deref for Items {
 }
 impl syn::parse::Parse for Items {
 	fn parse(input: syn::parse::ParseStream) -> syn::parse::Result<Self> {
+		let attrs = input.call(Attribute::parse_inner)?;
 		let mut items = Vec::new();
 		while let Ok(item) = input.parse::<Item>() {
 			items.push(item);
 		}
-		Ok(Items { items })
+		Ok(Items { attrs, items })
 	}
 }
 struct ItemFns {
@@ -45,7 +53,134 @@ impl syn::parse::Parse for ItemFns {
 		Ok(ItemFns { items })
 	}
 }
+/// Argument of an api function supplied by the server instead of the client
+pub(crate) enum Injected {
+	/// `Principal` of the caller, `Principal::Anonymous` without a token
+	Principal,
+	/// `JWTClaims` of the caller, the call fails without a token
+	Claims,
+	/// `Option<JWTClaims>` of the caller
+	OptionalClaims,
+}
+/// The injected argument arg is, if any
+///
+/// Injected arguments are not part of the request parameters, the server
+/// derives them from the JWT of the request.
+pub(crate) fn injected(arg: &FnArg) -> Option<Injected> {
+	match arg {
+		FnArg::Typed(PatType { ty, .. }) => injected_type(ty),
+		_ => None,
+	}
+}
+fn injected_type(ty: &Type) -> Option<Injected> {
+	let segment = match ty {
+		Type::Path(TypePath { path, .. }) => path.segments.last()?,
+		_ => return None,
+	};
+	if segment.ident == "Principal" {
+		Some(Injected::Principal)
+	} else if segment.ident == "JWTClaims" {
+		Some(Injected::Claims)
+	} else if segment.ident == "Option" {
+		match &segment.arguments {
+			PathArguments::AngleBracketed(args) => match args.args.first() {
+				Some(GenericArgument::Type(ty)) => match injected_type(ty) {
+					Some(Injected::Claims) => Some(Injected::OptionalClaims),
+					_ => None,
+				},
+				_ => None,
+			},
+			_ => None,
+		}
+	} else {
+		None
+	}
+}
+pub(crate) fn is_injected(arg: &FnArg) -> bool {
+	injected(arg).is_some()
+}
+/// Whether an api function returns a `Result<T, E>`
+///
+/// The server responds to errors with the `ErrorStatus` of the error, the
+/// client decodes the error from the response instead of failing on the status.
+pub(crate) fn is_result(output: &ReturnType) -> bool {
+	result_types(output).is_some()
+}
+/// The types `T` and `E` of an api function returning a `Result<T, E>`
+pub(crate) fn result_types(output: &ReturnType) -> Option<(Type, Type)> {
+	let ty = match output {
+		ReturnType::Type(_, ty) => &**ty,
+		ReturnType::Default => return None,
+	};
+	let segment = match ty {
+		Type::Path(TypePath { path, .. }) => path.segments.last()?,
+		_ => return None,
+	};
+	if segment.ident != "Result" {
+		return None;
+	}
+	match &segment.arguments {
+		PathArguments::AngleBracketed(args) => {
+			let mut types = args.args.iter().filter_map(|arg| match arg {
+				GenericArgument::Type(ty) => Some(ty.clone()),
+				_ => None,
+			});
+			match (types.next(), types.next()) {
+				(Some(ok), Some(err)) => Some((ok, err)),
+				_ => None,
+			}
+		}
+		_ => None,
+	}
+}
 
+/// The item type `T` of an api function returning an `impl Stream<Item = T>`
+///
+/// The server responds to calls of streaming functions with a JSON line per
+/// item, the client decodes them into a `futures::Stream`.
+pub(crate) fn stream_item(output: &ReturnType) -> Option<Type> {
+	let bounds = match output {
+		ReturnType::Type(_, ty) => match &**ty {
+			Type::ImplTrait(TypeImplTrait { bounds, .. }) => bounds,
+			_ => return None,
+		},
+		ReturnType::Default => return None,
+	};
+	bounds.iter().find_map(|bound| match bound {
+		TypeParamBound::Trait(TraitBound { path, .. }) => {
+			let segment = path.segments.last()?;
+			if segment.ident != "Stream" {
+				return None;
+			}
+			match &segment.arguments {
+				PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+					GenericArgument::Binding(Binding { ident, ty, .. }) if ident == "Item" => Some(ty.clone()),
+					_ => None,
+				}),
+				_ => None,
+			}
+		}
+		_ => None,
+	})
+}
+/// Define a database Schema with its storage, Database impls and maintenance functions
+///
+/// ```ignore
+/// database_table! {
+///     pub Schema {
+///         user: User,
+///         task: Task,
+///     }
+///     name = "test_database",
+///     env = "BUDGET_APP_DATABASE",
+///     snapshot_env = "BUDGET_APP",
+///     migrations = migrations,
+/// }
+/// ```
+#[proc_macro]
+pub fn database_table(input: TokenStream) -> TokenStream {
+	database::define_database(input)
+}
 /// Define server side REST handlers for a type
 #[proc_macro]
 pub fn rest_api(input: TokenStream) -> TokenStream {
@@ -66,6 +201,7 @@ pub fn rest_handlers(input: TokenStream) -> TokenStream {
 	let post_name = format_ident!("post_{}", ident);
 	let get_all_name = format_ident!("get_{}s", ident);
 	let delete_name = format_ident!("delete_{}", ident);
+	let search_name = format_ident!("search_{}s", ident);
 	//let update_name = format_ident!("update_{}", ident);
 	TokenStream::from(quote! {
 		rocket::routes![
@@ -73,6 +209,7 @@ pub fn rest_handlers(input: TokenStream) -> TokenStream {
 			api::handlers::#post_name,
 			api::handlers::#get_all_name,
 			api::handlers::#delete_name,
+			api::handlers::#search_name,
 			//api::handlers::#update_name,
 		]
 	})
@@ -127,7 +264,7 @@ pub fn api(input: TokenStream) -> TokenStream {
 		})
 		.collect();
     // parse rest function items
-	let rest_fns: Vec<Vec<ItemFn>> = rest_apis
+	let rest_fns: Vec<ItemFn> = rest_apis
 		.iter()
 		.flat_map(|ts| {
 			let fns = syn::parse::<ItemFns>(ts.clone()).unwrap();
@@ -135,15 +272,24 @@ pub fn api(input: TokenStream) -> TokenStream {
 		})
 		.collect();
     // append rest functions to other functions
-	fns.extend(rest_fns.iter().cloned());
+	fns.extend(rest_fns);
 
+	let version = schema::version(&items.attrs);
+	let schema = schema::define_schema(&fns, version);
 	let protocol = rpc::define_protocol(&fns);
-	let server = server::define_server(&fns);
-	let client = client::define_client(&fns);
+	let dispatch = dispatch::define_dispatch(&fns);
+	let server = server::define_server(&fns, server::Backend::of(&items.attrs), version);
+	let openapi = openapi::define_openapi(&fns, version);
+	let client = client::define_client(&fns, version);
+	let websocket = websocket::define_websocket(&fns, websocket::push_type(&items.attrs));
 	TokenStream::from(quote! {
 		#(#imports)*
+		#schema
 		#protocol
+		#dispatch
 		#server
+		#openapi
 		#client
+		#websocket
 	})
 }
@@ -0,0 +1,186 @@
+use quote::{
+	format_ident,
+	quote,
+	ToTokens,
+};
+use proc_macro2::{
+	TokenStream as TokenStream2,
+};
+use crate::access::Access;
+use syn::{
+	*,
+};
+
+/// Define a function returning the OpenAPI 3 document of the api functions
+pub fn define_openapi(fns: &Vec<ItemFn>, version: u32) -> TokenStream2 {
+	let paths: Vec<TokenStream2> = fns.iter().map(|f| path(f.clone(), version)).collect();
+	quote! {
+		/// OpenAPI 3 document of the api, served at /api/openapi.json
+		#[cfg(not(target_arch="wasm32"))]
+		pub fn openapi() -> serde_json::Value {
+			let mut gen = schemars::gen::SchemaSettings::openapi3().into_generator();
+			let mut paths = serde_json::Map::new();
+			#(#paths)*
+			serde_json::json!({
+				"openapi": "3.0.3",
+				"info": {
+					"title": env!("CARGO_PKG_NAME"),
+					"version": env!("CARGO_PKG_VERSION"),
+					"x-api-version": API_VERSION,
+					"x-schema-hash": api_schema_hash(),
+				},
+				"paths": paths,
+				"components": {
+					"schemas": gen.definitions(),
+					"securitySchemes": {
+						"jwt": {
+							"type": "apiKey",
+							"in": "header",
+							"name": "Authorization",
+						},
+					},
+				},
+				"security": [{ "jwt": [] }],
+			})
+		}
+	}
+}
+fn path(item: ItemFn, version: u32) -> TokenStream2 {
+	let ItemFn {
+		attrs,
+		sig,
+		..
+	} = item.clone();
+	let ident = sig.ident;
+	let params_ident = format_ident!("{}Parameters", ident.clone());
+	let result_ident = format_ident!("{}Result", ident.clone());
+	let route = crate::schema::route(version, &ident);
+	let operation = ident.to_string();
+	let description = attrs
+		.iter()
+		.filter(|attr| attr.path.is_ident("doc"))
+		.filter_map(|attr| match attr.parse_meta() {
+			Ok(Meta::NameValue(MetaNameValue { lit: Lit::Str(doc), .. })) => Some(doc.value().trim().to_string()),
+			_ => None,
+		})
+		.collect::<Vec<_>>()
+		.join("\n");
+	let description = if description.is_empty() {
+		quote! {}
+	} else {
+		quote! { "description": #description, }
+	};
+	// public functions don't need a token
+	let security = match Access::of(&item) {
+		Access::Public => quote! { "security": [], },
+		_ => quote! {},
+	};
+	// error results are responded with the status of the error
+	let errors = if crate::is_result(&sig.output) {
+		quote! {
+			"default": {
+				"description": "Error of the call",
+				"content": {
+					"application/json": {
+						"schema": result,
+					},
+					"application/msgpack": {
+						"schema": result,
+					},
+				},
+			},
+		}
+	} else {
+		quote! {}
+	};
+	// streaming functions respond with a line per item
+	let content = if crate::stream_item(&sig.output).is_some() {
+		quote! {
+			"application/x-ndjson": {
+				"schema": result,
+			},
+		}
+	} else {
+		quote! {
+			"application/json": {
+				"schema": result,
+			},
+			"application/msgpack": {
+				"schema": result,
+			},
+		}
+	};
+	quote! {
+		{
+			let parameters = gen.subschema_for::<#params_ident>();
+			let result = gen.subschema_for::<#result_ident>();
+			paths.insert(#route.to_string(), serde_json::json!({
+				"post": {
+					"operationId": #operation,
+					#description
+					#security
+					"requestBody": {
+						"required": true,
+						"content": {
+							"application/json": {
+								"schema": parameters,
+							},
+							"application/msgpack": {
+								"schema": parameters,
+							},
+						},
+					},
+					"responses": {
+						"200": {
+							"description": "Result of the call",
+							"content": {
+								#content
+							},
+						},
+						#errors
+					},
+				},
+			}));
+		}
+	}
+}
+/// Attribute giving a field of type ty the schema of its serialized form
+///
+/// rql `Id`s don't implement `JsonSchema`, they are serialized as uuid strings.
+pub(crate) fn schema_attr(ty: &Type) -> Option<Attribute> {
+	schema_type(ty).map(|schema| {
+		let with = schema.to_token_stream().to_string();
+		parse_quote! {
+			#[cfg_attr(not(target_arch="wasm32"), schemars(with = #with))]
+		}
+	})
+}
+/// ty with every `Id<T>` replaced by `String`, if it contains one
+fn schema_type(ty: &Type) -> Option<Type> {
+	match ty {
+		Type::Path(TypePath { qself: None, path }) => {
+			let mut path = path.clone();
+			let last = path.segments.last_mut()?;
+			if last.ident == "Id" {
+				return Some(parse_quote! { String });
+			}
+			let mut replaced = false;
+			if let PathArguments::AngleBracketed(args) = &mut last.arguments {
+				for arg in args.args.iter_mut() {
+					if let GenericArgument::Type(ty) = arg {
+						if let Some(schema) = schema_type(ty) {
+							*ty = schema;
+							replaced = true;
+						}
+					}
+				}
+			}
+			if replaced {
+				Some(Type::Path(TypePath { qself: None, path }))
+			} else {
+				None
+			}
+		}
+		_ => None,
+	}
+}
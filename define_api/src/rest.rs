@@ -23,44 +23,54 @@ pub fn define_rest_api(input: TokenStream) -> TokenStream {
 	let get_all = define_get_all(ty.clone(), ident.clone());
 	let post = define_post(ty.clone(), ident.clone());
 	let delete = define_delete(ty.clone(), ident.clone());
+	let search = define_search(ty.clone(), ident.clone());
 	//let update = define_update(ty.clone(), ident.clone());
 	TokenStream::from(quote! {
 		#get
 		#get_all
 		#post
 		#delete
+		#search
 		//#update
 	})
 }
 fn define_get(ty: Type, ident: Ident) -> TokenStream2 {
 	let name = format_ident!("get_{}", ident);
 	quote! {
-		fn #name(id: Id<#ty>) -> Option<Entry<#ty>> {
-			<#ty as DatabaseTable<'_, Schema>>::get(id)
+		fn #name(principal: Principal, id: Id<#ty>) -> Result<Option<Entry<#ty>>, database_table::Error> {
+			<#ty as DatabaseTable<'_, Schema>>::get_as(&principal, id)
 		}
 	}
 }
 fn define_post(ty: Type, ident: Ident) -> TokenStream2 {
 	let name = format_ident!("post_{}", ident);
 	quote! {
-		fn #name(data: #ty) -> Id<#ty> {
-			<#ty as DatabaseTable<'_, Schema>>::insert(data)
+		fn #name(principal: Principal, data: #ty) -> Result<Id<#ty>, database_table::Error> {
+			<#ty as DatabaseTable<'_, Schema>>::insert_as(&principal, data)
 		}
 	}
 }
 fn define_get_all(ty: Type, ident: Ident) -> TokenStream2 {
 	let name = format_ident!("get_{}s", ident);
 	quote! {
-		fn #name() -> Vec<Entry<#ty>> {
-			<#ty as DatabaseTable<'_, Schema>>::get_all()
+		fn #name(principal: Principal, query: ListQuery) -> Result<Page<#ty>, database_table::Error> {
+			<#ty as DatabaseTable<'_, Schema>>::list_as(&principal, query)
 		}
 	}
 }
 fn define_delete(ty: Type, ident: Ident) -> TokenStream2 {
 	let name = format_ident!("delete_{}", ident);
 	quote! {
-		fn #name(id: Id<#ty>) -> Option<#ty> {
-			<#ty as DatabaseTable<'_, Schema>>::delete(id)
+		fn #name(principal: Principal, id: Id<#ty>) -> Result<Option<#ty>, database_table::Error> {
+			<#ty as DatabaseTable<'_, Schema>>::delete_as(&principal, id)
+		}
+	}
+}
+fn define_search(ty: Type, ident: Ident) -> TokenStream2 {
+	let name = format_ident!("search_{}s", ident);
+	quote! {
+		fn #name(principal: Principal, query: String) -> Result<Vec<Entry<#ty>>, database_table::Error> {
+			<#ty as DatabaseTable<'_, Schema>>::search_as(&principal, &query)
 		}
 	}
 }
@@ -8,6 +8,7 @@ use proc_macro2::{
 use syn::{
 	punctuated::Punctuated,
 	token::*,
+	Type,
 	*,
 };
 
@@ -55,11 +56,15 @@ fn params(ident: Ident, inputs: Punctuated<FnArg, Comma>) -> TokenStream2 {
 		brace_token: Brace::default(),
 		named: inputs
 			.iter()
+			.filter(|arg| !crate::is_injected(arg))
 			.map(|arg| {
 				match arg {
 					FnArg::Typed(ty) => {
 						Field {
-							attrs: ty.attrs.clone(),
+							attrs: ty.attrs.iter()
+								.cloned()
+								.chain(crate::openapi::schema_attr(&ty.ty))
+								.collect(),
 							vis: Visibility::Inherited,
 							ident: Some(match *ty.pat.clone() {
 								Pat::Ident(pat) => pat.ident,
@@ -77,30 +82,33 @@ fn params(ident: Ident, inputs: Punctuated<FnArg, Comma>) -> TokenStream2 {
 	quote! {
 		#[allow(non_camel_case_types)]
 		#[derive(Deserialize, Serialize)]
+		#[cfg_attr(not(target_arch="wasm32"), derive(schemars::JsonSchema))]
 		pub struct #ident #fields
 	}
 }
 fn result(ident: Ident, ty: ReturnType) -> TokenStream2 {
-	let fields = match ty {
-		ReturnType::Default => Fields::Unit,
-		ReturnType::Type(_arrow, ty) => {
-			let mut fields = Punctuated::new();
-			fields.push_value(Field {
-				attrs: vec![],
-				vis: Visibility::Inherited,
-				ident: None,
-				colon_token: None,
-				ty: *ty,
-			});
-			Fields::Unnamed(FieldsUnnamed {
-				paren_token: Paren::default(),
-				unnamed: fields,
-			})
-		}
+	// streaming functions respond with a result per item
+	let ty: Type = match (crate::stream_item(&ty), ty) {
+		(Some(item), _) => item,
+		(None, ReturnType::Default) => syn::parse_quote! { () },
+		(None, ReturnType::Type(_arrow, ty)) => *ty,
 	};
+	let mut fields = Punctuated::new();
+	fields.push_value(Field {
+		attrs: crate::openapi::schema_attr(&ty).into_iter().collect(),
+		vis: Visibility::Inherited,
+		ident: None,
+		colon_token: None,
+		ty,
+	});
+	let fields = Fields::Unnamed(FieldsUnnamed {
+		paren_token: Paren::default(),
+		unnamed: fields,
+	});
 	quote! {
 		#[allow(non_camel_case_types)]
 		#[derive(Deserialize, Serialize)]
+		#[cfg_attr(not(target_arch="wasm32"), derive(schemars::JsonSchema))]
 		pub struct #ident #fields;
 	}
 }
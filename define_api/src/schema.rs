@@ -0,0 +1,65 @@
+use quote::{
+	format_ident,
+	quote,
+};
+use proc_macro2::{
+	TokenStream as TokenStream2,
+};
+use syn::{
+	*,
+};
+
+/// Version of the api, the namespace of its routes
+///
+/// `#![version(2)]` at the start of `api!` serves the functions at
+/// `/api/v2/call/..`, version 1 by default.
+pub fn version(attrs: &[Attribute]) -> u32 {
+	attrs.iter()
+		.find(|attr| attr.path.is_ident("version"))
+		.map(|attr| {
+			let version: LitInt = attr.parse_args().expect("expected #![version(1)]");
+			version.base10_parse().expect("expected an integer api version")
+		})
+		.unwrap_or(1)
+}
+/// Route of the api function ident
+pub(crate) fn route(version: u32, ident: &Ident) -> String {
+	format!("/api/v{}/call/{}", version, ident)
+}
+/// Route of the batch calls of the api
+pub(crate) fn batch_route(version: u32) -> String {
+	format!("/api/v{}/batch", version)
+}
+/// Define the version constant and the schema hash of the api
+pub fn define_schema(fns: &[ItemFn], version: u32) -> TokenStream2 {
+	let names: Vec<String> = fns.iter().map(|f| f.sig.ident.to_string()).collect();
+	let params: Vec<Ident> = fns.iter().map(|f| format_ident!("{}Parameters", f.sig.ident)).collect();
+	let results: Vec<Ident> = fns.iter().map(|f| format_ident!("{}Result", f.sig.ident)).collect();
+	quote! {
+		/// Version of the api, the namespace of its routes
+		pub const API_VERSION: u32 = #version;
+		#[cfg(not(target_arch="wasm32"))]
+		::lazy_static::lazy_static! {
+			static ref API_SCHEMA_HASH: String = app_model::api_schema::hash(#version, vec![
+				#((#names, schemars::schema_for!(#params), schemars::schema_for!(#results)),)*
+			]);
+		}
+		/// Hash of the version and of the schemas of the parameters and results
+		/// of the api functions
+		///
+		/// Clients send it with their calls, the server rejects calls of
+		/// clients built with a different api, see `app_model::api_schema`.
+		#[cfg(not(target_arch="wasm32"))]
+		pub fn api_schema_hash() -> &'static str {
+			&API_SCHEMA_HASH
+		}
+		/// Hash of the api of the server the client is built for
+		///
+		/// The schemas are only generated natively, build the client with the
+		/// hash printed by the server in `API_SCHEMA_HASH`.
+		#[cfg(target_arch="wasm32")]
+		pub fn api_schema_hash() -> &'static str {
+			env!("API_SCHEMA_HASH", "Set API_SCHEMA_HASH to the api schema hash of the server")
+		}
+	}
+}
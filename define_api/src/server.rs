@@ -5,14 +5,45 @@ use quote::{
 use proc_macro2::{
     TokenStream as TokenStream2,
 };
+use crate::{
+	access::Access,
+	Injected,
+};
 use syn::{
 	punctuated::Punctuated,
 	token::*,
 	*,
 };
 
-pub fn define_server(fns: &Vec<ItemFn>) -> TokenStream2 {
-	let handlers = define_handlers(&fns);
+/// Server stack the handlers of an api are generated for
+#[derive(Clone, Copy)]
+pub enum Backend {
+	Rocket,
+	Tide,
+}
+impl Backend {
+	/// Backend selected by `#![backend(tide)]` at the start of `api!`, Rocket by default
+	pub fn of(attrs: &[Attribute]) -> Self {
+		attrs.iter()
+			.find(|attr| attr.path.is_ident("backend"))
+			.map(|attr| {
+				let backend: Ident = attr.parse_args().expect("expected #![backend(rocket)] or #![backend(tide)]");
+				if backend == "rocket" {
+					Self::Rocket
+				} else if backend == "tide" {
+					Self::Tide
+				} else {
+					panic!("unknown api backend {}, expected rocket or tide", backend)
+				}
+			})
+			.unwrap_or(Self::Rocket)
+	}
+}
+pub fn define_server(fns: &Vec<ItemFn>, backend: Backend, version: u32) -> TokenStream2 {
+	let handlers = match backend {
+		Backend::Rocket => define_rocket_handlers(&fns, version),
+		Backend::Tide => define_tide_handlers(&fns, version),
+	};
 	let calls = define_calls(&fns);
 	quote! {
 		#handlers
@@ -35,12 +66,87 @@ fn call(item: ItemFn) -> TokenStream2 {
 	item.vis = Visibility::Public(VisPublic {
 		pub_token: Pub::default(),
 	});
+	item.attrs.retain(|attr| !crate::access::is_access_attr(attr));
 	quote! {
 		#item
 	}
 }
-fn define_handlers(fns: &Vec<ItemFn>) -> TokenStream2 {
-	let routes: Vec<TokenStream2> = fns.iter().map(|f| route(f.clone())).collect();
+/// Statements decoding the claims of the request token and checking the access of item
+///
+/// error gives the error expression of a response status.
+pub(crate) fn claims(item: &ItemFn, error: fn(u16) -> TokenStream2) -> TokenStream2 {
+	let unauthorized = error(401);
+	let forbidden = error(403);
+	let decode = quote! {
+		#[allow(unused_variables)]
+		let claims = token.as_ref().and_then(|token| token.decode().ok());
+	};
+	match Access::of(item) {
+		Access::Public => decode,
+		Access::Authenticated => quote! {
+			#decode
+			if claims.is_none() {
+				return Err(#unauthorized);
+			}
+		},
+		Access::Role(role) => quote! {
+			#decode
+			if claims.is_none() {
+				return Err(#unauthorized);
+			}
+			if !claims.as_ref().map(|claims| claims.has_role(#role)).unwrap_or(false) {
+				return Err(#forbidden);
+			}
+		},
+	}
+}
+/// Arguments of the call of item, from the request parameters and the claims
+pub(crate) fn args(item: &ItemFn, error: fn(u16) -> TokenStream2) -> Punctuated<Expr, Comma> {
+	let unauthorized = error(401);
+	item.sig.inputs
+		.iter()
+		.map(|arg| {
+			match (arg, crate::injected(arg)) {
+				(_, Some(Injected::Principal)) => {
+					syn::parse_quote! { claims.as_ref().map(Principal::from).unwrap_or(Principal::Anonymous) }
+				}
+				(_, Some(Injected::Claims)) => {
+					syn::parse_quote! { claims.clone().ok_or_else(|| #unauthorized)? }
+				}
+				(_, Some(Injected::OptionalClaims)) => {
+					syn::parse_quote! { claims.clone() }
+				}
+				(FnArg::Typed(ty), None) => {
+					let member = format!(
+						"parameters.{}",
+						match *ty.pat.clone() {
+							Pat::Ident(pat) => pat.ident,
+							_ => panic!("api function params must have idents"),
+						}
+					);
+					let expr: ExprField = syn::parse_str(&member).unwrap();
+					Expr::Field(expr)
+				}
+				_ => panic!("api functions may not take self parameter"),
+			}
+		})
+		.collect()
+}
+/// Call of the api function item from a handler
+///
+/// async functions are awaited in async handlers, the synchronous ones block
+/// on them in `app_model::api_runtime`.
+pub(crate) fn invoke(item: &ItemFn, args: Punctuated<Expr, Comma>, awaits: bool) -> TokenStream2 {
+	let ident = &item.sig.ident;
+	match (item.sig.asyncness.is_some(), awaits) {
+		(false, _) => quote! { call::#ident(#args) },
+		(true, true) => quote! { call::#ident(#args).await },
+		(true, false) => quote! { app_model::api_runtime::block_on(call::#ident(#args)) },
+	}
+}
+fn define_rocket_handlers(fns: &Vec<ItemFn>, version: u32) -> TokenStream2 {
+	let routes: Vec<TokenStream2> = fns.iter().map(|f| rocket_route(f.clone(), version)).collect();
+	let batch_route = crate::schema::batch_route(version);
 	quote! {
 		#[cfg(not(target_arch="wasm32"))]
 		pub mod handlers {
@@ -64,63 +170,252 @@ fn define_handlers(fns: &Vec<ItemFn>) -> TokenStream2 {
 					Json,
 				},
 			};
+			use app_model::encoding::{
+				Acceptable,
+				Encoded,
+			};
+			/// Request guard rejecting calls of clients built with a different api
+			pub struct ApiSchema;
+			impl<'a, 'r> rocket::request::FromRequest<'a, 'r> for ApiSchema {
+				type Error = ();
+				fn from_request(request: &'a rocket::Request<'r>) -> rocket::request::Outcome<Self, ()> {
+					let header = request.headers().get_one(app_model::api_schema::HEADER);
+					if app_model::api_schema::is_outdated(header, api_schema_hash()) {
+						rocket::Outcome::Failure((Status::PreconditionFailed, ()))
+					} else {
+						rocket::Outcome::Success(ApiSchema)
+					}
+				}
+			}
+			/// Catcher telling outdated clients to reload, register with `catchers![api::handlers::outdated]`
+			#[rocket::catch(412)]
+			pub fn outdated() -> &'static str {
+				app_model::api_schema::OUTDATED_MESSAGE
+			}
 			#(#routes)*
+			/// Calls of a batch request, responded to in order
+			#[rocket::post(#batch_route, data="<calls>")]
+			pub fn batch(_schema: ApiSchema, _accept: Acceptable, token: Option<JWT>, calls: Encoded<Vec<ApiCall>>) -> Result<Encoded<Vec<Result<ApiReturn, u16>>>, Status> {
+				let Encoded(calls) = calls;
+				if calls.len() > app_model::api_batch::MAX_CALLS {
+					return Err(Status::PayloadTooLarge);
+				}
+				Ok(Encoded(app_model::api_runtime::block_on(super::dispatch::batch(token, calls))))
+			}
+			#[rocket::get("/api/openapi.json")]
+			pub fn openapi() -> Json<serde_json::Value> {
+				Json(super::openapi())
+			}
 		}
 	}
 }
-fn route(item: ItemFn) -> TokenStream2 {
-	#[allow(unused)]
-	let ItemFn {
-		attrs, //: Vec<Attribute>
-		vis,   //: Visibility
-		sig,   //: Signature
-		block, //: Box<Block>
-	} = item.clone();
-
-	#[allow(unused)]
-	let Signature {
-		constness,   //: Option<Const>
-		asyncness,   //: Option<Async>
-		unsafety,	//: Option<Unsafe>
-		abi,		 //: Option<Abi>
-		fn_token,	//: Fn
-		ident,	   //: Ident
-		generics,	//: Generics
-		paren_token, //: Paren
-		inputs,	  //: Punctuated<FnArg, Comma>
-		variadic,	//: Option<Dot3>
-		output,	  //: ReturnType
-	} = sig;
-
+fn rocket_error(status: u16) -> TokenStream2 {
+	match status {
+		401 => quote! { Status::Unauthorized },
+		_ => quote! { Status::Forbidden },
+	}
+}
+fn rocket_route(item: ItemFn, version: u32) -> TokenStream2 {
+	let ident = item.sig.ident.clone();
 	let params_ident = format_ident!("{}Parameters", ident.clone());
 	let result_ident = format_ident!("{}Result", ident.clone());
-
-	let route = format!("/api/call/{}", ident);
-	let args: Punctuated<Expr, Comma> = inputs
-		.iter()
-		.map(|arg| {
-			match arg {
-				FnArg::Typed(ty) => {
-					let member = format!(
-						"parameters.{}",
-						match *ty.pat.clone() {
-							Pat::Ident(pat) => pat.ident,
-							_ => panic!("api function params must have idents"),
-						}
-					);
-					let expr: ExprField = syn::parse_str(&member).unwrap();
-					Expr::Field(expr)
-				}
-				_ => panic!("api functions may not take self parameter"),
+	let route = crate::schema::route(version, &ident);
+	let claims = claims(&item, rocket_error);
+	let call = invoke(&item, args(&item, rocket_error), false);
+	if crate::stream_item(&item.sig.output).is_some() {
+		quote! {
+			#[rocket::post(#route, data="<parameters>")]
+			pub fn #ident(_schema: ApiSchema, token: Option<JWT>, parameters: Encoded<#params_ident>) -> Result<rocket::response::content::Content<rocket::response::Stream<app_model::api_stream::JsonLines>>, Status> {
+				#claims
+				let Encoded(parameters) = parameters;
+				let items = futures::StreamExt::map(#call, #result_ident);
+				Ok(rocket::response::content::Content(
+					ContentType::new("application", "x-ndjson"),
+					rocket::response::Stream::from(app_model::api_stream::JsonLines::new(items)),
+				))
+			}
+		}
+	} else if crate::is_result(&item.sig.output) {
+		quote! {
+			#[rocket::post(#route, data="<parameters>")]
+			pub fn #ident(_schema: ApiSchema, _accept: Acceptable, token: Option<JWT>, parameters: Encoded<#params_ident>) -> Result<status::Custom<Encoded<#result_ident>>, Status> {
+				#claims
+				let Encoded(parameters) = parameters;
+				let result = #call;
+				let status = match &result {
+					Ok(_) => Status::Ok,
+					Err(e) => Status::from_code(database_table::ErrorStatus::status(e)).unwrap_or(Status::InternalServerError),
+				};
+				Ok(status::Custom(status, Encoded(#result_ident(result))))
+			}
+		}
+	} else {
+		quote! {
+			#[rocket::post(#route, data="<parameters>")]
+			pub fn #ident(_schema: ApiSchema, _accept: Acceptable, token: Option<JWT>, parameters: Encoded<#params_ident>) -> Result<Encoded<#result_ident>, Status> {
+				#claims
+				let Encoded(parameters) = parameters;
+				Ok(Encoded(#result_ident(#call)))
+			}
+		}
+	}
+}
+fn define_tide_handlers(fns: &Vec<ItemFn>, version: u32) -> TokenStream2 {
+	let routes: Vec<TokenStream2> = fns.iter().map(|f| tide_route(f.clone())).collect();
+	let batch_route = crate::schema::batch_route(version);
+	let check_schema = tide_check_schema();
+	let token = tide_token();
+	let negotiate = tide_negotiate(false);
+	let registrations: Vec<TokenStream2> = fns.iter()
+		.map(|f| {
+			let ident = f.sig.ident.clone();
+			let route = crate::schema::route(version, &ident);
+			quote! {
+				server.at(#route).post(#ident::<State>);
 			}
 		})
 		.collect();
 	quote! {
-		#[rocket::post(#route, data="<parameters>")]
-		pub fn #ident(token: JWT, parameters: Json<#params_ident>) -> Json<#result_ident> {
-			let _ = token;
-			let Json(parameters) = parameters;
-			Json(#result_ident(call::#ident(#args)))
+		#[cfg(not(target_arch="wasm32"))]
+		pub mod handlers {
+			use super::*;
+			use jwt::{
+				*,
+			};
+			use tide::{
+				Body,
+				Request,
+				Response,
+			};
+			#(#routes)*
+			/// Calls of a batch request, responded to in order
+			pub async fn batch<State: Clone + Send + Sync + 'static>(mut req: Request<State>) -> tide::Result {
+				#check_schema
+				#token
+				#negotiate
+				let calls: Vec<ApiCall> = encoding.decode(&req.body_bytes().await?)
+					.map_err(|e| tide::Error::from_str(400, e))?;
+				if calls.len() > app_model::api_batch::MAX_CALLS {
+					return Err(tide::Error::from_str(413, "Too many calls."));
+				}
+				let results = super::dispatch::batch(token, calls).await;
+				let body = accept.encode(&results)
+					.map_err(|e| tide::Error::from_str(500, e))?;
+				let mut response = Response::new(200);
+				response.set_body(Body::from(body));
+				response.set_content_type(accept.content_type());
+				Ok(response)
+			}
+			pub async fn openapi<State: Clone + Send + Sync + 'static>(_: Request<State>) -> tide::Result<Body> {
+				Body::from_json(&super::openapi())
+			}
+			/// Register the api endpoints at server
+			pub fn routes<State: Clone + Send + Sync + 'static>(server: &mut tide::Server<State>) {
+				#(#registrations)*
+				server.at(#batch_route).post(batch::<State>);
+				server.at("/api/openapi.json").get(openapi::<State>);
+			}
+		}
+	}
+}
+fn tide_error(status: u16) -> TokenStream2 {
+	let message = match status {
+		401 => "Unauthorized.",
+		_ => "Forbidden.",
+	};
+	quote! { tide::Error::from_str(#status, #message) }
+}
+fn tide_route(item: ItemFn) -> TokenStream2 {
+	let ident = item.sig.ident.clone();
+	let params_ident = format_ident!("{}Parameters", ident.clone());
+	let result_ident = format_ident!("{}Result", ident.clone());
+	let claims = claims(&item, tide_error);
+	let call = invoke(&item, args(&item, tide_error), true);
+	let status = if crate::is_result(&item.sig.output) {
+		quote! {
+			let status = match &result {
+				Ok(_) => 200,
+				Err(e) => database_table::ErrorStatus::status(e),
+			};
+		}
+	} else {
+		quote! {
+			let status = 200;
+		}
+	};
+	let respond = if crate::stream_item(&item.sig.output).is_some() {
+		quote! {
+			let lines = futures::StreamExt::map(#call, |item| {
+				Ok::<_, std::io::Error>(app_model::api_stream::line(&#result_ident(item)))
+			});
+			let body = futures::io::BufReader::new(futures::TryStreamExt::into_async_read(Box::pin(lines)));
+			let mut response = Response::new(200);
+			response.set_body(Body::from_reader(body, None));
+			response.set_content_type(app_model::api_stream::CONTENT_TYPE);
+		}
+	} else {
+		quote! {
+			let result = #call;
+			#status
+			let body = accept.encode(&#result_ident(result))
+				.map_err(|e| tide::Error::from_str(500, e))?;
+			let mut response = Response::new(status);
+			response.set_body(Body::from(body));
+			response.set_content_type(accept.content_type());
 		}
+	};
+	let check_schema = tide_check_schema();
+	let token = tide_token();
+	let negotiate = tide_negotiate(crate::stream_item(&item.sig.output).is_some());
+	quote! {
+		pub async fn #ident<State: Clone + Send + Sync + 'static>(mut req: Request<State>) -> tide::Result {
+			#check_schema
+			#token
+			#claims
+			#negotiate
+			let parameters: #params_ident = encoding.decode(&req.body_bytes().await?)
+				.map_err(|e| tide::Error::from_str(400, e))?;
+			#respond
+			Ok(response)
+		}
+	}
+}
+/// Statements responding to calls of clients built with a different api
+fn tide_check_schema() -> TokenStream2 {
+	quote! {
+		let schema = req.header(app_model::api_schema::HEADER).map(|values| values.last().as_str());
+		if app_model::api_schema::is_outdated(schema, api_schema_hash()) {
+			let mut response = Response::new(app_model::api_schema::OUTDATED);
+			response.set_body(app_model::api_schema::OUTDATED_MESSAGE);
+			return Ok(response);
+		}
+	}
+}
+fn tide_token() -> TokenStream2 {
+	quote! {
+		let token = req.header("authorization")
+			.map(|values| JWT::from(values.last().as_str().to_string()));
+	}
+}
+/// Statements selecting the encodings of the request and the response
+///
+/// Responds with 406 if the Accept header accepts no encoding, unless the
+/// handler streams its result, which is always sent as JSON lines.
+fn tide_negotiate(streams: bool) -> TokenStream2 {
+	let accept = if streams {
+		quote! { None }
+	} else {
+		quote! { req.header("accept").map(|values| values.last().as_str()) }
+	};
+	quote! {
+		// parameters and result are encoded as negotiated by content type, JSON by default
+		#[allow(unused_variables)]
+		let (encoding, accept) = match app_model::encoding::Encoding::negotiate(
+			req.header("content-type").map(|values| values.last().as_str()),
+			#accept,
+		) {
+			Some(encodings) => encodings,
+			None => return Err(tide::Error::from_str(406, "Not acceptable.")),
+		};
 	}
 }
@@ -0,0 +1,115 @@
+use quote::{
+	quote,
+};
+use proc_macro2::{
+	TokenStream as TokenStream2,
+};
+use syn::{
+	Type,
+	*,
+};
+
+/// Type of the messages pushed over the api websocket, if enabled
+///
+/// `#![websocket]` at the start of `api!` enables the websocket transport,
+/// `#![websocket(push = "ServerMessage")]` also lets the server push messages.
+pub fn push_type(attrs: &[Attribute]) -> Option<Type> {
+	attrs.iter()
+		.find(|attr| attr.path.is_ident("websocket"))
+		.map(|attr| {
+			if attr.tokens.is_empty() {
+				parse_quote! { () }
+			} else {
+				let push: MetaNameValue = attr.parse_args().expect("expected #![websocket(push = \"Type\")]");
+				if !push.path.is_ident("push") {
+					panic!("unknown websocket argument, expected push = \"Type\"");
+				}
+				match push.lit {
+					Lit::Str(ty) => ty.parse().expect("expected a push type"),
+					_ => panic!("expected #![websocket(push = \"Type\")]"),
+				}
+			}
+		})
+}
+/// Define the messages, server and client of the api websocket
+pub fn define_websocket(fns: &Vec<ItemFn>, push: Option<Type>) -> TokenStream2 {
+	let push = match push {
+		Some(push) => push,
+		None => return quote! {},
+	};
+	let methods: Vec<TokenStream2> = crate::dispatch::dispatched(fns)
+		.into_iter()
+		.map(crate::dispatch::client_method)
+		.collect();
+	quote! {
+		/// Request sent by clients over the api websocket
+		pub type ApiRequest = app_model::api_socket::SocketRequest<ApiCall>;
+		/// Message sent by the server over the api websocket
+		pub type ApiMessage = app_model::api_socket::SocketMessage<ApiReturn, #push>;
+
+		#[cfg(not(target_arch="wasm32"))]
+		pub mod websocket {
+			use super::*;
+			use jwt::{
+				*,
+			};
+			use futures::{
+				Sink,
+				Stream,
+				StreamExt,
+			};
+			/// Number of calls of a connection executed concurrently
+			const CONCURRENT_CALLS: usize = 16;
+			/// Response to a request received over the api websocket
+			pub async fn respond(request: ApiRequest) -> ApiMessage {
+				if app_model::api_schema::is_outdated(request.schema.as_deref(), api_schema_hash()) {
+					return ApiMessage::Response {
+						id: request.id,
+						result: Err(app_model::api_schema::OUTDATED),
+					};
+				}
+				let token = request.token.map(JWT::from);
+				ApiMessage::Response {
+					id: request.id,
+					result: super::dispatch::call(token, request.call).await,
+				}
+			}
+			/// Serve an api websocket connection
+			///
+			/// Responds to the requests of incoming concurrently, in the order
+			/// they complete, and sends the responses and pushes to outgoing,
+			/// until both streams end or sending fails.
+			pub async fn serve<I, O, P>(incoming: I, outgoing: O, pushes: P) -> Result<(), O::Error>
+				where I: Stream<Item = ApiRequest>,
+					  O: Sink<ApiMessage>,
+					  P: Stream<Item = #push>,
+			{
+				let responses = incoming.map(respond).buffer_unordered(CONCURRENT_CALLS);
+				futures::stream::select(responses, pushes.map(ApiMessage::Push))
+					.map(Ok)
+					.forward(outgoing)
+					.await
+			}
+		}
+
+		/// Client of the api functions over a websocket, see `app_model::api_socket::SocketClient`
+		#[derive(Clone)]
+		pub struct ApiSocket(app_model::api_socket::SocketClient<ApiCall, ApiReturn>);
+		impl ApiSocket {
+			/// New client and the requests to send over the websocket
+			pub fn new() -> (Self, futures::channel::mpsc::UnboundedReceiver<ApiRequest>) {
+				let (client, requests) = app_model::api_socket::SocketClient::new(api_schema_hash());
+				(Self(client), requests)
+			}
+			/// Complete the call of a received response, returns pushed messages
+			pub fn receive(&self, message: ApiMessage) -> Option<#push> {
+				self.0.receive(message)
+			}
+			/// Fail the pending calls, after the websocket closed
+			pub fn disconnect(&self) {
+				self.0.disconnect()
+			}
+			#(#methods)*
+		}
+	}
+}